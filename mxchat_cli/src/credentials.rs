@@ -0,0 +1,78 @@
+use std::{env, fs, path::Path};
+
+use crate::error::CliError;
+
+pub const DEFAULT_SERVER: &str = "127.0.0.1:8080";
+
+/// Account used by the commands. Each value comes from the environment (`MXCHAT_SERVER`,
+/// `MXCHAT_USERNAME`, `MXCHAT_PASSWORD`, `MXCHAT_NICKNAME`) or else from the credentials
+/// file, made of `key = value` lines with the same keys in lower case.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub nickname: Option<String>,
+}
+
+impl Credentials {
+    pub fn load(file: Option<&Path>) -> Result<Self, CliError> {
+        let file_content = match file {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| CliError::Credentials(format!("Could not read credentials file {}: {e}", path.display())))?,
+            None => String::new()
+        };
+
+        Self::from_sources(&file_content, |key| env::var(format!("MXCHAT_{}", key.to_uppercase())).ok())
+    }
+
+    fn from_sources(file_content: &str, env_var: impl Fn(&str) -> Option<String>) -> Result<Self, CliError> {
+        let file_value = |key: &str| file_content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .find(|(line_key, _)| line_key.trim() == key)
+            .map(|(_, value)| value.trim().to_string());
+
+        let value = |key: &str| env_var(key)
+            .filter(|value| !value.is_empty())
+            .or_else(|| file_value(key));
+
+        let required = |key: &str| value(key)
+            .ok_or_else(|| CliError::Credentials(format!("Missing {key}, set MXCHAT_{} or add it to the credentials file", key.to_uppercase())));
+
+        Ok(Self {
+            server: value("server").unwrap_or_else(|| String::from(DEFAULT_SERVER)),
+            username: required("username")?,
+            password: required("password")?,
+            nickname: value("nickname"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let file_content = "# CI account\nusername = ci\npassword=from file\n";
+        let env_var = |key: &str| (key == "password").then(|| String::from("from env"));
+
+        let credentials = Credentials::from_sources(file_content, env_var).unwrap();
+
+        assert_eq!(credentials, Credentials {
+            server: String::from(DEFAULT_SERVER),
+            username: String::from("ci"),
+            password: String::from("from env"),
+            nickname: None,
+        });
+    }
+
+    #[test]
+    fn test_missing_username_is_rejected() {
+        let result = Credentials::from_sources("password = secret", |_| None);
+        assert!(matches!(result, Err(CliError::Credentials(_))));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{fmt, io};
+
+use mxchat_core::error::ErrorCode;
+use mxchat_sdk::ClientError;
+use serde_json::json;
+
+/// Failure of a command, reported on stderr. Scripts can tell failures apart with the
+/// exit code:
+///
+/// | Exit code | Failure                                                 |
+/// |-----------|---------------------------------------------------------|
+/// | 0         | success                                                 |
+/// | 1         | missing credentials or unusable state directory        |
+/// | 2         | invalid arguments                                       |
+/// | 3         | the server could not be reached                        |
+/// | 4         | the server did not answer in time                      |
+/// | 5         | the connection was lost                                 |
+/// | 6         | unexpected reply from the server                        |
+/// | 7         | the account requires a two-factor code, see `--code`    |
+/// | 8         | stdin could not be read or stdout written               |
+/// | 10-15     | protocol errors, `UnknownCommand` to `Internal`         |
+/// | 20-27     | user errors, `UserAlreadyExists` to `InvalidAccountData` |
+/// | 30        | `UserOffline`                                           |
+/// | 99        | error code unknown to this version                      |
+///
+/// Server errors map to the tens of their error code, `PasswordIncorrect` (203) exits with 23.
+/// A closed stdout, as in `mxchat-cli listen | head -1`, ends the command quietly with 0.
+#[derive(Debug)]
+pub enum CliError {
+    Credentials(String),
+    State(io::Error),
+    Output(io::Error),
+    Client(ClientError),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Credentials(_) | CliError::State(_) => 1,
+            CliError::Output(_) => 8,
+            CliError::Client(ClientError::Io(_)) => 3,
+            CliError::Client(ClientError::Timeout) => 4,
+            CliError::Client(ClientError::Disconnected) => 5,
+            CliError::Client(ClientError::UnexpectedReply(_)) => 6,
+            CliError::Client(ClientError::SecondFactorRequired) => 7,
+            CliError::Client(ClientError::Server(error)) => match error.code {
+                ErrorCode::UnknownCommand => 10,
+                ErrorCode::InvalidPayload => 11,
+                ErrorCode::FrameTooLarge => 12,
+                ErrorCode::RateLimited => 13,
+                ErrorCode::IdleTimeout => 14,
+                ErrorCode::Internal => 15,
+                ErrorCode::UserAlreadyExists => 20,
+                ErrorCode::UserAlreadyConnected => 21,
+                ErrorCode::UserNotFound => 22,
+                ErrorCode::PasswordIncorrect => 23,
+                ErrorCode::NotAuthenticated => 24,
+                ErrorCode::RegistrationDisabled => 25,
+                ErrorCode::SecondFactorIncorrect => 26,
+                ErrorCode::InvalidAccountData => 27,
+                ErrorCode::UserOffline => 30,
+                ErrorCode::Unrecognized(_) => 99,
+            }
+        }
+    }
+
+    /// Whether the reader of stdout went away, which is how pipes end early rather than a failure.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, CliError::Output(e) if e.kind() == io::ErrorKind::BrokenPipe)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let server_error = match self {
+            CliError::Client(ClientError::Server(error)) => json!({
+                "code": error.code.code(),
+                "name": format!("{:?}", error.code),
+                "detail": error.detail,
+                "retry_after_ms": error.retry_after.map(|retry_after| retry_after.as_millis() as u64),
+            }),
+            _ => serde_json::Value::Null
+        };
+
+        json!({
+            "error": self.to_string(),
+            "exit_code": self.exit_code(),
+            "server_error": server_error,
+        })
+    }
+}
+
+impl From<ClientError> for CliError {
+    fn from(value: ClientError) -> Self {
+        CliError::Client(value)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Credentials(message) => write!(f, "{message}"),
+            CliError::State(e) => write!(f, "Could not use the state directory: {e}"),
+            CliError::Output(e) => write!(f, "Could not read stdin or write stdout: {e}"),
+            CliError::Client(e) => write!(f, "{e}"),
+        }
+    }
+}
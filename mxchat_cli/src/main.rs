@@ -0,0 +1,355 @@
+mod credentials;
+mod error;
+mod store;
+
+use std::{io::{self, Read, Write}, path::PathBuf, process::ExitCode, thread, time::Duration};
+
+use clap::{Parser, Subcommand};
+use credentials::Credentials;
+use error::CliError;
+use mxchat_core::{auth::{User, UserConnectData, UserId, UserRegisterData}, error::ErrorCode, notification::Notification};
+use mxchat_sdk::{Client, ClientError};
+use serde::Serialize;
+use serde_json::json;
+use store::{Direction, HistoryEntry, Store, StoredContact};
+
+/// Non-interactive mxchat client for scripts and CI. Every command connects and logs in
+/// with the credentials, then disconnects once done. Connections and logins refused by
+/// the rate limits of the server are retried once the server allows it.
+#[derive(Parser)]
+#[command(name = "mxchat-cli")]
+struct Cli {
+    /// File with `server`, `username`, `password` and `nickname` lines, overridden by the environment
+    #[arg(long, global = true, env = "MXCHAT_CREDENTIALS")]
+    credentials: Option<PathBuf>,
+
+    /// Directory where contacts and history are kept
+    #[arg(long, global = true, env = "MXCHAT_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Code of the authenticator app, or a recovery code, for accounts enrolled in two-factor authentication
+    #[arg(long, global = true, env = "MXCHAT_SECOND_FACTOR_CODE")]
+    code: Option<String>,
+
+    /// Prints one JSON object per line, errors included
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Registers the account of the credentials
+    Register,
+    /// Checks the credentials and prints the user
+    Login,
+    /// Sends a message, read from stdin when no text is given
+    Send {
+        /// Username of the recipient
+        #[arg(long)]
+        to: String,
+        text: Option<String>,
+    },
+    /// Contacts kept by the CLI
+    Contacts {
+        #[command(subcommand)]
+        command: ContactsCommand,
+    },
+    /// Prints the messages exchanged with a contact
+    History {
+        /// Username of the contact
+        #[arg(long = "with")]
+        with: String,
+    },
+    /// Prints incoming messages as they arrive
+    Listen {
+        /// Exits after this many messages
+        #[arg(long)]
+        count: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContactsCommand {
+    List,
+    /// Looks the user up on the server and adds them to the contacts
+    Add {
+        username: String,
+    },
+    /// Searches the server for users by the start of their username or nickname
+    Search {
+        query: String,
+        /// Most results printed
+        #[arg(long, default_value_t = 10)]
+        limit: u16,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.is_broken_pipe() => ExitCode::SUCCESS,
+        Err(e) => {
+            if cli.json {
+                eprintln!("{}", e.to_json());
+            }
+            else {
+                eprintln!("error: {e}");
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), CliError> {
+    let credentials = Credentials::load(cli.credentials.as_deref())?;
+    let output = Output { json: cli.json };
+
+    match &cli.command {
+        CliCommand::Register => register(&credentials, &output),
+        CliCommand::Login => {
+            let (_, user) = login(cli, &credentials)?;
+            output.print(&user_json(&user), &format!("Logged in as {} ({})", user.username, user.nickname))
+        }
+        CliCommand::Send { to, text } => {
+            let text = match text {
+                Some(text) => text.clone(),
+                None => read_stdin()?
+            };
+            send(cli, &credentials, &output, to, &text)
+        }
+        CliCommand::Contacts { command: ContactsCommand::List } => {
+            let store = Store::open(cli.state_dir.clone(), &credentials.username).map_err(CliError::State)?;
+            for contact in store.contacts().map_err(CliError::State)? {
+                output.print(&contact, &format!("{}\t{}", contact.username, contact.nickname))?;
+            }
+            Ok(())
+        }
+        CliCommand::Contacts { command: ContactsCommand::Add { username } } => {
+            let (client, _) = login(cli, &credentials)?;
+            let store = Store::open(cli.state_dir.clone(), &credentials.username).map_err(CliError::State)?;
+            let contact = add_contact(&client, &store, username)?;
+            output.print(&contact, &format!("Added {} ({})", contact.username, contact.nickname))
+        }
+        CliCommand::Contacts { command: ContactsCommand::Search { query, limit } } => {
+            let (client, _) = login(cli, &credentials)?;
+            for result in client.search_users(query.as_str(), *limit)? {
+                let value = json!({
+                    "id": result.contact.id.get(),
+                    "username": result.username,
+                    "nickname": result.contact.nickname,
+                    "bot": result.contact.bot,
+                });
+                output.print(&value, &format!("{}\t{}", result.username, result.contact.nickname))?;
+            }
+            Ok(())
+        }
+        CliCommand::History { with } => {
+            let store = Store::open(cli.state_dir.clone(), &credentials.username).map_err(CliError::State)?;
+            for entry in store.history(with).map_err(CliError::State)? {
+                output.print_history(&entry)?;
+            }
+            Ok(())
+        }
+        CliCommand::Listen { count } => listen(cli, &credentials, &output, *count),
+    }
+}
+
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn print(&self, value: &impl Serialize, text: &str) -> Result<(), CliError> {
+        let mut stdout = io::stdout().lock();
+
+        if self.json {
+            let line = serde_json::to_string(value).map_err(io::Error::other).map_err(CliError::Output)?;
+            writeln!(stdout, "{line}").map_err(CliError::Output)?;
+        }
+        else {
+            writeln!(stdout, "{text}").map_err(CliError::Output)?;
+        }
+
+        // Output is flushed for each line, so `listen` can be piped
+        stdout.flush().map_err(CliError::Output)
+    }
+
+    fn print_history(&self, entry: &HistoryEntry) -> Result<(), CliError> {
+        let arrow = match entry.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+
+        self.print(entry, &format!("{} {arrow} {}: {}", entry.timestamp, entry.with, entry.content))
+    }
+}
+
+fn user_json(user: &User) -> serde_json::Value {
+    json!({
+        "id": user.id.get(),
+        "username": user.username,
+        "nickname": user.nickname,
+        "bot": user.bot,
+    })
+}
+
+fn read_stdin() -> Result<String, CliError> {
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).map_err(CliError::Output)?;
+
+    Ok(text.trim_end().to_string())
+}
+
+/// Retries of an operation refused by the rate limits, as scripts run commands in a row.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+
+/// Longer waits asked by the server fail right away.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+
+fn retry_rate_limited<T>(mut operation: impl FnMut() -> Result<T, ClientError>) -> Result<T, ClientError> {
+    let mut retries = 0;
+
+    loop {
+        match operation() {
+            Err(ClientError::Server(error)) if error.code == ErrorCode::RateLimited && retries < MAX_RATE_LIMIT_RETRIES => {
+                match error.retry_after {
+                    Some(retry_after) if retry_after <= MAX_RATE_LIMIT_WAIT => thread::sleep(retry_after),
+                    _ => return Err(ClientError::Server(error))
+                }
+                retries += 1;
+            }
+            result => return result
+        }
+    }
+}
+
+fn register(credentials: &Credentials, output: &Output) -> Result<(), CliError> {
+    let client = retry_rate_limited(|| Client::connect(&credentials.server))?;
+
+    retry_rate_limited(|| client.register(UserRegisterData {
+        username: credentials.username.clone(),
+        nickname: credentials.nickname.clone().unwrap_or_else(|| credentials.username.clone()),
+        password: credentials.password.clone(),
+        bot: false,
+    }))?;
+
+    output.print(&json!({ "registered": credentials.username }), &format!("Registered {}", credentials.username))
+}
+
+fn login(cli: &Cli, credentials: &Credentials) -> Result<(Client, User), CliError> {
+    let client = retry_rate_limited(|| Client::connect(&credentials.server))?;
+
+    let result = retry_rate_limited(|| client.login(UserConnectData {
+        username: credentials.username.clone(),
+        password: credentials.password.clone(),
+    }));
+
+    let user = match (result, &cli.code) {
+        (Err(ClientError::SecondFactorRequired), Some(code)) => client.second_factor(code.as_str())?,
+        (result, _) => result?
+    };
+
+    Ok((client, user))
+}
+
+fn add_contact(client: &Client, store: &Store, username: &str) -> Result<StoredContact, CliError> {
+    let contact = client.add_contact(username)?;
+
+    let contact = StoredContact {
+        id: contact.id.get(),
+        username: username.into(),
+        nickname: contact.nickname,
+        bot: contact.bot,
+    };
+    store.save_contact(contact.clone()).map_err(CliError::State)?;
+
+    Ok(contact)
+}
+
+fn send(cli: &Cli, credentials: &Credentials, output: &Output, to: &str, text: &str) -> Result<(), CliError> {
+    let (client, _) = login(cli, credentials)?;
+    let store = Store::open(cli.state_dir.clone(), &credentials.username).map_err(CliError::State)?;
+
+    // Ids are looked up again, they do not survive a restart of the server
+    let contact = add_contact(&client, &store, to)?;
+    client.send_message(UserId::new(contact.id), text)?;
+
+    let entry = HistoryEntry::now(Direction::Sent, to, text);
+    store.append_history(&entry).map_err(CliError::State)?;
+
+    output.print_history(&entry)
+}
+
+fn listen(cli: &Cli, credentials: &Credentials, output: &Output, count: Option<usize>) -> Result<(), CliError> {
+    let (client, _) = login(cli, credentials)?;
+    let store = Store::open(cli.state_dir.clone(), &credentials.username).map_err(CliError::State)?;
+
+    let mut received = 0;
+    while count.is_none_or(|count| received < count) {
+        let event = client.events()
+            .recv()
+            .map_err(|_| ClientError::Disconnected)?;
+
+        let Notification::MessageReceived(message) = event.notification else {
+            continue;
+        };
+
+        store.save_contact(StoredContact {
+            id: message.sender.id.get(),
+            username: message.sender.username.clone(),
+            nickname: message.sender.nickname.clone(),
+            bot: message.sender.bot,
+        }).map_err(CliError::State)?;
+
+        let entry = HistoryEntry::now(Direction::Received, &message.sender.username, &message.content);
+        store.append_history(&entry).map_err(CliError::State)?;
+        output.print_history(&entry)?;
+
+        received += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mxchat_core::error::ErrorInfo;
+
+    use super::*;
+
+    fn rate_limited(retry_after: Duration) -> ClientError {
+        ClientError::Server(ErrorInfo::from(ErrorCode::RateLimited).with_retry_after(retry_after))
+    }
+
+    #[test]
+    fn test_rate_limited_operations_are_retried() {
+        let mut attempts = 0;
+        let result = retry_rate_limited(|| {
+            attempts += 1;
+            if attempts < 3 { Err(rate_limited(Duration::from_millis(10))) } else { Ok(attempts) }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Waits longer than the cap, and more refusals than the retries, are reported
+        let mut attempts = 0;
+        let result = retry_rate_limited(|| -> Result<(), ClientError> {
+            attempts += 1;
+            Err(rate_limited(Duration::from_secs(60)))
+        });
+        assert!(matches!(result, Err(ClientError::Server(error)) if error.code == ErrorCode::RateLimited));
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result = retry_rate_limited(|| -> Result<(), ClientError> {
+            attempts += 1;
+            Err(rate_limited(Duration::from_millis(1)))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, MAX_RATE_LIMIT_RETRIES + 1);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{env, fs::{self, OpenOptions}, io::{self, BufRead, BufReader, Write}, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+/// The server keeps neither contacts nor messages, so the CLI keeps them in a directory
+/// per user: `contacts.json` and `history.jsonl`, one message per line.
+pub struct Store {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredContact {
+    pub id: u32,
+    pub username: String,
+    pub nickname: String,
+    #[serde(default)]
+    pub bot: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// Username of the other side of the conversation.
+    pub with: String,
+    pub content: String,
+}
+
+impl HistoryEntry {
+    pub fn now(direction: Direction, with: &str, content: &str) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            timestamp,
+            direction,
+            with: with.into(),
+            content: content.into(),
+        }
+    }
+}
+
+impl Store {
+    /// Uses `state_dir`, or else `$XDG_DATA_HOME/mxchat` or `~/.local/share/mxchat`.
+    pub fn open(state_dir: Option<PathBuf>, username: &str) -> io::Result<Self> {
+        let base_dir = state_dir
+            .or_else(|| env::var_os("XDG_DATA_HOME").map(|dir| PathBuf::from(dir).join("mxchat")))
+            .or_else(|| env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".local/share/mxchat")))
+            .unwrap_or_else(|| PathBuf::from(".mxchat"));
+
+        let dir = base_dir.join(username);
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self { dir })
+    }
+
+    fn contacts_path(&self) -> PathBuf {
+        self.dir.join("contacts.json")
+    }
+
+    fn history_path(&self) -> PathBuf {
+        self.dir.join("history.jsonl")
+    }
+
+    pub fn contacts(&self) -> io::Result<Vec<StoredContact>> {
+        match fs::read(self.contacts_path()) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Adds the contact, or updates it if it is already known.
+    pub fn save_contact(&self, contact: StoredContact) -> io::Result<()> {
+        let mut contacts = self.contacts()?;
+
+        match contacts.iter_mut().find(|known_contact| known_contact.id == contact.id) {
+            Some(known_contact) => *known_contact = contact,
+            None => contacts.push(contact)
+        }
+
+        let bytes = serde_json::to_vec_pretty(&contacts).map_err(io::Error::other)?;
+        fs::write(self.contacts_path(), bytes)
+    }
+
+    pub fn append_history(&self, entry: &HistoryEntry) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.history_path())?;
+
+        let line = serde_json::to_string(entry).map_err(io::Error::other)?;
+        writeln!(file, "{line}")
+    }
+
+    pub fn history(&self, with: &str) -> io::Result<Vec<HistoryEntry>> {
+        let file = match fs::File::open(self.history_path()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+        };
+
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let entry: HistoryEntry = serde_json::from_str(&line?).map_err(io::Error::other)?;
+            if entry.with == with {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+}
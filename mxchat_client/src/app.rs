@@ -1,27 +1,36 @@
-use crate::{auth_page::AuthentificationPage, chat_page::ChatPage};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::{auth_page::AuthentificationPage, chat_page::ChatPage, networking::Session};
 
 pub struct ChatApp {
     auth_page: AuthentificationPage,
     chat_page: Option<ChatPage>,
+    sessions: Receiver<Session>,
 }
 
 impl ChatApp {
     pub fn new() -> Self {
+        let (sessions_sender, sessions) = mpsc::channel();
+
         Self {
-            auth_page: AuthentificationPage::new(),
-            chat_page: None
+            auth_page: AuthentificationPage::new(sessions_sender),
+            chat_page: None,
+            sessions,
         }
     }
 }
 
 impl eframe::App for ChatApp {
     fn update(&mut self, ctx: &eframe::egui::Context, _: &mut eframe::Frame) {
+        if let Ok(session) = self.sessions.try_recv() {
+            self.chat_page = Some(ChatPage::new(ctx, session));
+        }
+
         let logout = if let Some(chat_page) = &mut self.chat_page {
             chat_page.show(ctx)
         }
         else {
-            self.chat_page = self.auth_page.show(ctx)
-            .map(|(socket, user)| ChatPage::new(socket, user));
+            self.auth_page.show(ctx);
             false
         };
 
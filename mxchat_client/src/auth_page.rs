@@ -1,9 +1,10 @@
-use std::net::TcpStream;
+use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender, TryRecvError}, Arc}, thread};
 
 use eframe::egui::{self, CursorIcon};
-use mxchat_core::{auth::{User, UserConnectData, UserRegisterData}, notification::Notification};
+use mxchat_core::{auth::{UserConnectData, UserRegisterData}, error::ErrorCode, validation::{ValidationError, ValidationPolicy}};
+use mxchat_sdk::{session::SessionCredentials, Client, ClientError};
 
-use crate::{gui_utils::number_text_edit, networking::{read_notification, read_notification_payload, send_connect_cmd, send_register_cmd}};
+use crate::{chat_page::JobStatus, gui_utils::number_text_edit, networking::{connect_client, Session}};
 
 pub struct AuthentificationPage {
     registration_page: RegistrationPage,
@@ -12,34 +13,31 @@ pub struct AuthentificationPage {
 }
 
 impl AuthentificationPage {
-    pub fn new() -> Self {
+    pub fn new(sessions: Sender<Session>) -> Self {
         Self {
-            registration_page: RegistrationPage::new(),
-            login_page: LoginPage::new(),
+            registration_page: RegistrationPage::new(sessions.clone()),
+            login_page: LoginPage::new(sessions),
             registering: false
         }
     }
 
-    pub fn show(&mut self, ctx: &egui::Context) -> Option<(TcpStream, User)> {
+    pub fn show(&mut self, ctx: &egui::Context) {
 
         egui::CentralPanel::default()
         .show(ctx, |ui| {
             ui.heading("Chat App");
             ui.separator();
 
-            let result = if self.registering {
-                self.registration_page.show(ui)
+            if self.registering {
+                self.registration_page.show(ui);
             }
             else {
-                self.login_page.show(ui)
-            };
+                self.login_page.show(ui);
+            }
 
             ui.add_space(30.0);
             self.switch_page_widgets(ui);
-
-            result
-
-        }).inner
+        });
     }
 
     fn switch_page_widgets(&mut self, ui: &mut egui::Ui) {
@@ -60,36 +58,166 @@ impl AuthentificationPage {
     }
 }
 
+/// Login of an account enrolled in two-factor authentication, the connection is kept
+/// open until the code is sent.
+struct SecondFactorPrompt {
+    client: Client,
+    credentials: SessionCredentials,
+    code: String,
+    /// Why the previous code was refused.
+    error_message: Option<String>,
+}
+
+enum AuthJobError {
+    Failed(String),
+    SecondFactorRequired(Box<SecondFactorPrompt>),
+}
+
+impl From<ClientError> for AuthJobError {
+    fn from(value: ClientError) -> Self {
+        AuthJobError::Failed(value.to_string())
+    }
+}
+
+/// Connection and authentication running in the background. Errors come back through
+/// `errors`, while the session is sent straight to the application.
+struct AuthJob {
+    cancelled: Arc<AtomicBool>,
+    errors: Receiver<AuthJobError>,
+}
+
+impl AuthJob {
+    fn spawn(
+        ctx: egui::Context,
+        sessions: Sender<Session>,
+        job: impl FnOnce() -> Result<Session, AuthJobError> + Send + 'static
+    ) -> Self {
+        let (errors_sender, errors) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let job_cancelled = Arc::clone(&cancelled);
+
+        thread::spawn(move || {
+            let result = job();
+            if job_cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+
+            match result {
+                Ok(session) => { let _ = sessions.send(session); }
+                Err(error) => { let _ = errors_sender.send(error); }
+            }
+
+            ctx.request_repaint();
+        });
+
+        Self {
+            cancelled,
+            errors
+        }
+    }
+
+    fn cancel(self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `None` while the job is still running.
+    fn poll(&self) -> Option<Result<(), AuthJobError>> {
+        match self.errors.try_recv() {
+            Ok(error) => Some(Err(error)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Ok(()))
+        }
+    }
+}
+
+/// Shows the progress of the authentication job, returns `true` when it has succeeded.
+/// Logins waiting for a two-factor code are handed over through `second_factor`.
+fn show_job_status(
+    ui: &mut egui::Ui,
+    job: &mut Option<AuthJob>,
+    job_status: &mut JobStatus,
+    second_factor: &mut Option<SecondFactorPrompt>
+) -> bool {
+    let mut succeeded = false;
+
+    if let Some(result) = job.as_ref().and_then(AuthJob::poll) {
+        *job = None;
+        match result {
+            Ok(()) => {
+                *job_status = JobStatus::Idle;
+                succeeded = true;
+            }
+            Err(AuthJobError::Failed(error_message)) => *job_status = JobStatus::Failed(error_message),
+            Err(AuthJobError::SecondFactorRequired(prompt)) => {
+                *job_status = JobStatus::Idle;
+                *second_factor = Some(*prompt);
+            }
+        }
+    }
+
+    match job_status {
+        JobStatus::Idle => (),
+        JobStatus::InProgress => {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Connecting to server...");
+                if ui.button("Cancel").clicked() {
+                    if let Some(job) = job.take() {
+                        job.cancel();
+                    }
+                    *job_status = JobStatus::Idle;
+                }
+            });
+        }
+        JobStatus::Failed(error_message) => {
+            ui.separator();
+            ui.label(error_message.as_str());
+        }
+    }
+
+    succeeded
+}
+
 struct RegistrationPage {
     host_name: String,
     port: String,
     registration_data: UserRegisterData,
-    error_message: Option<String>
+    /// The default policy of the servers, checked before submitting.
+    validation: ValidationPolicy,
+    job_status: JobStatus,
+    job: Option<AuthJob>,
+    sessions: Sender<Session>,
 }
 
 impl RegistrationPage {
-    fn new() -> Self {
+    fn new(sessions: Sender<Session>) -> Self {
 
         let registration_data = UserRegisterData {
             username: String::new(),
             nickname: String::new(),
             password: String::new(),
+            bot: false,
         };
-        
+
         Self {
             host_name: String::from("127.0.0.1"),
             port: String::from("8080"),
             registration_data,
-            error_message: None,
+            validation: ValidationPolicy::default(),
+            job_status: JobStatus::Idle,
+            job: None,
+            sessions,
         }
     }
 
-    fn show(&mut self, ui: &mut egui::Ui) -> Option<(TcpStream, User)> {
-        let result = egui::Grid::new("registration_form_grid")
+    fn show(&mut self, ui: &mut egui::Ui) {
+        let in_progress = self.job_status == JobStatus::InProgress;
+
+        ui.add_enabled_ui(!in_progress, |ui| egui::Grid::new("registration_form_grid")
         .max_col_width(500.0)
         .spacing((10.0, 20.0))
         .show(ui, |ui| {
-
             ui.label("Hostname");
             ui.text_edit_singleline(&mut self.host_name);
 
@@ -110,82 +238,72 @@ impl RegistrationPage {
             ui.text_edit_singleline(&mut self.registration_data.password);
             ui.end_row();
 
-
-
             let response = ui.add_enabled(
                 self.is_form_valid(),
                 egui::Button::new("Register")
             );
 
             if response.clicked() {
-                self.register_and_connect()
+                self.register_and_connect(ui.ctx().clone());
             }
-            else {
-                None
-            }
-        }).inner;
+        }));
 
-        if result.is_some() {
-            self.clear();
-            return result;
+        if let Some(error) = self.validation_error() {
+            ui.colored_label(ui.visuals().error_fg_color, error.to_string());
         }
 
-        if let Some(error_message) = &self.error_message {
-            ui.separator();
-            ui.label(error_message);
+        // New accounts are not enrolled in two-factor authentication
+        if show_job_status(ui, &mut self.job, &mut self.job_status, &mut None) {
+            self.clear();
         }
-
-        None
-
     }
 
     fn is_form_valid(&self) -> bool {
         !self.host_name.is_empty() &&
         !self.port.is_empty() &&
-        !self.registration_data.username.is_empty() &&
-        !self.registration_data.nickname.is_empty() &&
-        !self.registration_data.password.is_empty()
+        self.validation.validate_register_data(&self.registration_data).is_ok()
     }
 
-    fn create_tcp_connection(&self) -> Result<TcpStream, String> {
-        let address = format!("{}:{}", self.host_name, self.port);
-        let socket = TcpStream::connect(address)
-            .map_err(|_| String::from("Could not connect to server"))?;
+    /// The reason the server would refuse the account, once the user started filling the form.
+    fn validation_error(&self) -> Option<ValidationError> {
+        let data = &self.registration_data;
+        if data.username.is_empty() && data.nickname.is_empty() && data.password.is_empty() {
+            return None;
+        }
 
-        Ok(socket)
+        self.validation.validate_register_data(data).err()
     }
 
-    fn register_user(&self, socket: &mut TcpStream) -> Result<(), String> {
-
-        send_register_cmd(socket, self.registration_data.clone())
-            .map_err(|_| String::from("Could not register user"))?;
+    fn address(&self) -> String {
+        format!("{}:{}", self.host_name, self.port)
+    }
 
-        let notification = read_notification(socket)
-                .map_err(|_| String::from("Error while connecting to server"))?;
+    fn register_and_connect(&mut self, ctx: egui::Context) {
+        let address = self.address();
+        // Registers the normalized username, the one the server stores
+        let Ok(registration_data) = self.validation.validate_register_data(&self.registration_data) else {
+            return;
+        };
 
-        match notification {
-            Notification::UserRegistred => Ok(()),
-            Notification::UserAlreadyExist => Err(String::from("User already registered")),
-            _ => Err(String::from("Error while connecting to server"))
-        }
-    }
+        self.job_status = JobStatus::InProgress;
+        self.job = Some(AuthJob::spawn(ctx.clone(), self.sessions.clone(), move || {
+            let client = connect_client(&ctx, &address)?;
 
-    fn register_and_connect(&mut self) -> Option<(TcpStream, User)> {
+            client.register(registration_data.clone())?;
 
-        self.create_tcp_connection()
-            .and_then(|mut socket| {
-                self.register_user(&mut socket)?;
+            let connect_data = UserConnectData {
+                username: registration_data.username,
+                password: registration_data.password,
+            };
 
-                let connect_data = UserConnectData {
-                    username: self.registration_data.username.clone(),
-                    password: self.registration_data.password.clone(),
-                };
+            let user = client.login(connect_data.clone())?;
+            let credentials = SessionCredentials {
+                address,
+                connect_data
+            };
 
-                let user = connect_user(&mut socket, connect_data)?;
-                Ok((socket, user))
-            })
-            .map_err(|error_message| self.error_message = Some(error_message))
-            .ok()
+            Ok(Session { client, user, credentials })
+        }));
     }
 
     fn clear(&mut self) {
@@ -200,12 +318,15 @@ struct LoginPage {
     host_name: String,
     port: String,
     connect_data: UserConnectData,
+    second_factor: Option<SecondFactorPrompt>,
 
-    error_message: Option<String>
+    job_status: JobStatus,
+    job: Option<AuthJob>,
+    sessions: Sender<Session>,
 }
 
 impl LoginPage {
-    fn new() -> Self {
+    fn new(sessions: Sender<Session>) -> Self {
 
         let connect_data = UserConnectData {
             password: String::new(),
@@ -216,13 +337,31 @@ impl LoginPage {
             host_name: String::from("127.0.0.1"),
             port: String::from("8080"),
             connect_data,
+            second_factor: None,
 
-            error_message: None
+            job_status: JobStatus::Idle,
+            job: None,
+            sessions,
         }
     }
 
-    fn show(&mut self, ui: &mut egui::Ui) -> Option<(TcpStream, User)> {
-        let result = egui::Grid::new("registration_form_grid")
+    fn show(&mut self, ui: &mut egui::Ui) {
+        if self.second_factor.is_some() {
+            self.show_second_factor_form(ui);
+        }
+        else {
+            self.show_login_form(ui);
+        }
+
+        if show_job_status(ui, &mut self.job, &mut self.job_status, &mut self.second_factor) {
+            self.clear();
+        }
+    }
+
+    fn show_login_form(&mut self, ui: &mut egui::Ui) {
+        let in_progress = self.job_status == JobStatus::InProgress;
+
+        ui.add_enabled_ui(!in_progress, |ui| egui::Grid::new("registration_form_grid")
         .max_col_width(500.0)
         .spacing((10.0, 20.0))
         .show(ui, |ui| {
@@ -244,29 +383,53 @@ impl LoginPage {
 
             let response = ui
             .add_enabled(
-                self.is_form_valid(), 
+                self.is_form_valid(),
                 egui::Button::new("Login")
             );
 
             if response.clicked() {
-                self.connect_user()
-            }
-            else {
-                None
+                self.connect_user(ui.ctx().clone());
             }
-        }).inner;
+        }));
+    }
 
-        if result.is_some() {
-            self.clear();
-            return result;
-        }
+    fn show_second_factor_form(&mut self, ui: &mut egui::Ui) {
+        let in_progress = self.job_status == JobStatus::InProgress;
+        let Some(prompt) = &mut self.second_factor else {
+            return;
+        };
 
-        if let Some(error_message) = &self.error_message {
-            ui.separator();
-            ui.label(error_message);
+        ui.label("This account is protected by two-factor authentication.");
+        ui.label("Enter the code of your authenticator app, or one of your recovery codes.");
+        ui.add_space(10.0);
+
+        let mut verify = false;
+        let mut cancel = false;
+
+        ui.add_enabled_ui(!in_progress, |ui| egui::Grid::new("second_factor_form_grid")
+        .spacing((10.0, 20.0))
+        .show(ui, |ui| {
+            ui.label("Code");
+            let response = ui.text_edit_singleline(&mut prompt.code);
+            let submitted = response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            ui.end_row();
+
+            let can_verify = !prompt.code.trim().is_empty();
+            verify = ui.add_enabled(can_verify, egui::Button::new("Verify")).clicked() || (submitted && can_verify);
+            cancel = ui.button("Cancel").clicked();
+        }));
+
+        if let Some(error_message) = &prompt.error_message {
+            ui.colored_label(ui.visuals().error_fg_color, error_message);
         }
 
-        None
+        if verify {
+            self.verify_second_factor(ui.ctx().clone());
+        }
+        else if cancel {
+            // Dropping the client closes the connection waiting for the code
+            self.second_factor = None;
+        }
     }
 
     fn is_form_valid(&self) -> bool {
@@ -276,52 +439,60 @@ impl LoginPage {
         !self.connect_data.password.is_empty()
     }
 
-    fn create_tcp_connection(&self) -> Result<TcpStream, String> {
-        let address = format!("{}:{}", self.host_name, self.port);
-        let socket = TcpStream::connect(address)
-            .map_err(|_| String::from("Could not connect to server"))?;
+    fn address(&self) -> String {
+        format!("{}:{}", self.host_name, self.port)
+    }
+
+    fn connect_user(&mut self, ctx: egui::Context) {
+        let address = self.address();
+        let connect_data = self.connect_data.clone();
+
+        self.job_status = JobStatus::InProgress;
+        self.job = Some(AuthJob::spawn(ctx.clone(), self.sessions.clone(), move || {
+            let client = connect_client(&ctx, &address)?;
+
+            let credentials = SessionCredentials {
+                address,
+                connect_data
+            };
 
-        Ok(socket)
+            match client.login(credentials.connect_data.clone()) {
+                Ok(user) => Ok(Session { client, user, credentials }),
+                Err(ClientError::SecondFactorRequired) => Err(AuthJobError::SecondFactorRequired(Box::new(SecondFactorPrompt {
+                    client,
+                    credentials,
+                    code: String::new(),
+                    error_message: None,
+                }))),
+                Err(e) => Err(e.into())
+            }
+        }));
     }
 
-    fn connect_user(&mut self) -> Option<(TcpStream, User)> {
+    fn verify_second_factor(&mut self, ctx: egui::Context) {
+        let Some(mut prompt) = self.second_factor.take() else {
+            return;
+        };
 
-        self.create_tcp_connection()
-            .and_then(|mut socket| {
-                let user = connect_user(&mut socket, self.connect_data.clone())?;
-                Ok((socket, user))
-            })
-            .map_err(|error_message| self.error_message = Some(error_message))
-            .ok()
+        self.job_status = JobStatus::InProgress;
+        self.job = Some(AuthJob::spawn(ctx, self.sessions.clone(), move || {
+            let code = std::mem::take(&mut prompt.code);
+
+            match prompt.client.second_factor(code.trim()) {
+                Ok(user) => Ok(Session { client: prompt.client, user, credentials: prompt.credentials }),
+                // Another code can be tried on the same connection
+                Err(ClientError::Server(error)) if error.code == ErrorCode::SecondFactorIncorrect => {
+                    prompt.error_message = Some(error.message);
+                    Err(AuthJobError::SecondFactorRequired(Box::new(prompt)))
+                }
+                Err(e) => Err(e.into())
+            }
+        }));
     }
 
     fn clear(&mut self) {
         self.connect_data.username.clear();
         self.connect_data.password.clear();
+        self.second_factor = None;
     }
 }
-
-fn connect_user(socket: &mut TcpStream, connect_data: UserConnectData) -> Result<User, String> {
-
-    send_connect_cmd(socket, connect_data)
-            .map_err(|_| String::from("Could not connect user"))?;
-
-    let notification = read_notification(socket)
-            .map_err(|_| String::from("Error while connecting to server"))?;
-
-    match notification {
-        Notification::UserConnected => 
-                read_notification_payload(socket)
-                .ok()
-                .and_then(|mut bytes_buffer| User::from_bytes(&mut bytes_buffer))
-                .ok_or(String::from("Cannot read user data from server"))
-                .inspect(|user| println!("User = {user:?}")),
-        Notification::UserIsAlreadyConnected => 
-            Err(String::from("User is already connected")),
-        Notification::UserNotFound =>
-            Err(String::from("User is not registered")),
-        Notification::UserPasswordIncorrect =>
-            Err(String::from("Password is incorrect")),
-        _ => Err(String::from("Error while connecting to server"))
-    }
-}
\ No newline at end of file
@@ -1,12 +1,21 @@
+mod account_panel;
 mod contacts_panel;
+mod second_factor_panel;
 
-use std::{net::TcpStream, sync::Arc, thread};
+use std::time::Duration;
 
+use account_panel::{AccountPanel, AccountPanelEvent};
 use contacts_panel::{ContactPanelEvent, ContactsPanel};
 use eframe::egui;
-use mxchat_core::{auth::User, io::BytesBuffer};
+use mxchat_core::{auth::{User, UserId}, command::Command, messaging::{Contact, IncomingMessage, OutgoingMessage, UserSearch}, notification::Notification};
 
-use crate::{messenger::{MessagingInstance, Messenger}, networking::{read_notification, read_notification_payload, send_request_contact_cmd}, notifications_handler::{ChatNotificationHandler, NotificationHandlerSignal, NotificationsQueue}};
+use mxchat_sdk::{pending_requests::PendingRequests, session::{ConnectionManager, ConnectionState}};
+use second_factor_panel::{SecondFactorPanel, SecondFactorPanelEvent};
+
+use crate::{messenger::{ChatMessage, MessageStatus, MessagingInstance, Messenger}, networking::Session, notifications_handler::{ChatNotificationHandler, NotificationHandlerSignal}, pending_requests::PendingRequest};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SUGGESTIONS_LIMIT: u16 = 8;
 
 #[derive(Eq, PartialEq)]
 pub enum JobStatus {
@@ -22,51 +31,147 @@ pub enum ShowMainContentSignal {
 }
 
 pub struct ChatPage {
-    socket: TcpStream,
+    connection: ConnectionManager,
     current_user: User,
     contacts_panel: ContactsPanel,
+    account_panel: AccountPanel,
+    second_factor_panel: SecondFactorPanel,
+    /// Code typed while the reconnection waits for the second factor.
+    reconnect_code: String,
     exit: bool,
+    /// Set by the messages the user should be alerted about, until the window asked for attention.
+    attention_requested: bool,
 
     messenger: Messenger,
 
-    notifications_queue: Arc<NotificationsQueue>,
+    pending_requests: PendingRequests<PendingRequest>,
 }
 
 impl ChatPage {
-    pub fn new(socket: TcpStream, current_user: User) -> Self {
-        let notifications_queue = Arc::new(NotificationsQueue::new());
-
-        run_notification_listener_par(
-            Arc::clone(&notifications_queue),
-            socket.try_clone().unwrap()
-        );
+    pub fn new(ctx: &egui::Context, session: Session) -> Self {
+        let current_user = session.user.clone();
+        let ctx = ctx.clone();
+        let connection = ConnectionManager::new(session.client, session.credentials, move || ctx.request_repaint());
 
         let contacts_panel = ContactsPanel::new(&current_user.username);
+        let account_panel = AccountPanel::new(&current_user.nickname);
 
-        Self {
-            socket,
+        let mut chat_page = Self {
+            connection,
             current_user,
             contacts_panel,
-            notifications_queue,
+            account_panel,
+            second_factor_panel: SecondFactorPanel::new(),
+            reconnect_code: String::new(),
+            pending_requests: PendingRequests::new(REQUEST_TIMEOUT),
             exit: false,
+            attention_requested: false,
             messenger: Messenger::new(),
-        }
+        };
+        chat_page.request_settings();
+
+        chat_page
+    }
+
+    /// Loads the settings kept by the server, shown in the user data view.
+    fn request_settings(&mut self) {
+        self.send_request(Command::RequestPrivacySettings, PendingRequest::RequestPrivacySettings);
+
+        self.send_request(Command::RequestBlockedUsers, PendingRequest::RequestBlockedUsers);
     }
 
     pub fn show(&mut self, ctx: &egui::Context) -> bool {
+        if let Some(user) = self.connection.update() {
+            self.on_reconnected(user);
+        }
+        self.pending_requests.start_deadlines(self.connection.take_flushed());
         self.handle_notifications();
+        if std::mem::take(&mut self.attention_requested) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(egui::UserAttentionType::Informational));
+        }
+        self.handle_expired_requests(ctx);
+        self.show_connection_status_panel(ctx);
         self.contacts_panel.show(ctx);
         if let Some(content_show_signal) = self.contacts_panel.main_content_signal() {
             self.show_central_panel(ctx, content_show_signal);
         }
+        if !matches!(self.contacts_panel.main_content_signal(), Some(ShowMainContentSignal::UserData)) {
+            self.second_factor_panel.hide_recovery_codes();
+        }
 
         if let Some(event) = self.contacts_panel.next_event() {
             self.handle_contact_panel_event(event);
         }
 
+        if let Some(event) = self.account_panel.next_event() {
+            self.handle_account_panel_event(event);
+        }
+
+        if let Some(event) = self.second_factor_panel.next_event() {
+            self.handle_second_factor_panel_event(event);
+        }
+
         self.exit
     }
 
+    /// Sends the command, its reply is awaited from when it is written to the connection.
+    fn send_request(&mut self, cmd: Command, request: PendingRequest) {
+        let request_id = self.connection.send(cmd);
+
+        if self.connection.is_queued(request_id) {
+            self.pending_requests.insert_queued(request_id, request);
+        }
+        else {
+            self.pending_requests.insert(request_id, request);
+        }
+    }
+
+    fn on_reconnected(&mut self, user: User) {
+        self.current_user = user;
+
+        for username in self.contacts_panel.contacts_usernames() {
+            self.send_request(Command::RequestContact(username.clone()), PendingRequest::RefreshContact(username));
+        }
+
+        self.request_settings();
+    }
+
+    fn show_connection_status_panel(&mut self, ctx: &egui::Context) {
+        let (status, code_required) = match self.connection.state() {
+            ConnectionState::Connected => return,
+            ConnectionState::Reconnecting(attempt) => 
+                (format!("Reconnecting… (attempt {attempt})"), false),
+            ConnectionState::SecondFactorRequired(None) => 
+                (String::from("Enter your two-factor code to log back in"), true),
+            ConnectionState::SecondFactorRequired(Some(error_message)) => 
+                (format!("{error_message}, enter your two-factor code to log back in"), true),
+            ConnectionState::Failed(error_message) => 
+                (format!("Connection to server lost: {error_message}"), false),
+        };
+
+        egui::TopBottomPanel::top("connection_status_panel")
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.colored_label(ui.visuals().error_fg_color, status);
+
+                if code_required {
+                    ui.add(egui::TextEdit::singleline(&mut self.reconnect_code).desired_width(100.0));
+
+                    let code = self.reconnect_code.trim();
+                    if ui.add_enabled(!code.is_empty(), egui::Button::new("Log back in")).clicked() {
+                        let code = code.to_string();
+                        self.reconnect_code.clear();
+                        self.connection.send_second_factor(code);
+                    }
+                }
+
+                if ui.button("Back to login").clicked() {
+                    self.exit = true;
+                }
+            });
+        });
+    }
+
     fn show_central_panel(&mut self, ctx: &egui::Context, content_show_signal: ShowMainContentSignal) {
         egui::CentralPanel::default()
         .show(ctx, |ui| {
@@ -81,116 +186,296 @@ impl ChatPage {
         ui.vertical_centered(|ui| ui.heading(&self.current_user.nickname));
         ui.separator();
 
-        egui::Grid::new("user_infos")
+        egui::ScrollArea::vertical()
+        .auto_shrink(false)
         .show(ui, |ui| {
-            ui.label("Username");
-            ui.label(&self.current_user.username);
-            ui.end_row();
+            egui::Grid::new("user_infos")
+            .show(ui, |ui| {
+                ui.label("Username");
+                ui.label(&self.current_user.username);
+                ui.end_row();
 
-            ui.label("Nickname");
-            ui.label(&self.current_user.nickname);
+                ui.label("Nickname");
+                ui.label(&self.current_user.nickname);
+            });
+
+            ui.add_space(20.0);
+            self.account_panel.show(ui, &self.current_user);
+
+            ui.add_space(20.0);
+            self.second_factor_panel.show(ui);
         });
     }
 
     fn show_conversation(&mut self, ui: &mut egui::Ui) {
         let selected_contact = self.contacts_panel.seletected_contact().unwrap();
+        let contact_id = selected_contact.id;
         let title = format!("Chat with {}", selected_contact.nickname);
         ui.vertical_centered(|ui| ui.heading(title));
         ui.separator();
 
-        let instance = self.messenger.get_messaging_instance(selected_contact.id).unwrap();
+        let instance = self.messenger.get_messaging_instance(contact_id).unwrap();
+
+        if Self::show_chat_controls(ui, instance) {
+            let content = std::mem::take(&mut instance.text_to_send);
+            let message_index = instance.push_message(ChatMessage {
+                author: self.current_user.nickname.clone(),
+                content: content.clone(),
+                status: MessageStatus::Sending,
+            });
 
-        Self::show_chat_controls(ui, instance);
-        
+            let message = OutgoingMessage { recipient: contact_id, content };
+            self.send_request(Command::SendMessage(message), PendingRequest::SendMessage(contact_id, message_index));
+        }
+
+        let instance = self.messenger.get_messaging_instance(contact_id).unwrap();
         egui::ScrollArea::vertical()
         .auto_shrink(false)
         .stick_to_bottom(true)
         .show(ui, |ui| {
-            for _ in 0..100 {
-                ui.label("Hello world!");
+            for message in &instance.messages {
+                Self::show_message(ui, message);
             }
         });
     }
 
-    fn show_chat_controls(ui: &mut egui::Ui, instance: &mut MessagingInstance) {
+    fn show_message(ui: &mut egui::Ui, message: &ChatMessage) {
+        ui.horizontal_wrapped(|ui| {
+            ui.strong(&message.author);
+            ui.label(&message.content);
+        });
+
+        match &message.status {
+            MessageStatus::Received | MessageStatus::Delivered => (),
+            MessageStatus::Sending => { ui.weak("Sending…"); }
+            MessageStatus::NotDelivered(error_message) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Message not delivered: {error_message}"));
+            }
+        }
+    }
+
+    /// Returns `true` when a message is ready to be sent.
+    fn show_chat_controls(ui: &mut egui::Ui, instance: &mut MessagingInstance) -> bool {
         egui::TopBottomPanel::bottom("chat_crtls_panel")
         .min_height(75.0)
         .show_inside(ui, |ui| {
         ui.with_layout(egui::Layout::right_to_left(egui::Align::Min), |ui| {
-            let _ = ui.button("send");
+            let can_send = !instance.text_to_send.trim().is_empty();
+            let send_clicked = ui.add_enabled(can_send, egui::Button::new("send")).clicked();
             let message_area = egui::TextEdit::multiline(&mut instance.text_to_send);
             egui::ScrollArea::vertical()
             .show(ui, |ui| ui.add_sized(ui.available_size(), message_area));
-        });
-        });
+
+            send_clicked
+        }).inner
+        }).inner
     }
 
     fn handle_notifications(&mut self) {
-        let result = self.notifications_queue.pop_notification();
-        if result.is_none() {
-            return;
-        }
-
-        let (notification, payload) = result.unwrap();
+        while let Some(event) = self.connection.next_event() {
+            match &event.notification {
+                // Secrets of the second factor stay out of the logs
+                Notification::SecondFactorEnrollment(_) | Notification::SecondFactorEnabled(_) => 
+                    println!("Received two-factor secrets for request {:?}", event.request_id),
+                notification => println!("Received notification {:?} for request {:?}", notification, event.request_id),
+            }
 
-        println!("Received notification {:?}", notification);
+            let signal = ChatNotificationHandler::handle_notification(event.notification);
 
-        let signal = ChatNotificationHandler::handle_notification(notification, payload);
+            match event.request_id {
+                Some(request_id) => match self.pending_requests.take(request_id) {
+                    Some(request) => self.handle_reply(request, signal),
+                    None => println!("Dropping reply to unknown or expired request {:?}", request_id)
+                },
+                None => self.handle_event(signal)
+            }
+        }
+    }
 
-        match signal {
-            NotificationHandlerSignal::ContactReceived(contact) => {
+    fn handle_reply(&mut self, request: PendingRequest, signal: NotificationHandlerSignal) {
+        match (request, signal) {
+            (PendingRequest::AddContact(username), NotificationHandlerSignal::ContactReceived(contact)) => {
                 if contact.id != self.current_user.id {
                     self.messenger.add_messsaging_instance(contact.id);
-                    self.contacts_panel.add_contact(contact);
+                    self.contacts_panel.add_contact(contact, username);
                 }
                 else {
                     self.contacts_panel.contact_search_failed("You can't add yourself as contact!");
                 }
             }
-            NotificationHandlerSignal::ContactRetreivingFailed(error_message) => 
+            (PendingRequest::AddContact(_), NotificationHandlerSignal::RequestFailed(error_message)) => 
                 self.contacts_panel.contact_search_failed(&error_message),
-            
+            (PendingRequest::RefreshContact(_), NotificationHandlerSignal::ContactReceived(contact)) => 
+                self.contacts_panel.update_contact(contact),
+            (PendingRequest::RefreshContact(username), NotificationHandlerSignal::RequestFailed(error_message)) => 
+                println!("Could not refresh contact {username}: {error_message}"),
+            (PendingRequest::SendMessage(contact_id, message_index), NotificationHandlerSignal::MessageSent) => 
+                self.set_message_status(contact_id, message_index, MessageStatus::Delivered),
+            (PendingRequest::SendMessage(contact_id, message_index), NotificationHandlerSignal::RequestFailed(error_message)) => 
+                self.set_message_status(contact_id, message_index, MessageStatus::NotDelivered(error_message)),
+            (PendingRequest::EnableSecondFactor, NotificationHandlerSignal::SecondFactorEnrollment(enrollment)) => 
+                self.second_factor_panel.enrollment_received(enrollment),
+            (PendingRequest::ConfirmSecondFactor, NotificationHandlerSignal::SecondFactorEnabled(recovery_codes)) => 
+                self.second_factor_panel.enabled(recovery_codes),
+            (PendingRequest::EnableSecondFactor | PendingRequest::ConfirmSecondFactor, NotificationHandlerSignal::RequestFailed(error_message)) => 
+                self.second_factor_panel.request_failed(&error_message),
+            (PendingRequest::ChangeNickname, NotificationHandlerSignal::AccountUpdated(user)) => {
+                self.account_panel.nickname_changed(&user.nickname);
+                self.current_user = user;
+            }
+            (PendingRequest::ChangePassword(password), NotificationHandlerSignal::AccountUpdated(_)) => {
+                self.connection.update_password(password);
+                self.account_panel.password_changed();
+            }
+            (PendingRequest::DeleteAccount, NotificationHandlerSignal::AccountDeleted) => 
+                self.exit = true,
+            (PendingRequest::ChangeNickname | PendingRequest::ChangePassword(_) | PendingRequest::DeleteAccount | PendingRequest::UpdatePrivacySettings | PendingRequest::Unblock, NotificationHandlerSignal::RequestFailed(error_message)) => 
+                self.account_panel.request_failed(&error_message),
+            (PendingRequest::SearchUsers(query), NotificationHandlerSignal::SearchResults(results)) => 
+                self.contacts_panel.search_results_received(&query, results),
+            (PendingRequest::SearchUsers(query), NotificationHandlerSignal::RequestFailed(error_message)) => 
+                println!("Could not search users matching {query}: {error_message}"),
+            (PendingRequest::RequestPrivacySettings, NotificationHandlerSignal::PrivacySettings(settings)) => 
+                self.account_panel.privacy_settings_received(settings),
+            (PendingRequest::RequestPrivacySettings, NotificationHandlerSignal::RequestFailed(error_message)) => 
+                println!("Could not load the privacy settings: {error_message}"),
+            (PendingRequest::UpdatePrivacySettings, NotificationHandlerSignal::PrivacySettings(settings)) => 
+                self.account_panel.privacy_settings_updated(settings),
+            (PendingRequest::Block(contact_id), NotificationHandlerSignal::BlockedUsers(blocked_users)) => {
+                self.contacts_panel.remove_contact(contact_id);
+                self.account_panel.blocked_users_received(blocked_users);
+            }
+            (PendingRequest::Block(contact_id), NotificationHandlerSignal::RequestFailed(error_message)) => 
+                println!("Could not block contact {contact_id:?}: {error_message}"),
+            (PendingRequest::Unblock, NotificationHandlerSignal::BlockedUsers(blocked_users)) => 
+                self.account_panel.user_unblocked(blocked_users),
+            (PendingRequest::RequestBlockedUsers, NotificationHandlerSignal::BlockedUsers(blocked_users)) => 
+                self.account_panel.blocked_users_received(blocked_users),
+            (PendingRequest::RequestBlockedUsers, NotificationHandlerSignal::RequestFailed(error_message)) => 
+                println!("Could not load the blocked users: {error_message}"),
+
             _ => ()
         }
     }
 
+    fn handle_event(&mut self, signal: NotificationHandlerSignal) {
+        match signal {
+            NotificationHandlerSignal::MessageReceived(message) => self.on_message_received(message),
+            NotificationHandlerSignal::ContactUpdated(contact) => self.contacts_panel.update_contact(contact),
+            NotificationHandlerSignal::ContactDeleted(contact_id) => self.contacts_panel.remove_contact(contact_id),
+            NotificationHandlerSignal::RequestFailed(error_message) => println!("{error_message}"),
+            _ => ()
+        }
+    }
+
+    /// Messages from users who are not in the contacts yet add them to the contacts.
+    fn on_message_received(&mut self, message: IncomingMessage) {
+        let sender = message.sender;
+        if sender.id == self.current_user.id {
+            return;
+        }
+
+        let contact = Contact {
+            id: sender.id,
+            nickname: sender.nickname.clone(),
+            bot: sender.bot,
+        };
+        self.contacts_panel.insert_contact(contact, sender.username);
+        self.messenger.add_messsaging_instance(sender.id);
+        if self.contacts_panel.message_received(sender.id) {
+            self.attention_requested = true;
+        }
+
+        if let Some(instance) = self.messenger.get_messaging_instance(sender.id) {
+            instance.push_message(ChatMessage {
+                author: sender.nickname,
+                content: message.content,
+                status: MessageStatus::Received,
+            });
+        }
+    }
+
+    fn set_message_status(&mut self, contact_id: UserId, message_index: usize, status: MessageStatus) {
+        if let Some(instance) = self.messenger.get_messaging_instance(contact_id) {
+            instance.set_message_status(message_index, status);
+        }
+    }
+
+    fn handle_expired_requests(&mut self, ctx: &egui::Context) {
+        for request in self.pending_requests.take_expired() {
+            match request {
+                PendingRequest::AddContact(_) => 
+                    self.contacts_panel.contact_search_failed("Request timed out"),
+                PendingRequest::RefreshContact(username) => 
+                    println!("Refreshing contact {username} timed out"),
+                PendingRequest::SendMessage(contact_id, message_index) => 
+                    self.set_message_status(contact_id, message_index, MessageStatus::NotDelivered(String::from("Request timed out"))),
+                PendingRequest::EnableSecondFactor | PendingRequest::ConfirmSecondFactor => 
+                    self.second_factor_panel.request_failed("Request timed out"),
+                PendingRequest::ChangeNickname | PendingRequest::ChangePassword(_) | PendingRequest::DeleteAccount | PendingRequest::UpdatePrivacySettings | PendingRequest::Unblock => 
+                    self.account_panel.request_failed("Request timed out"),
+                PendingRequest::Block(contact_id) => 
+                    println!("Blocking contact {contact_id:?} timed out"),
+                PendingRequest::RequestBlockedUsers => 
+                    println!("Loading the blocked users timed out"),
+                PendingRequest::SearchUsers(query) => 
+                    println!("Searching users matching {query} timed out"),
+                PendingRequest::RequestPrivacySettings => 
+                    println!("Loading the privacy settings timed out"),
+            }
+        }
+
+        if let Some(next_expiration) = self.pending_requests.next_expiration() {
+            ctx.request_repaint_after(next_expiration);
+        }
+    }
+
     fn handle_contact_panel_event(&mut self, event: ContactPanelEvent) {
         match event {
             ContactPanelEvent::SendRequestContact(username) => {
-                if send_request_contact_cmd(&mut self.socket, username).is_err() {
-                    self.contacts_panel.contact_search_failed("Error while connecting to server");
-                }
+                self.send_request(Command::RequestContact(username.clone()), PendingRequest::AddContact(username));
+            }
+            ContactPanelEvent::SendSearchUsers(query) => {
+                let search = UserSearch { query: query.clone(), limit: SUGGESTIONS_LIMIT };
+                self.send_request(Command::SearchUsers(search), PendingRequest::SearchUsers(query));
+            }
+            ContactPanelEvent::SendBlock(contact_id) => {
+                self.send_request(Command::Block(contact_id), PendingRequest::Block(contact_id));
             }
             ContactPanelEvent::DisconnectUser => {
                 self.exit = true;
             }
         }
     }
-}
-
 
-fn run_notification_listener(notifications_queue: Arc<NotificationsQueue>, mut socket: TcpStream) {
-    loop {
-        let result = read_notification(&mut socket)
-        .and_then(|notification| {
-            let payload = if notification.has_payload() {
-                read_notification_payload(&mut socket)?
+    fn handle_account_panel_event(&mut self, event: AccountPanelEvent) {
+        let (command, request) = match event {
+            AccountPanelEvent::ChangeNickname(nickname) => 
+                (Command::ChangeNickname(nickname), PendingRequest::ChangeNickname),
+            AccountPanelEvent::ChangePassword(password_change) => {
+                let new_password = password_change.new.clone();
+                (Command::ChangePassword(password_change), PendingRequest::ChangePassword(new_password))
             }
-            else {
-                BytesBuffer::empty()
-            };
-            Ok((notification, payload))
-        });
+            AccountPanelEvent::DeleteAccount(password) => 
+                (Command::DeleteAccount(password), PendingRequest::DeleteAccount),
+            AccountPanelEvent::UpdatePrivacySettings(settings) => 
+                (Command::UpdatePrivacySettings(settings), PendingRequest::UpdatePrivacySettings),
+            AccountPanelEvent::Unblock(user_id) => 
+                (Command::Unblock(user_id), PendingRequest::Unblock),
+        };
+
+        self.send_request(command, request);
+    }
 
-        if let Ok((notification, payload)) = result {
-            notifications_queue.push_notification(notification, payload);
-        }
+    fn handle_second_factor_panel_event(&mut self, event: SecondFactorPanelEvent) {
+        let (command, request) = match event {
+            SecondFactorPanelEvent::SendEnableSecondFactor(password) => 
+                (Command::EnableSecondFactor(password), PendingRequest::EnableSecondFactor),
+            SecondFactorPanelEvent::SendConfirmSecondFactor(code) => 
+                (Command::ConfirmSecondFactor(code), PendingRequest::ConfirmSecondFactor),
+        };
 
+        self.send_request(command, request);
     }
 }
 
-fn run_notification_listener_par(notifications_queue: Arc<NotificationsQueue>, socket: TcpStream) {
-    thread::spawn(|| {
-        run_notification_listener(notifications_queue, socket);
-    });
-}
\ No newline at end of file
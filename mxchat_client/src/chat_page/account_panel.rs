@@ -0,0 +1,236 @@
+use eframe::egui;
+use mxchat_core::{auth::{PasswordChange, PrivacySettings, User, UserId}, messaging::Contact, validation::ValidationPolicy};
+
+use super::JobStatus;
+
+pub enum AccountPanelEvent {
+    ChangeNickname(String),
+    ChangePassword(PasswordChange),
+    DeleteAccount(String),
+    UpdatePrivacySettings(PrivacySettings),
+    Unblock(UserId),
+}
+
+/// Edit forms of the user data view. A single request runs at a time, its outcome is
+/// shown under the forms.
+pub struct AccountPanel {
+    nickname: String,
+    old_password: String,
+    new_password: String,
+    new_password_confirmation: String,
+    deletion_password: String,
+    deletion_confirmed: bool,
+    /// `None` until the server sent them.
+    privacy_settings: Option<PrivacySettings>,
+    blocked_users: Vec<Contact>,
+    validation: ValidationPolicy,
+    job_status: JobStatus,
+    success_message: Option<&'static str>,
+    event: Option<AccountPanelEvent>,
+}
+
+impl AccountPanel {
+    pub fn new(nickname: &str) -> Self {
+        Self {
+            nickname: nickname.into(),
+            old_password: String::new(),
+            new_password: String::new(),
+            new_password_confirmation: String::new(),
+            deletion_password: String::new(),
+            deletion_confirmed: false,
+            privacy_settings: None,
+            blocked_users: Vec::new(),
+            validation: ValidationPolicy::default(),
+            job_status: JobStatus::Idle,
+            success_message: None,
+            event: None,
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<AccountPanelEvent> {
+        self.event.take()
+    }
+
+    pub fn nickname_changed(&mut self, nickname: &str) {
+        self.nickname = nickname.into();
+        self.request_succeeded("Nickname changed");
+    }
+
+    pub fn password_changed(&mut self) {
+        self.old_password.clear();
+        self.new_password.clear();
+        self.new_password_confirmation.clear();
+        self.request_succeeded("Password changed");
+    }
+
+    pub fn privacy_settings_received(&mut self, settings: PrivacySettings) {
+        self.privacy_settings = Some(settings);
+    }
+
+    pub fn privacy_settings_updated(&mut self, settings: PrivacySettings) {
+        self.privacy_settings = Some(settings);
+        self.request_succeeded("Privacy settings saved");
+    }
+
+    pub fn blocked_users_received(&mut self, blocked_users: Vec<Contact>) {
+        self.blocked_users = blocked_users;
+    }
+
+    pub fn user_unblocked(&mut self, blocked_users: Vec<Contact>) {
+        self.blocked_users = blocked_users;
+        self.request_succeeded("User unblocked");
+    }
+
+    fn request_succeeded(&mut self, message: &'static str) {
+        self.job_status = JobStatus::Idle;
+        self.success_message = Some(message);
+    }
+
+    pub fn request_failed(&mut self, error_message: &str) {
+        self.job_status = JobStatus::Failed(error_message.into());
+    }
+
+    fn send(&mut self, event: AccountPanelEvent) {
+        self.event = Some(event);
+        self.job_status = JobStatus::InProgress;
+        self.success_message = None;
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, current_user: &User) {
+        let in_progress = self.job_status == JobStatus::InProgress;
+
+        ui.add_enabled_ui(!in_progress, |ui| {
+            ui.heading("Nickname");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.nickname);
+
+                let nickname = self.validation.validate_nickname(&self.nickname);
+                let can_save = nickname.as_ref().is_ok_and(|nickname| *nickname != current_user.nickname);
+                if ui.add_enabled(can_save, egui::Button::new("Save")).clicked() {
+                    if let Ok(nickname) = nickname {
+                        self.send(AccountPanelEvent::ChangeNickname(nickname));
+                    }
+                }
+            });
+
+            ui.add_space(20.0);
+            self.show_password_form(ui, &current_user.username);
+
+            ui.add_space(20.0);
+            self.show_privacy_settings(ui);
+
+            ui.add_space(20.0);
+            self.show_deletion_form(ui);
+        });
+
+        match &self.job_status {
+            JobStatus::Idle => if let Some(message) = self.success_message {
+                ui.label(message);
+            },
+            JobStatus::InProgress => { ui.spinner(); }
+            JobStatus::Failed(error_message) => {
+                ui.colored_label(ui.visuals().error_fg_color, error_message);
+            }
+        }
+    }
+
+    fn show_password_form(&mut self, ui: &mut egui::Ui, username: &str) {
+        ui.heading("Password");
+
+        egui::Grid::new("password_form_grid")
+        .spacing((10.0, 10.0))
+        .show(ui, |ui| {
+            ui.label("Current password");
+            ui.add(egui::TextEdit::singleline(&mut self.old_password).password(true));
+            ui.end_row();
+
+            ui.label("New password");
+            ui.add(egui::TextEdit::singleline(&mut self.new_password).password(true));
+            ui.end_row();
+
+            ui.label("Confirm new password");
+            ui.add(egui::TextEdit::singleline(&mut self.new_password_confirmation).password(true));
+            ui.end_row();
+        });
+
+        let password_validation = self.validation.validate_password(&self.new_password, username);
+        let passwords_match = self.new_password == self.new_password_confirmation;
+
+        if let Err(error) = &password_validation {
+            if !self.new_password.is_empty() {
+                ui.colored_label(ui.visuals().error_fg_color, error.to_string());
+            }
+        }
+        else if !passwords_match && !self.new_password_confirmation.is_empty() {
+            ui.colored_label(ui.visuals().error_fg_color, "The new passwords don't match");
+        }
+
+        let can_change = !self.old_password.is_empty() && password_validation.is_ok() && passwords_match;
+        if ui.add_enabled(can_change, egui::Button::new("Change password")).clicked() {
+            let password_change = PasswordChange {
+                old: self.old_password.clone(),
+                new: self.new_password.clone(),
+            };
+            self.send(AccountPanelEvent::ChangePassword(password_change));
+        }
+    }
+
+    fn show_privacy_settings(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Privacy");
+
+        let Some(mut settings) = self.privacy_settings else {
+            ui.spinner();
+            return;
+        };
+
+        let response = ui.checkbox(&mut settings.discoverable, "Show my account in search results")
+            .on_hover_text("Hidden accounts can still be added with their exact username");
+        if response.changed() {
+            self.send(AccountPanelEvent::UpdatePrivacySettings(settings));
+        }
+
+        ui.add_space(10.0);
+        ui.label("Blocked users");
+
+        if self.blocked_users.is_empty() {
+            ui.weak("Nobody is blocked. Block a contact from its menu in the contacts list.");
+            return;
+        }
+
+        let mut unblocked = None;
+        egui::Grid::new("blocked_users_grid")
+        .spacing((10.0, 5.0))
+        .show(ui, |ui| {
+            for blocked_user in &self.blocked_users {
+                ui.label(&blocked_user.nickname);
+                if ui.button("Unblock").clicked() {
+                    unblocked = Some(blocked_user.id);
+                }
+                ui.end_row();
+            }
+        });
+
+        if let Some(user_id) = unblocked {
+            self.send(AccountPanelEvent::Unblock(user_id));
+        }
+    }
+
+    fn show_deletion_form(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Delete account");
+        ui.label("Your account is removed from the contacts of the other users. This can't be undone.");
+
+        ui.horizontal(|ui| {
+            ui.label("Password");
+            ui.add(egui::TextEdit::singleline(&mut self.deletion_password).password(true));
+        });
+        ui.checkbox(&mut self.deletion_confirmed, "I want to delete my account");
+
+        let can_delete = self.deletion_confirmed && !self.deletion_password.is_empty();
+        let button = egui::Button::new(egui::RichText::new("Delete account").color(ui.visuals().error_fg_color));
+        if ui.add_enabled(can_delete, button).clicked() {
+            let password = std::mem::take(&mut self.deletion_password);
+            self.deletion_confirmed = false;
+            self.send(AccountPanelEvent::DeleteAccount(password));
+        }
+    }
+}
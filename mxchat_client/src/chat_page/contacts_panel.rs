@@ -1,18 +1,42 @@
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
 use eframe::egui;
-use mxchat_core::messaging::Contact;
+use mxchat_core::{auth::UserId, messaging::{Contact, SearchResult}};
 
 use super::{JobStatus, ShowMainContentSignal};
 
+/// Pause in the typing before the suggestions are searched.
+const SUGGESTIONS_DELAY: Duration = Duration::from_millis(300);
+
 pub enum ContactPanelEvent {
     SendRequestContact(String),
+    SendSearchUsers(String),
+    SendBlock(UserId),
     DisconnectUser
 }
 
+/// Entry of the context menu of a contact, or a click selecting it.
+enum ContactAction {
+    Select,
+    ToggleMute,
+    Block,
+}
+
 pub struct ContactsPanel {
     content_show_signal: Option<ShowMainContentSignal>,
     contacts: Vec<Contact>,
+    contacts_usernames: HashMap<UserId, String>,
+    /// Contacts whose messages are kept without alerting the user, only known to this client.
+    muted_contacts: HashSet<UserId>,
+    /// Messages received since the conversation was last shown.
+    unread_messages: HashMap<UserId, usize>,
     searched_contact: Option<String>,
     contact_search_job_status: JobStatus,
+    /// Accounts matching the search bar, for the query they were searched with.
+    suggestions: Vec<SearchResult>,
+    suggestions_query: String,
+    /// Last change of the search bar not searched yet.
+    search_edited_at: Option<Instant>,
     event: Option<ContactPanelEvent>,
     selected_contact: Option<usize>,
     username: String
@@ -23,8 +47,14 @@ impl ContactsPanel {
         Self {
             content_show_signal: None,
             contacts: Vec::new(),
+            contacts_usernames: HashMap::new(),
+            muted_contacts: HashSet::new(),
+            unread_messages: HashMap::new(),
             searched_contact: None,
             contact_search_job_status: JobStatus::Idle,
+            suggestions: Vec::new(),
+            suggestions_query: String::new(),
+            search_edited_at: None,
             event: None,
             selected_contact: None,
             username: username.into()
@@ -39,12 +69,87 @@ impl ContactsPanel {
         self.event.take()
     }
 
-    pub fn add_contact(&mut self, contact: Contact) {
-        self.contacts.push(contact);
+    pub fn add_contact(&mut self, contact: Contact, username: String) {
+        self.insert_contact(contact, username);
+
         self.contact_search_job_status = JobStatus::Idle;
         if let Some(buffer) = self.searched_contact.as_mut() {
             buffer.clear();
         }
+        self.clear_suggestions();
+    }
+
+    /// Adds the contact without touching the search bar, used for contacts added by the application.
+    pub fn insert_contact(&mut self, contact: Contact, username: String) {
+        self.contacts_usernames.insert(contact.id, username);
+
+        if self.contacts.iter().any(|known_contact| known_contact.id == contact.id) {
+            self.update_contact(contact);
+        }
+        else {
+            self.contacts.push(contact);
+        }
+    }
+
+    pub fn update_contact(&mut self, contact: Contact) {
+        if let Some(known_contact) = self.contacts.iter_mut().find(|known_contact| known_contact.id == contact.id) {
+            known_contact.nickname = contact.nickname;
+        }
+    }
+
+    /// Forgets the contact, closing its conversation when it is shown.
+    pub fn remove_contact(&mut self, contact_id: UserId) {
+        self.contacts_usernames.remove(&contact_id);
+        self.unread_messages.remove(&contact_id);
+
+        let Some(index) = self.contacts.iter().position(|contact| contact.id == contact_id) else {
+            return;
+        };
+        self.contacts.remove(index);
+
+        match self.selected_contact {
+            Some(selected) if selected == index => {
+                self.selected_contact = None;
+                if matches!(self.content_show_signal, Some(ShowMainContentSignal::Conversation)) {
+                    self.content_show_signal = None;
+                }
+            }
+            Some(selected) if selected > index => self.selected_contact = Some(selected - 1),
+            _ => ()
+        }
+    }
+
+    /// Counts the message as unread unless its conversation is shown. Returns `true` when
+    /// the user should be alerted, which muted contacts never do.
+    pub fn message_received(&mut self, contact_id: UserId) -> bool {
+        let conversation_shown = matches!(self.content_show_signal, Some(ShowMainContentSignal::Conversation)) &&
+            self.seletected_contact().is_some_and(|contact| contact.id == contact_id);
+        if conversation_shown {
+            return false;
+        }
+
+        *self.unread_messages.entry(contact_id).or_default() += 1;
+        !self.muted_contacts.contains(&contact_id)
+    }
+
+    pub fn contacts_usernames(&self) -> Vec<String> {
+        self.contacts_usernames
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Suggestions searched for a query the user changed since are dropped.
+    pub fn search_results_received(&mut self, query: &str, results: Vec<SearchResult>) {
+        if query == self.suggestions_query {
+            self.suggestions = results;
+        }
+    }
+
+    fn clear_suggestions(&mut self) {
+        self.suggestions.clear();
+        self.suggestions_query.clear();
+        self.search_edited_at = None;
     }
 
     pub fn contact_search_failed(&mut self, error_message: &str) {
@@ -74,6 +179,11 @@ impl ContactsPanel {
                 }
             });
 
+            if self.searched_contact.is_some() {
+                self.search_suggestions(ui.ctx());
+                self.show_suggestions(ui);
+            }
+
             if let JobStatus::Failed(error_message) = &self.contact_search_job_status {
                 ui.label(error_message);
             }
@@ -130,42 +240,132 @@ impl ContactsPanel {
             if ui.add_enabled(self.contact_search_job_status != JobStatus::InProgress, egui::Button::new("X")).clicked() {
                 self.searched_contact = None;
                 self.contact_search_job_status = JobStatus::Idle;
+                self.clear_suggestions();
                 return;
             }
 
             let text_edit = 
-                    egui::TextEdit::singleline(self.searched_contact.as_mut().unwrap());
+                    egui::TextEdit::singleline(self.searched_contact.as_mut().unwrap())
+                    .hint_text("Username or nickname");
 
-            ui.add_sized(ui.available_size(), text_edit);
+            if ui.add_sized(ui.available_size(), text_edit).changed() {
+                self.search_edited_at = Some(Instant::now());
+            }
         });
     }
 
-    fn show_contacts(&mut self, ui: &mut egui::Ui) {
-        let new_selected_contact= self.contacts
-            .iter()
-            .enumerate()
-            .filter_map(|e| Self::show_contact(e, ui))
-            .reduce(|acc, _| acc);
+    /// Searches the suggestions once the user paused typing.
+    fn search_suggestions(&mut self, ctx: &egui::Context) {
+        let Some(edited_at) = self.search_edited_at else {
+            return;
+        };
 
-        if let Some(selected_contact) = new_selected_contact {
-            self.selected_contact = Some(selected_contact);
-            self.content_show_signal = Some(ShowMainContentSignal::Conversation);
+        let elapsed = edited_at.elapsed();
+        if elapsed < SUGGESTIONS_DELAY {
+            ctx.request_repaint_after(SUGGESTIONS_DELAY - elapsed);
+            return;
+        }
+
+        self.search_edited_at = None;
+        let query = self.searched_contact.as_deref().unwrap_or_default().trim().to_string();
+        if query.is_empty() {
+            self.clear_suggestions();
+        }
+        else if query != self.suggestions_query {
+            self.suggestions_query = query.clone();
+            self.event = Some(ContactPanelEvent::SendSearchUsers(query));
         }
     }
 
-    fn show_contact((contact_index, contact): (usize, &Contact), ui: &mut egui::Ui) -> Option<usize> {
-        let label = egui::Label::new(&contact.nickname)
-            .sense(egui::Sense::hover().union(egui::Sense::click()));
+    fn show_suggestions(&mut self, ui: &mut egui::Ui) {
+        let in_progress = self.contact_search_job_status == JobStatus::InProgress;
+        let mut added_username = None;
 
-        let response = ui.add(label)
-            .on_hover_cursor(egui::CursorIcon::PointingHand);
+        for suggestion in &self.suggestions {
+            if self.contacts_usernames.contains_key(&suggestion.contact.id) {
+                continue;
+            }
 
+            let mut text = format!("{} ({})", suggestion.contact.nickname, suggestion.username);
+            if suggestion.contact.bot {
+                text.push_str(" [bot]");
+            }
 
-        if response.clicked() {
-            Some(contact_index)
+            let response = ui.add_enabled(!in_progress, egui::Button::new(text).frame(false))
+                .on_hover_text("Add to the contacts");
+            if response.clicked() {
+                added_username = Some(suggestion.username.clone());
+            }
         }
-        else {
-            None
+
+        if let Some(username) = added_username {
+            self.searched_contact = Some(username.clone());
+            self.contact_search_job_status = JobStatus::InProgress;
+            self.event = Some(ContactPanelEvent::SendRequestContact(username));
+        }
+    }
+
+    fn show_contacts(&mut self, ui: &mut egui::Ui) {
+        let mut action = None;
+        for (contact_index, contact) in self.contacts.iter().enumerate() {
+            if let Some(contact_action) = self.show_contact(contact, ui) {
+                action = Some((contact_index, contact.id, contact_action));
+            }
+        }
+
+        match action {
+            Some((contact_index, contact_id, ContactAction::Select)) => {
+                self.selected_contact = Some(contact_index);
+                self.content_show_signal = Some(ShowMainContentSignal::Conversation);
+                self.unread_messages.remove(&contact_id);
+            }
+            Some((_, contact_id, ContactAction::ToggleMute)) => {
+                let unmuted = self.muted_contacts.remove(&contact_id);
+                if !unmuted {
+                    self.muted_contacts.insert(contact_id);
+                }
+            }
+            Some((_, contact_id, ContactAction::Block)) => self.event = Some(ContactPanelEvent::SendBlock(contact_id)),
+            None => ()
+        }
+    }
+
+    fn show_contact(&self, contact: &Contact, ui: &mut egui::Ui) -> Option<ContactAction> {
+        let muted = self.muted_contacts.contains(&contact.id);
+
+        let mut text = contact.nickname.clone();
+        if contact.bot {
+            text.push_str(" [bot]");
+        }
+        if muted {
+            text.push_str(" [muted]");
+        }
+        if let Some(unread) = self.unread_messages.get(&contact.id) {
+            text.push_str(&format!(" ({unread})"));
+        }
+
+        let mut label = egui::RichText::new(text);
+        if self.unread_messages.contains_key(&contact.id) && !muted {
+            label = label.strong();
         }
+
+        let response = ui.add(egui::Label::new(label).sense(egui::Sense::click()))
+            .on_hover_cursor(egui::CursorIcon::PointingHand);
+
+        let mut action = response.clicked().then_some(ContactAction::Select);
+
+        response.context_menu(|ui| {
+            if ui.button(if muted { "Unmute" } else { "Mute" }).clicked() {
+                action = Some(ContactAction::ToggleMute);
+                ui.close_menu();
+            }
+
+            if ui.button("Block").on_hover_text("Their messages and contact requests are dropped by the server").clicked() {
+                action = Some(ContactAction::Block);
+                ui.close_menu();
+            }
+        });
+
+        action
     }
 }
\ No newline at end of file
@@ -0,0 +1,173 @@
+use eframe::egui;
+use mxchat_core::auth::SecondFactorEnrollment;
+
+use crate::qr_code::QrCode;
+
+use super::JobStatus;
+
+/// Modules of empty border around the QR code, scanners need it to find the symbol.
+const QUIET_ZONE: usize = 4;
+const MODULE_SIZE: f32 = 4.0;
+
+pub enum SecondFactorPanelEvent {
+    SendEnableSecondFactor(String),
+    SendConfirmSecondFactor(String),
+}
+
+enum EnrollmentStep {
+    NotStarted,
+    /// The secret to add to the authenticator app, waiting for one of its codes.
+    Confirming {
+        enrollment: SecondFactorEnrollment,
+        qr_code: Option<QrCode>,
+        code: String,
+    },
+    /// Recovery codes, only shown until the user leaves the view.
+    Enabled(Vec<String>),
+}
+
+/// Enrollment in two-factor authentication, shown in the user data view.
+pub struct SecondFactorPanel {
+    step: EnrollmentStep,
+    /// Asked again before enrolling, which replaces any previous second factor.
+    password: String,
+    job_status: JobStatus,
+    event: Option<SecondFactorPanelEvent>,
+}
+
+impl SecondFactorPanel {
+    pub fn new() -> Self {
+        Self {
+            step: EnrollmentStep::NotStarted,
+            password: String::new(),
+            job_status: JobStatus::Idle,
+            event: None,
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<SecondFactorPanelEvent> {
+        self.event.take()
+    }
+
+    pub fn enrollment_received(&mut self, enrollment: SecondFactorEnrollment) {
+        self.job_status = JobStatus::Idle;
+        self.step = EnrollmentStep::Confirming {
+            qr_code: QrCode::encode(enrollment.provisioning_uri.as_bytes()),
+            enrollment,
+            code: String::new(),
+        };
+    }
+
+    pub fn enabled(&mut self, recovery_codes: Vec<String>) {
+        self.job_status = JobStatus::Idle;
+        self.step = EnrollmentStep::Enabled(recovery_codes);
+    }
+
+    pub fn request_failed(&mut self, error_message: &str) {
+        self.job_status = JobStatus::Failed(error_message.into());
+    }
+
+    /// Forgets the recovery codes once they were shown.
+    pub fn hide_recovery_codes(&mut self) {
+        if matches!(self.step, EnrollmentStep::Enabled(_)) {
+            self.step = EnrollmentStep::NotStarted;
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Two-factor authentication");
+        ui.add_space(5.0);
+
+        let in_progress = self.job_status == JobStatus::InProgress;
+
+        match &mut self.step {
+            EnrollmentStep::NotStarted => {
+                ui.label("Protect your account with the codes of an authenticator app. Enrolling again replaces the previous app and recovery codes.");
+
+                ui.horizontal(|ui| {
+                    ui.label("Password");
+                    ui.add_enabled(!in_progress, egui::TextEdit::singleline(&mut self.password).password(true));
+                });
+
+                let can_enable = !in_progress && !self.password.is_empty();
+                if ui.add_enabled(can_enable, egui::Button::new("Enable two-factor authentication")).clicked() {
+                    self.event = Some(SecondFactorPanelEvent::SendEnableSecondFactor(std::mem::take(&mut self.password)));
+                    self.job_status = JobStatus::InProgress;
+                }
+            }
+            EnrollmentStep::Confirming { enrollment, qr_code, code } => {
+                ui.label("Scan the QR code with your authenticator app, or enter the secret by hand.");
+
+                if let Some(qr_code) = qr_code {
+                    show_qr_code(ui, qr_code);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Secret");
+                    ui.monospace(&enrollment.secret);
+                    if ui.small_button("Copy").clicked() {
+                        ui.ctx().copy_text(enrollment.secret.clone());
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Code of the app");
+                    ui.add_enabled(!in_progress, egui::TextEdit::singleline(code).char_limit(6).desired_width(60.0));
+
+                    let can_confirm = !in_progress && !code.trim().is_empty();
+                    if ui.add_enabled(can_confirm, egui::Button::new("Confirm")).clicked() {
+                        self.event = Some(SecondFactorPanelEvent::SendConfirmSecondFactor(code.trim().to_string()));
+                        self.job_status = JobStatus::InProgress;
+                    }
+                });
+            }
+            EnrollmentStep::Enabled(recovery_codes) => {
+                ui.label("Two-factor authentication is enabled.");
+                ui.label("Keep these recovery codes somewhere safe, each of them replaces a code of the app once. They are not shown again.");
+
+                egui::Grid::new("recovery_codes")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (index, recovery_code) in recovery_codes.iter().enumerate() {
+                        ui.monospace(recovery_code);
+                        if index % 2 == 1 {
+                            ui.end_row();
+                        }
+                    }
+                });
+
+                if ui.button("Copy").clicked() {
+                    ui.ctx().copy_text(recovery_codes.join("\n"));
+                }
+            }
+        }
+
+        match &self.job_status {
+            JobStatus::Idle => (),
+            JobStatus::InProgress => { ui.spinner(); }
+            JobStatus::Failed(error_message) => {
+                ui.colored_label(ui.visuals().error_fg_color, error_message);
+            }
+        }
+    }
+}
+
+fn show_qr_code(ui: &mut egui::Ui, qr_code: &QrCode) {
+    let modules = qr_code.size() + 2 * QUIET_ZONE;
+    let side = modules as f32 * MODULE_SIZE;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(side, side), egui::Sense::hover());
+
+    // Scanners expect dark modules on a light background, whatever the theme
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::WHITE);
+
+    for y in 0..qr_code.size() {
+        for x in 0..qr_code.size() {
+            if qr_code.is_dark(x, y) {
+                let min = rect.min + egui::vec2((x + QUIET_ZONE) as f32, (y + QUIET_ZONE) as f32) * MODULE_SIZE;
+                let module = egui::Rect::from_min_size(min, egui::Vec2::splat(MODULE_SIZE));
+                painter.rect_filled(module, 0.0, egui::Color32::BLACK);
+            }
+        }
+    }
+}
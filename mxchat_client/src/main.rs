@@ -5,6 +5,8 @@ mod gui_utils;
 mod networking;
 mod notifications_handler;
 mod messenger;
+mod pending_requests;
+mod qr_code;
 
 use app::ChatApp;
 use eframe::egui::ViewportBuilder;
@@ -1,14 +1,41 @@
 use std::collections::HashMap;
 use mxchat_core::auth::UserId;
 
+pub enum MessageStatus {
+    Received,
+    Sending,
+    Delivered,
+    NotDelivered(String),
+}
+
+pub struct ChatMessage {
+    pub author: String,
+    pub content: String,
+    pub status: MessageStatus,
+}
+
 pub struct MessagingInstance {
     pub text_to_send: String,
+    pub messages: Vec<ChatMessage>,
 }
 
 impl MessagingInstance {
     fn new() -> Self {
         Self {
             text_to_send: String::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// Returns the index of the message, to update its status once the server answers.
+    pub fn push_message(&mut self, message: ChatMessage) -> usize {
+        self.messages.push(message);
+        self.messages.len() - 1
+    }
+
+    pub fn set_message_status(&mut self, message_index: usize, status: MessageStatus) {
+        if let Some(message) = self.messages.get_mut(message_index) {
+            message.status = status;
         }
     }
 }
@@ -25,10 +52,10 @@ impl Messenger {
     }
 
     pub fn add_messsaging_instance(&mut self, user_id: UserId) {
-        self.intances.insert(user_id, MessagingInstance::new());
+        self.intances.entry(user_id).or_insert_with(MessagingInstance::new);
     }
 
     pub fn get_messaging_instance(&mut self, user_id: UserId) -> Option<&mut MessagingInstance> {
         self.intances.get_mut(&user_id)
     }
-}
\ No newline at end of file
+}
@@ -1,62 +1,48 @@
-use std::{collections::VecDeque, sync::RwLock};
-
-use mxchat_core::{io::BytesBuffer, messaging::Contact, notification::Notification};
-
-pub struct NotificationsQueue {
-    notifications: RwLock<VecDeque<(Notification, BytesBuffer)>>
-}
-
-impl NotificationsQueue {
-    pub fn new() -> Self {
-        Self {
-            notifications: RwLock::new(VecDeque::new())
-        }
-    }
-
-    pub fn push_notification(&self, notification: Notification, payload: BytesBuffer) {
-        self.notifications
-            .write()
-            .unwrap()
-            .push_back((notification, payload));
-    }
-
-    pub fn pop_notification(&self) -> Option<(Notification, BytesBuffer)> {
-        self.notifications
-            .write()
-            .unwrap()
-            .pop_front()
-    }
-}
+use mxchat_core::{auth::{PrivacySettings, SecondFactorEnrollment, User, UserId}, messaging::{Contact, IncomingMessage, SearchResult}, notification::Notification};
 
 pub enum NotificationHandlerSignal {
     ContactReceived(Contact),
-    ContactRetreivingFailed(String),
+    MessageReceived(IncomingMessage),
+    MessageSent,
+    SecondFactorEnrollment(SecondFactorEnrollment),
+    SecondFactorEnabled(Vec<String>),
+    AccountUpdated(User),
+    AccountDeleted,
+    ContactUpdated(Contact),
+    ContactDeleted(UserId),
+    SearchResults(Vec<SearchResult>),
+    PrivacySettings(PrivacySettings),
+    BlockedUsers(Vec<Contact>),
+    RequestFailed(String),
     None
 }
 
 pub struct ChatNotificationHandler;
 
 impl ChatNotificationHandler {
-    pub fn handle_notification(notification: Notification, mut payload: BytesBuffer) -> NotificationHandlerSignal {
+    pub fn handle_notification(notification: Notification) -> NotificationHandlerSignal {
         match notification {
-            Notification::ReceiveContactInfo => {
-                Contact::from_bytes(&mut payload)
-                .inspect(|contact| println!("contact {:?}", contact))
-                .map_or(
-                    NotificationHandlerSignal::ContactRetreivingFailed(
-                        "Error while retreiving user information".into()
-                    ),
-                    |contact| NotificationHandlerSignal::ContactReceived(contact)
-                )
+            Notification::ReceiveContactInfo(contact) => {
+                println!("contact {:?}", contact);
+                NotificationHandlerSignal::ContactReceived(contact)
             },
-            Notification::UserNotFound => {
-                NotificationHandlerSignal::ContactRetreivingFailed(
-                    "User not found".into()
-                )
+            Notification::MessageReceived(message) => NotificationHandlerSignal::MessageReceived(message),
+            Notification::MessageSent => NotificationHandlerSignal::MessageSent,
+            Notification::SecondFactorEnrollment(enrollment) => NotificationHandlerSignal::SecondFactorEnrollment(enrollment),
+            Notification::SecondFactorEnabled(recovery_codes) => NotificationHandlerSignal::SecondFactorEnabled(recovery_codes),
+            Notification::AccountUpdated(user) => NotificationHandlerSignal::AccountUpdated(user),
+            Notification::AccountDeleted => NotificationHandlerSignal::AccountDeleted,
+            Notification::ContactUpdated(contact) => NotificationHandlerSignal::ContactUpdated(contact),
+            Notification::ContactDeleted(user_id) => NotificationHandlerSignal::ContactDeleted(user_id),
+            Notification::SearchResults(results) => NotificationHandlerSignal::SearchResults(results),
+            Notification::PrivacySettings(settings) => NotificationHandlerSignal::PrivacySettings(settings),
+            Notification::BlockedUsers(blocked_users) => NotificationHandlerSignal::BlockedUsers(blocked_users),
+            Notification::Error(error) => {
+                println!("Server error {} ({:?}): {:?}", error.code.code(), error.code, error.detail);
+                NotificationHandlerSignal::RequestFailed(error.message)
             }
 
             _ => NotificationHandlerSignal::None,
         }
     }
 }
-
@@ -0,0 +1,21 @@
+use mxchat_core::auth::UserId;
+
+pub enum PendingRequest {
+    AddContact(String),
+    RefreshContact(String),
+    /// Message sent to the contact, with its index in the conversation.
+    SendMessage(UserId, usize),
+    EnableSecondFactor,
+    ConfirmSecondFactor,
+    ChangeNickname,
+    /// The new password, for the next reconnections once the server accepted it.
+    ChangePassword(String),
+    DeleteAccount,
+    /// Suggestions of the contacts search bar, for the query searched.
+    SearchUsers(String),
+    RequestPrivacySettings,
+    UpdatePrivacySettings,
+    Block(UserId),
+    Unblock,
+    RequestBlockedUsers,
+}
@@ -0,0 +1,436 @@
+/// QR codes of versions 1 to 10 in byte mode with the medium error correction level,
+/// up to 213 bytes, enough for provisioning URIs.
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+/// Error correction codewords per block, then the blocks of each group with their data codewords.
+struct VersionInfo {
+    ec_codewords: usize,
+    groups: &'static [(usize, usize)],
+}
+
+const VERSIONS: [VersionInfo; 10] = [
+    VersionInfo { ec_codewords: 10, groups: &[(1, 16)] },
+    VersionInfo { ec_codewords: 16, groups: &[(1, 28)] },
+    VersionInfo { ec_codewords: 26, groups: &[(1, 44)] },
+    VersionInfo { ec_codewords: 18, groups: &[(2, 32)] },
+    VersionInfo { ec_codewords: 24, groups: &[(2, 43)] },
+    VersionInfo { ec_codewords: 16, groups: &[(4, 27)] },
+    VersionInfo { ec_codewords: 18, groups: &[(4, 31)] },
+    VersionInfo { ec_codewords: 22, groups: &[(2, 38), (2, 39)] },
+    VersionInfo { ec_codewords: 22, groups: &[(3, 36), (2, 37)] },
+    VersionInfo { ec_codewords: 26, groups: &[(4, 43), (1, 44)] },
+];
+
+const ALIGNMENT_POSITIONS: [&[usize]; 10] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// Format bits of the medium error correction level.
+const EC_LEVEL_BITS: u32 = 0b00;
+
+impl QrCode {
+    /// Returns `None` when the data does not fit in version 10.
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let (version, info) = (1..=VERSIONS.len())
+            .zip(&VERSIONS)
+            .find(|(version, info)| data_capacity(info) * 8 >= 4 + char_count_bits(*version) + data.len() * 8)?;
+
+        let codewords = add_error_correction(info, &data_codewords(data, version, data_capacity(info)));
+
+        let mut qr_code = QrCode::empty(version);
+        let mut is_function = vec![false; qr_code.modules.len()];
+        qr_code.draw_function_patterns(version, &mut is_function);
+        qr_code.draw_codewords(&codewords, &is_function);
+
+        let best_mask = (0..8)
+            .min_by_key(|mask| {
+                let mut masked = QrCode { size: qr_code.size, modules: qr_code.modules.clone() };
+                masked.apply_mask(*mask, &is_function);
+                masked.draw_format_bits(*mask);
+                masked.penalty()
+            })
+            .unwrap_or_default();
+
+        qr_code.apply_mask(best_mask, &is_function);
+        qr_code.draw_format_bits(best_mask);
+
+        Some(qr_code)
+    }
+
+    fn empty(version: usize) -> Self {
+        let size = version * 4 + 17;
+
+        Self {
+            size,
+            modules: vec![false; size * size],
+        }
+    }
+
+    /// Number of modules on a side, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize, is_function: &mut [bool]) {
+        let size = self.size;
+        let mut set_function = |qr_code: &mut Self, x: usize, y: usize, dark: bool| {
+            qr_code.set(x, y, dark);
+            is_function[y * size + x] = true;
+        };
+
+        for i in 0..size {
+            set_function(self, 6, i, i % 2 == 0);
+            set_function(self, i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns with their separators
+        for (center_x, center_y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4..=4_isize {
+                for dx in -4..=4_isize {
+                    let (x, y) = (center_x as isize + dx, center_y as isize + dy);
+                    if (0..size as isize).contains(&x) && (0..size as isize).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        set_function(self, x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+
+        let positions = ALIGNMENT_POSITIONS[version - 1];
+        let is_edge = |index: usize| index == 0 || index + 1 == positions.len();
+        for (i, center_x) in positions.iter().enumerate() {
+            for (j, center_y) in positions.iter().enumerate() {
+                // The corners of the finder patterns have no alignment pattern
+                if is_edge(i) && is_edge(j) && (i == 0 || j == 0) {
+                    continue;
+                }
+
+                for dy in -2..=2_isize {
+                    for dx in -2..=2_isize {
+                        let (x, y) = ((*center_x as isize + dx) as usize, (*center_y as isize + dy) as usize);
+                        set_function(self, x, y, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+
+        // Format bits are drawn once the mask is chosen, their modules are only reserved here
+        for i in (0..9).filter(|i| *i != 6) {
+            set_function(self, 8, i, false);
+            set_function(self, i, 8, false);
+        }
+        for i in 0..8 {
+            set_function(self, size - 1 - i, 8, false);
+            set_function(self, 8, size - 1 - i, false);
+        }
+
+        if version >= 7 {
+            let bits = version_bits(version);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                set_function(self, a, b, dark);
+                set_function(self, b, a, dark);
+            }
+        }
+    }
+
+    /// Fills the modules left in a zigzag of two columns, from the bottom right corner.
+    fn draw_codewords(&mut self, codewords: &[u8], is_function: &[bool]) {
+        let mut bit_index = 0;
+        let mut right = self.size - 1;
+
+        loop {
+            // The vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..self.size {
+                let y = if upward { self.size - 1 - vertical } else { vertical };
+
+                for x in [right, right - 1] {
+                    if !is_function[y * self.size + x] && bit_index < codewords.len() * 8 {
+                        let dark = (codewords[bit_index / 8] >> (7 - bit_index % 8)) & 1 == 1;
+                        self.set(x, y, dark);
+                        bit_index += 1;
+                    }
+                }
+            }
+
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32, is_function: &[bool]) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+
+                if invert && !is_function[y * self.size + x] {
+                    let index = y * self.size + x;
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set(8, i, bit(i));
+        }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i));
+        }
+
+        // Always dark module
+        self.set(8, size - 8, true);
+    }
+
+    /// Penalty of the masked symbol, lower values are easier to scan.
+    fn penalty(&self) -> usize {
+        let lines = |horizontal: bool| (0..self.size).map(move |i| {
+            (0..self.size).map(move |j| if horizontal { self.is_dark(j, i) } else { self.is_dark(i, j) })
+        });
+
+        let mut penalty = 0;
+
+        for horizontal in [true, false] {
+            for line in lines(horizontal) {
+                let line: Vec<bool> = line.collect();
+
+                // Runs of five or more modules of the same color
+                for run in line.chunk_by(|a, b| a == b).filter(|run| run.len() >= 5) {
+                    penalty += run.len() - 2;
+                }
+
+                // Patterns looking like finder patterns
+                let finder_like = [true, false, true, true, true, false, true, false, false, false, false];
+                let mut reversed = finder_like;
+                reversed.reverse();
+                penalty += line.windows(finder_like.len())
+                    .filter(|window| *window == finder_like || *window == reversed)
+                    .count() * 40;
+            }
+        }
+
+        // Blocks of two by two modules of the same color
+        for y in 0..self.size - 1 {
+            for x in 0..self.size - 1 {
+                let dark = self.is_dark(x, y);
+                if self.is_dark(x + 1, y) == dark && self.is_dark(x, y + 1) == dark && self.is_dark(x + 1, y + 1) == dark {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Balance of dark and light modules
+        let dark_percent = self.modules.iter().filter(|dark| **dark).count() * 100 / self.modules.len();
+        penalty += dark_percent.abs_diff(50) / 5 * 10;
+
+        penalty
+    }
+}
+
+fn data_capacity(info: &VersionInfo) -> usize {
+    info.groups.iter().map(|(blocks, codewords)| blocks * codewords).sum()
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version < 10 { 8 } else { 16 }
+}
+
+/// Byte mode segment, terminated and padded to the capacity of the version.
+fn data_codewords(data: &[u8], version: usize, capacity: usize) -> Vec<u8> {
+    let mut bits = Vec::with_capacity(capacity * 8);
+    let mut push_bits = |value: usize, count: usize| {
+        bits.extend((0..count).rev().map(|i| (value >> i) & 1 == 1));
+    };
+
+    push_bits(0b0100, 4);
+    push_bits(data.len(), char_count_bits(version));
+    for byte in data {
+        push_bits(*byte as usize, 8);
+    }
+
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.extend(std::iter::repeat_n(false, terminator));
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut codewords: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |value, bit| value << 1 | *bit as u8))
+        .collect();
+
+    let padding = [0xec, 0x11].into_iter().cycle();
+    let missing = capacity - codewords.len();
+    codewords.extend(padding.take(missing));
+
+    codewords
+}
+
+/// Splits the data in blocks and interleaves them, followed by their error correction codewords.
+fn add_error_correction(info: &VersionInfo, data: &[u8]) -> Vec<u8> {
+    let divisor = reed_solomon_divisor(info.ec_codewords);
+
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    for (block_count, block_length) in info.groups {
+        for _ in 0..*block_count {
+            let (block, remaining) = rest.split_at(*block_length);
+            blocks.push((block, reed_solomon_remainder(block, &divisor)));
+            rest = remaining;
+        }
+    }
+
+    let max_block_length = info.groups.iter().map(|(_, length)| *length).max().unwrap_or_default();
+    let mut codewords = Vec::new();
+
+    for i in 0..max_block_length {
+        codewords.extend(blocks.iter().filter_map(|(block, _)| block.get(i)));
+    }
+    for i in 0..info.ec_codewords {
+        codewords.extend(blocks.iter().map(|(_, ec)| ec[i]));
+    }
+
+    codewords
+}
+
+/// Product in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u16 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11d);
+        product ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+
+    product as u8
+}
+
+/// Coefficients of the generator polynomial, without the leading one.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+
+    for byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (value, coefficient) in remainder.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(*coefficient, factor);
+        }
+    }
+
+    remainder
+}
+
+/// Error correction level and mask protected by a BCH code.
+fn format_bits(mask: u32) -> u32 {
+    let data = EC_LEVEL_BITS << 3 | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+
+    (data << 10 | remainder) ^ 0x5412
+}
+
+fn version_bits(version: usize) -> u32 {
+    let mut remainder = version as u32;
+    for _ in 0..12 {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+    }
+
+    (version as u32) << 12 | remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_correction() {
+        // "HELLO WORLD" in version 1 with the medium level
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        let codewords = add_error_correction(&VERSIONS[0], &data);
+
+        assert_eq!(codewords[..16], data);
+        assert_eq!(codewords[16..], [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(version_bits(7), 0x07c94);
+    }
+
+    #[test]
+    fn test_version_fits_data() {
+        let qr_code = QrCode::encode(b"otpauth://totp/mxchat%3Aalice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=mxchat&algorithm=SHA1&digits=6&period=30").unwrap();
+        assert_eq!(qr_code.size(), 7 * 4 + 17);
+
+        // Finder pattern of the top left corner
+        assert!(qr_code.is_dark(0, 0) && !qr_code.is_dark(1, 1) && qr_code.is_dark(3, 3));
+        assert!(QrCode::encode(&[0; 214]).is_none());
+    }
+}
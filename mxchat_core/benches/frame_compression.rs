@@ -0,0 +1,70 @@
+use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mxchat_core::{auth::UserId, encoding::{write_length_prefixed, Encode}, io::{read_frame, write_frame, BytesBuffer, DEFAULT_MAX_FRAME_SIZE}, messaging::Contact, request::RequestId};
+
+/// A message carrying a contact list, the kind of payload compression is meant for.
+fn contacts_message(contacts_count: u32) -> Vec<u8> {
+    let contacts: Vec<Contact> = (0..contacts_count)
+        .map(|id| Contact {
+            id: UserId::new(id),
+            nickname: format!("contact number {id}"),
+            bot: false,
+        })
+        .collect();
+
+    let mut message = BytesBuffer::empty();
+    2u8.encode(&mut message);
+    write_length_prefixed(&mut message, &contacts);
+
+    message.read_all().unwrap().to_vec()
+}
+
+/// Reads frames and acknowledges each of them with one byte, until the peer disconnects.
+fn spawn_loopback_reader() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.set_nodelay(true).unwrap();
+        while read_frame(&mut socket, DEFAULT_MAX_FRAME_SIZE).is_ok() {
+            if socket.write_all(&[1]).is_err() {
+                break;
+            }
+        }
+    });
+
+    let socket = TcpStream::connect(address).unwrap();
+    socket.set_nodelay(true).unwrap();
+
+    socket
+}
+
+fn bench_frame_compression(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback_frame");
+
+    for contacts_count in [10, 100, 1000] {
+        let message = contacts_message(contacts_count);
+        group.throughput(Throughput::Bytes(message.len() as u64));
+
+        for compression in [false, true] {
+            let mut socket = spawn_loopback_reader();
+            let label = if compression { "compressed" } else { "plain" };
+
+            group.bench_with_input(BenchmarkId::new(label, contacts_count), &message, |b, message| {
+                let mut ack = [0u8; 1];
+                b.iter(|| {
+                    let mut message = BytesBuffer::from_bytes(message.clone());
+                    write_frame(&mut socket, RequestId::new(1), &mut message, compression).unwrap();
+                    socket.read_exact(&mut ack).unwrap();
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_frame_compression);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mxchat_core::{auth::User, encoding::Decode, io::BytesBuffer, messaging::Contact, error::ErrorInfo, notification::Notification};
+
+fuzz_target!(|input: (Vec<u8>, Vec<usize>)| {
+    let (bytes, reads) = input;
+
+    let mut bytes_buffer = BytesBuffer::from_bytes(bytes.clone());
+    for bytes_count in reads {
+        let _ = bytes_buffer.read_bytes(bytes_count);
+    }
+    let _ = bytes_buffer.read_all();
+
+    let _ = User::decode(&mut BytesBuffer::from_bytes(bytes.clone()));
+    let _ = Contact::decode(&mut BytesBuffer::from_bytes(bytes.clone()));
+    let _ = ErrorInfo::decode(&mut BytesBuffer::from_bytes(bytes.clone()));
+    let _ = Notification::decode(&mut BytesBuffer::from_bytes(bytes));
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mxchat_core::{command::Command, io::BytesBuffer};
+
+fuzz_target!(|data: &[u8]| {
+    let mut bytes_buffer = BytesBuffer::from_bytes(data.to_vec());
+
+    if let Ok(cmd) = Command::from_bytes(&mut bytes_buffer) {
+        let mut serialized = BytesBuffer::empty();
+        cmd.to_bytes(&mut serialized);
+    }
+});
@@ -1,73 +1,42 @@
-use crate::{io::BytesBuffer, utils::{bytes_as_u32, u32_as_bytes}};
+use crate::{encoding::{Decode, Encode}, utils::{bytes_as_u32, u32_as_bytes}};
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct UserRegisterData {
     pub username: String,
     pub nickname: String,
-    pub password: String
-}
-
-impl UserRegisterData {
-    pub fn new(data_str: &str) -> Option<Self> {
-        let mut iter = data_str.split(';');
-        let username = iter.next()?.to_string();
-        let nickname = iter.next()?.to_string();
-        let password = iter.next()?.to_string();
-
-        Some(Self {
-            username,
-            nickname,
-            password
-        })
-    }
-
-    pub fn to_string(&self) -> String {
-        let mut result = String::with_capacity(
-            self.username.len() + 1 + 
-            self.nickname.len() + 1 + 
-            self.password.len()
-        );
-
-        result.push_str(&self.username);
-        result.push(';');
-        result.push_str(&self.nickname);
-        result.push(';');
-        result.push_str(&self.password);
-
-        result
-    }
+    pub password: String,
+    /// Registers an account run by a program, see [`User::bot`]. Only the admin API may
+    /// set it, the server refuses it in `Command::Register`.
+    pub bot: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct UserConnectData {
     pub username: String,
     pub password: String,
 }
 
-impl UserConnectData {
-    pub fn new(data_str: &str) -> Option<Self> {
-        let mut iter = data_str.split(';');
-        let username = iter.next()?.to_string();
-        let password = iter.next()?.to_string();
-
-        Some(Self {
-            username,
-            password,
-        })
-    }
-
-    pub fn to_string(&self) -> String {
-        let mut result = String::with_capacity(
-            self.username.len() + 1 + 
-            self.password.len()
-        );
+/// Payload of `Command::ChangePassword`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct PasswordChange {
+    pub old: String,
+    pub new: String,
+}
 
-        result.push_str(&self.username);
-        result.push(';');
-        result.push_str(&self.password);
+/// Privacy settings of the logged in account, see `Command::UpdatePrivacySettings`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct PrivacySettings {
+    /// Shown in the results of `Command::SearchUsers`. Hidden accounts can still be
+    /// added with their exact username.
+    pub discoverable: bool,
+}
 
-        result
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        Self {
+            discoverable: true,
+        }
     }
 }
 
@@ -99,49 +68,21 @@ impl UserId {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct User {
     pub id: UserId,
     pub username: String,
     pub nickname: String,
+    /// Set for bot accounts, clients badge them and the server rate limits them separately.
+    pub bot: bool,
 }
 
-impl User {
-    pub fn from_bytes(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
-        let user_id = bytes_buffer.read_bytes(UserId::size())?;
-
-        let id = UserId::from_bytes(&[
-            user_id[0],
-            user_id[1],
-            user_id[2],
-            user_id[3]
-        ]);
-
-        let data = String::from_utf8_lossy(bytes_buffer.read_all()?);
-
-        let mut iter = data.split(';');
-        let username = iter.next()?.to_string();
-        let nickname = iter.next()?.to_string();
-
-        Some(User {
-            id,
-            username,
-            nickname
-        })
-    }
-
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(
-            UserId::size() + 
-            self.username.len() + 1 + 
-            self.nickname.len()
-        );
-
-        result.extend_from_slice(&self.id.to_bytes());
-        result.extend_from_slice(self.username.as_bytes());
-        result.extend_from_slice(b";");
-        result.extend_from_slice(self.nickname.as_bytes());
-
-        result
-    }
-}
\ No newline at end of file
+/// Answer to `Command::EnableSecondFactor`. The account is enrolled once a code of the
+/// authenticator app is confirmed with `Command::ConfirmSecondFactor`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SecondFactorEnrollment {
+    /// `otpauth://` URI of the TOTP secret, usually shown as a QR code.
+    pub provisioning_uri: String,
+    /// The secret in base32, for authenticator apps without a camera.
+    pub secret: String,
+}
@@ -1,80 +1,141 @@
-use crate::{auth::{UserConnectData, UserRegisterData}, io::BytesBuffer, utils};
+use crate::{auth::{PasswordChange, PrivacySettings, UserConnectData, UserId, UserRegisterData}, encoding::{read_length_prefixed_bytes, write_length_prefixed, Decode, Encode}, io::{BytesBuffer, ProtocolFeatures}, messaging::{OutgoingMessage, UserSearch}};
 
-#[derive(Debug)]
+/// On the wire a command is its type byte followed by the length of its payload and the payload itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     Register(UserRegisterData),
     Connect(UserConnectData),
     RequestContact(String),
+    Ping,
+    Pong,
+    Handshake(ProtocolFeatures),
+    SendMessage(OutgoingMessage),
+    /// Starts the enrollment of the logged in account in two-factor authentication once
+    /// its password is checked, answered with `Notification::SecondFactorEnrollment`.
+    /// Confirming it replaces the second factor and the recovery codes of the account.
+    EnableSecondFactor(String),
+    /// Code of the authenticator app finishing the enrollment.
+    ConfirmSecondFactor(String),
+    /// Code of the authenticator app, or a recovery code, finishing a login answered
+    /// with `Notification::SecondFactorRequired`.
+    SecondFactor(String),
+    /// Answered with `Notification::AccountUpdated`, like `ChangeNickname`.
+    ChangePassword(PasswordChange),
+    /// The contacts of the user receive `Notification::ContactUpdated`.
+    ChangeNickname(String),
+    /// Deletes the logged in account once its password is checked, the connection is
+    /// logged out and the contacts of the user receive `Notification::ContactDeleted`.
+    DeleteAccount(String),
+    /// Finds discoverable accounts by the start or an approximation of their username or
+    /// nickname, answered with `Notification::SearchResults`.
+    SearchUsers(UserSearch),
+    /// Answered with `Notification::PrivacySettings`, like `UpdatePrivacySettings`.
+    RequestPrivacySettings,
+    UpdatePrivacySettings(PrivacySettings),
+    /// The server silently drops the messages and contact requests of the blocked user,
+    /// and leaves the blocking user out of its search results.
+    Block(UserId),
+    Unblock(UserId),
+    /// Answered with `Notification::BlockedUsers`, like `Block` and `Unblock`.
+    RequestBlockedUsers,
+    /// Sent before the handshake by a gateway relaying a client, such as the IRC one, with
+    /// the IP address of that client. The rate limits and login lockouts then apply to the
+    /// client rather than to the gateway. Only answered when refused, the server accepts it
+    /// from the gateways it trusts.
+    ForwardedPeer(String),
 }
 
 impl Command {
     pub fn from_bytes(bytes_buffer: &mut BytesBuffer) -> Result<Self, CommandParsingError> {
-        let cmd_type = bytes_buffer.read_bytes(1)
-            .ok_or(CommandParsingError::InvalidPayload)?[0];
+        let cmd_type = u8::decode(bytes_buffer)
+            .ok_or(CommandParsingError::InvalidPayload)?;
+
+        let mut payload = read_length_prefixed_bytes(bytes_buffer)
+            .ok_or(CommandParsingError::InvalidPayload)?;
+
         match cmd_type {
-            0 => Self::parse_register_cmd(bytes_buffer),
-            1 => Self::parse_connect_cmd(bytes_buffer),
-            2 => Self::parse_request_contact_cmd(bytes_buffer),
+            0 => Self::parse_payload(&mut payload, Command::Register),
+            1 => Self::parse_payload(&mut payload, Command::Connect),
+            2 => Self::parse_payload(&mut payload, Command::RequestContact),
+            3 => Ok(Command::Ping),
+            4 => Ok(Command::Pong),
+            5 => Self::parse_payload(&mut payload, Command::Handshake),
+            6 => Self::parse_payload(&mut payload, Command::SendMessage),
+            7 => Self::parse_payload(&mut payload, Command::EnableSecondFactor),
+            8 => Self::parse_payload(&mut payload, Command::ConfirmSecondFactor),
+            9 => Self::parse_payload(&mut payload, Command::SecondFactor),
+            10 => Self::parse_payload(&mut payload, Command::ChangePassword),
+            11 => Self::parse_payload(&mut payload, Command::ChangeNickname),
+            12 => Self::parse_payload(&mut payload, Command::DeleteAccount),
+            13 => Self::parse_payload(&mut payload, Command::SearchUsers),
+            14 => Ok(Command::RequestPrivacySettings),
+            15 => Self::parse_payload(&mut payload, Command::UpdatePrivacySettings),
+            16 => Self::parse_payload(&mut payload, Command::Block),
+            17 => Self::parse_payload(&mut payload, Command::Unblock),
+            18 => Ok(Command::RequestBlockedUsers),
+            19 => Self::parse_payload(&mut payload, Command::ForwardedPeer),
 
             _ => Err(CommandParsingError::UnknownCommand)
         }
     }
 
-    fn parse_register_cmd(bytes_buffer: &mut BytesBuffer) -> Result<Self, CommandParsingError> {
-        bytes_buffer
-            .read_all()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .and_then(|data_str| UserRegisterData::new(&data_str))
-            .map(|register_data| Command::Register(register_data))
-            .ok_or(CommandParsingError::InvalidPayload)
-    }
-
-    fn parse_connect_cmd(bytes_buffer: &mut BytesBuffer) -> Result<Self, CommandParsingError> {
-
-        bytes_buffer
-            .read_all()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .and_then(|data_str| UserConnectData::new(&data_str))
-            .map(|connect_data| Command::Connect(connect_data))
+    fn parse_payload<T: Decode>(payload: &mut BytesBuffer, command: impl FnOnce(T) -> Self) -> Result<Self, CommandParsingError> {
+        T::decode(payload)
+            .map(command)
             .ok_or(CommandParsingError::InvalidPayload)
     }
 
-    fn parse_request_contact_cmd(bytes_buffer: &mut BytesBuffer) -> Result<Self, CommandParsingError> {
-        bytes_buffer
-            .read_all()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .map(|username| username.to_string())
-            .map(|username| Command::RequestContact(username))
-            .ok_or(CommandParsingError::InvalidPayload)
+    fn cmd_type(&self) -> u8 {
+        match self {
+            Command::Register(_) => 0,
+            Command::Connect(_) => 1,
+            Command::RequestContact(_) => 2,
+            Command::Ping => 3,
+            Command::Pong => 4,
+            Command::Handshake(_) => 5,
+            Command::SendMessage(_) => 6,
+            Command::EnableSecondFactor(_) => 7,
+            Command::ConfirmSecondFactor(_) => 8,
+            Command::SecondFactor(_) => 9,
+            Command::ChangePassword(_) => 10,
+            Command::ChangeNickname(_) => 11,
+            Command::DeleteAccount(_) => 12,
+            Command::SearchUsers(_) => 13,
+            Command::RequestPrivacySettings => 14,
+            Command::UpdatePrivacySettings(_) => 15,
+            Command::Block(_) => 16,
+            Command::Unblock(_) => 17,
+            Command::RequestBlockedUsers => 18,
+            Command::ForwardedPeer(_) => 19,
+        }
     }
 
-
     // Serializing
     pub fn to_bytes(&self, bytes_buffer: &mut BytesBuffer) {
-        
-        match self {
-            Command::Register(user_register_data) => {
-                bytes_buffer.write_bytes(&[0]);
-                
-                utils::write_string_to_bytes_buffer(bytes_buffer, &user_register_data.to_string());
-            }
-            Command::Connect(user_connect_data) => {
-                bytes_buffer.write_bytes(&[1]);
+        self.cmd_type().encode(bytes_buffer);
 
-                utils::write_string_to_bytes_buffer(bytes_buffer, &user_connect_data.to_string());
-            },
-            Command::RequestContact(username) => {
-                bytes_buffer.write_bytes(&[2]);
-                utils::write_string_to_bytes_buffer(bytes_buffer, &username);
-            }
+        match self {
+            Command::Register(user_register_data) => write_length_prefixed(bytes_buffer, user_register_data),
+            Command::Connect(user_connect_data) => write_length_prefixed(bytes_buffer, user_connect_data),
+            Command::RequestContact(username) => write_length_prefixed(bytes_buffer, username),
+            Command::Handshake(features) => write_length_prefixed(bytes_buffer, features),
+            Command::SendMessage(message) => write_length_prefixed(bytes_buffer, message),
+            Command::ConfirmSecondFactor(code) | Command::SecondFactor(code) => write_length_prefixed(bytes_buffer, code),
+            Command::ChangePassword(password_change) => write_length_prefixed(bytes_buffer, password_change),
+            Command::ChangeNickname(nickname) => write_length_prefixed(bytes_buffer, nickname),
+            Command::EnableSecondFactor(password) | Command::DeleteAccount(password) => write_length_prefixed(bytes_buffer, password),
+            Command::SearchUsers(search) => write_length_prefixed(bytes_buffer, search),
+            Command::UpdatePrivacySettings(settings) => write_length_prefixed(bytes_buffer, settings),
+            Command::Block(user_id) | Command::Unblock(user_id) => write_length_prefixed(bytes_buffer, user_id),
+            Command::ForwardedPeer(address) => write_length_prefixed(bytes_buffer, address),
+            Command::Ping | Command::Pong | Command::RequestPrivacySettings | Command::RequestBlockedUsers =>
+                write_length_prefixed(bytes_buffer, &()),
         }
     }
-
-
 }
 
 #[derive(Debug)]
 pub enum CommandParsingError {
     UnknownCommand,
     InvalidPayload
-}
\ No newline at end of file
+}
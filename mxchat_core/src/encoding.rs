@@ -0,0 +1,310 @@
+use std::time::Duration;
+
+use crate::{auth::UserId, io::BytesBuffer, utils::{bytes_as_u16, bytes_as_u32, u16_as_bytes, u32_as_bytes}};
+
+pub use mxchat_derive::{Decode, Encode};
+
+/// Binary serialization of the protocol types. Integers are written big endian,
+/// strings and sequences are prefixed with their length as a `u32`.
+pub trait Encode {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer);
+}
+
+/// Reverse of [`Encode`], returns `None` when the buffer is too short or holds invalid data.
+pub trait Decode: Sized {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self>;
+}
+
+/// Writes the encoded `value` prefixed with its length, so that the reader can
+/// take it off the stream without knowing its type.
+pub fn write_length_prefixed(bytes_buffer: &mut BytesBuffer, value: &impl Encode) {
+    let mut value_buffer = BytesBuffer::empty();
+    value.encode(&mut value_buffer);
+
+    let value_bytes = value_buffer.read_all().unwrap_or_default();
+    bytes_buffer.write_bytes(&u32_as_bytes(value_bytes.len() as u32));
+    bytes_buffer.write_bytes(value_bytes);
+}
+
+/// Reads a value written by [`write_length_prefixed`].
+pub fn read_length_prefixed<T: Decode>(bytes_buffer: &mut BytesBuffer) -> Option<T> {
+    T::decode(&mut read_length_prefixed_bytes(bytes_buffer)?)
+}
+
+/// Takes the bytes of a value written by [`write_length_prefixed`] without decoding them.
+pub fn read_length_prefixed_bytes(bytes_buffer: &mut BytesBuffer) -> Option<BytesBuffer> {
+    let length = u32::decode(bytes_buffer)? as usize;
+
+    bytes_buffer
+        .read_bytes(length)
+        .map(|bytes| BytesBuffer::from_bytes(bytes.to_vec()))
+}
+
+fn read_array<const N: usize>(bytes_buffer: &mut BytesBuffer) -> Option<[u8; N]> {
+    bytes_buffer
+        .read_bytes(N)?
+        .try_into()
+        .ok()
+}
+
+impl Encode for () {
+    fn encode(&self, _bytes_buffer: &mut BytesBuffer) {}
+}
+
+impl Decode for () {
+    fn decode(_bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        bytes_buffer.write_bytes(&[*self]);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        read_array::<1>(bytes_buffer).map(|[byte]| byte)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        u8::from(*self).encode(bytes_buffer);
+    }
+}
+
+impl Decode for bool {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        match u8::decode(bytes_buffer)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None
+        }
+    }
+}
+
+impl Encode for u16 {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        bytes_buffer.write_bytes(&u16_as_bytes(*self));
+    }
+}
+
+impl Decode for u16 {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        read_array(bytes_buffer).map(|bytes| bytes_as_u16(&bytes))
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        bytes_buffer.write_bytes(&u32_as_bytes(*self));
+    }
+}
+
+impl Decode for u32 {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        read_array(bytes_buffer).map(|bytes| bytes_as_u32(&bytes))
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        bytes_buffer.write_bytes(&self.to_be_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        read_array(bytes_buffer).map(u64::from_be_bytes)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        self.as_str().encode(bytes_buffer);
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        (self.len() as u32).encode(bytes_buffer);
+        bytes_buffer.write_bytes(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        let length = u32::decode(bytes_buffer)? as usize;
+        let bytes = bytes_buffer.read_bytes(length)?;
+
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        self.is_some().encode(bytes_buffer);
+        if let Some(value) = self {
+            value.encode(bytes_buffer);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        if bool::decode(bytes_buffer)? {
+            T::decode(bytes_buffer).map(Some)
+        }
+        else {
+            Some(None)
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        (self.len() as u32).encode(bytes_buffer);
+        for value in self {
+            value.encode(bytes_buffer);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        let count = u32::decode(bytes_buffer)?;
+
+        // the count comes from the peer, so the vector grows as values are actually read
+        let mut values = Vec::new();
+        for _ in 0..count {
+            values.push(T::decode(bytes_buffer)?);
+        }
+
+        Some(values)
+    }
+}
+
+/// Durations are sent as milliseconds.
+impl Encode for Duration {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        u64::try_from(self.as_millis())
+            .unwrap_or(u64::MAX)
+            .encode(bytes_buffer);
+    }
+}
+
+impl Decode for Duration {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        u64::decode(bytes_buffer).map(Duration::from_millis)
+    }
+}
+
+impl Encode for UserId {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        bytes_buffer.write_bytes(&self.to_bytes());
+    }
+}
+
+impl Decode for UserId {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        read_array(bytes_buffer).map(|bytes| UserId::from_bytes(&bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::{auth::{PasswordChange, User, UserConnectData, UserRegisterData}, command::Command, error::{ErrorCode, ErrorInfo}, messaging::{Contact, UserSearch}};
+
+    use super::*;
+
+    fn round_trip<T: Encode + Decode>(value: &T) -> Option<T> {
+        let mut bytes_buffer = BytesBuffer::empty();
+        value.encode(&mut bytes_buffer);
+
+        let decoded = T::decode(&mut bytes_buffer);
+        assert_eq!(bytes_buffer.remaining(), 0);
+
+        decoded
+    }
+
+    fn command_round_trip(command: &Command) -> Command {
+        let mut bytes_buffer = BytesBuffer::empty();
+        command.to_bytes(&mut bytes_buffer);
+
+        Command::from_bytes(&mut bytes_buffer).unwrap()
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_and_invalid_data() {
+        let mut bytes_buffer = BytesBuffer::from_bytes(vec![0, 0, 0, 4, b'a']);
+        assert_eq!(String::decode(&mut bytes_buffer), None);
+
+        let mut bytes_buffer = BytesBuffer::from_bytes(vec![0, 0, 0, 1, 0xFF]);
+        assert_eq!(String::decode(&mut bytes_buffer), None);
+
+        let mut bytes_buffer = BytesBuffer::from_bytes(vec![0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(Vec::<u32>::decode(&mut bytes_buffer), None);
+    }
+
+    proptest! {
+        #[test]
+        fn test_string_round_trip(value in any::<String>()) {
+            prop_assert_eq!(round_trip(&value), Some(value));
+        }
+
+        #[test]
+        fn test_user_round_trip(id in any::<u32>(), username in any::<String>(), nickname in any::<String>(), bot in any::<bool>()) {
+            let user = User { id: UserId::new(id), username, nickname, bot };
+            prop_assert_eq!(round_trip(&user), Some(user));
+        }
+
+        #[test]
+        fn test_contact_round_trip(id in any::<u32>(), nickname in any::<String>(), bot in any::<bool>()) {
+            let contact = Contact { id: UserId::new(id), nickname, bot };
+            prop_assert_eq!(round_trip(&contact), Some(contact));
+        }
+
+        #[test]
+        fn test_error_info_round_trip(code in any::<u16>(), message in any::<String>(), detail in any::<Option<String>>(), retry_after_millis in any::<Option<u64>>()) {
+            let retry_after = retry_after_millis.map(Duration::from_millis);
+            let error = ErrorInfo { code: ErrorCode::from_code(code), message, detail, retry_after };
+            prop_assert_eq!(round_trip(&error), Some(error));
+        }
+
+        #[test]
+        fn test_command_round_trip(id in any::<u32>(), username in any::<String>(), nickname in any::<String>(), password in any::<String>()) {
+            let register = Command::Register(UserRegisterData {
+                username: username.clone(),
+                nickname: nickname.clone(),
+                password: password.clone(),
+                bot: false,
+            });
+            prop_assert_eq!(command_round_trip(&register), register);
+
+            let change_password = Command::ChangePassword(PasswordChange { old: password.clone(), new: nickname });
+            prop_assert_eq!(command_round_trip(&change_password), change_password);
+
+            let connect = Command::Connect(UserConnectData { username: username.clone(), password: password.clone() });
+            prop_assert_eq!(command_round_trip(&connect), connect);
+
+            let second_factor = Command::SecondFactor(password);
+            prop_assert_eq!(command_round_trip(&second_factor), second_factor);
+
+            let block = Command::Block(UserId::new(id));
+            prop_assert_eq!(command_round_trip(&block), block);
+
+            let search_users = Command::SearchUsers(UserSearch { query: username.clone(), limit: 10 });
+            prop_assert_eq!(command_round_trip(&search_users), search_users);
+
+            let forwarded_peer = Command::ForwardedPeer(username.clone());
+            prop_assert_eq!(command_round_trip(&forwarded_peer), forwarded_peer);
+
+            let request_contact = Command::RequestContact(username);
+            prop_assert_eq!(command_round_trip(&request_contact), request_contact);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use crate::{encoding::{Decode, Encode}, io::BytesBuffer};
+
+/// Machine readable reason of a [`Notification::Error`](crate::notification::Notification::Error).
+///
+/// The numeric codes are part of the protocol and never change meaning once released,
+/// new failures get new codes:
+///
+/// | Code | Variant                 | Meaning                                                |
+/// |------|-------------------------|--------------------------------------------------------|
+/// | 100  | `UnknownCommand`        | the command type is not known by the server            |
+/// | 101  | `InvalidPayload`        | the command payload could not be decoded               |
+/// | 102  | `FrameTooLarge`         | the frame exceeds the server limit, connection closed  |
+/// | 103  | `RateLimited`           | too many commands, `retry_after` says when to retry    |
+/// | 104  | `IdleTimeout`           | heartbeats went unanswered, connection closed          |
+/// | 105  | `Internal`              | the server failed to process the command               |
+/// | 200  | `UserAlreadyExists`     | the username is already registered                     |
+/// | 201  | `UserAlreadyConnected`  | the connection is already logged in                    |
+/// | 202  | `UserNotFound`          | no user is registered with this username               |
+/// | 203  | `PasswordIncorrect`     | the password does not match the username               |
+/// | 204  | `NotAuthenticated`      | the command needs the connection to be logged in       |
+/// | 205  | `RegistrationDisabled`  | accounts come from a directory, registering is refused |
+/// | 206  | `SecondFactorIncorrect` | the two-factor code is wrong, expired or already used  |
+/// | 207  | `InvalidAccountData`    | username, nickname or password refused, see message    |
+/// | 300  | `UserOffline`           | the recipient of a message is not connected            |
+///
+/// Codes unknown to this version decode as `Unrecognized`, so older clients can still show the message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    UnknownCommand,
+    InvalidPayload,
+    FrameTooLarge,
+    RateLimited,
+    IdleTimeout,
+    Internal,
+
+    UserAlreadyExists,
+    UserAlreadyConnected,
+    UserNotFound,
+    PasswordIncorrect,
+    NotAuthenticated,
+    RegistrationDisabled,
+    SecondFactorIncorrect,
+    InvalidAccountData,
+
+    UserOffline,
+
+    Unrecognized(u16),
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u16 {
+        match self {
+            ErrorCode::UnknownCommand => 100,
+            ErrorCode::InvalidPayload => 101,
+            ErrorCode::FrameTooLarge => 102,
+            ErrorCode::RateLimited => 103,
+            ErrorCode::IdleTimeout => 104,
+            ErrorCode::Internal => 105,
+            ErrorCode::UserAlreadyExists => 200,
+            ErrorCode::UserAlreadyConnected => 201,
+            ErrorCode::UserNotFound => 202,
+            ErrorCode::PasswordIncorrect => 203,
+            ErrorCode::NotAuthenticated => 204,
+            ErrorCode::RegistrationDisabled => 205,
+            ErrorCode::SecondFactorIncorrect => 206,
+            ErrorCode::InvalidAccountData => 207,
+            ErrorCode::UserOffline => 300,
+            ErrorCode::Unrecognized(code) => code,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            100 => ErrorCode::UnknownCommand,
+            101 => ErrorCode::InvalidPayload,
+            102 => ErrorCode::FrameTooLarge,
+            103 => ErrorCode::RateLimited,
+            104 => ErrorCode::IdleTimeout,
+            105 => ErrorCode::Internal,
+            200 => ErrorCode::UserAlreadyExists,
+            201 => ErrorCode::UserAlreadyConnected,
+            202 => ErrorCode::UserNotFound,
+            203 => ErrorCode::PasswordIncorrect,
+            204 => ErrorCode::NotAuthenticated,
+            205 => ErrorCode::RegistrationDisabled,
+            206 => ErrorCode::SecondFactorIncorrect,
+            207 => ErrorCode::InvalidAccountData,
+            300 => ErrorCode::UserOffline,
+            code => ErrorCode::Unrecognized(code),
+        }
+    }
+
+    pub fn default_message(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownCommand => "Unknown command",
+            ErrorCode::InvalidPayload => "Invalid command data",
+            ErrorCode::FrameTooLarge => "Request is too large",
+            ErrorCode::RateLimited => "Too many requests, retry later",
+            ErrorCode::IdleTimeout => "Connection closed after being idle",
+            ErrorCode::Internal => "Internal server error",
+            ErrorCode::UserAlreadyExists => "User already registered",
+            ErrorCode::UserAlreadyConnected => "User is already connected",
+            ErrorCode::UserNotFound => "User not found",
+            ErrorCode::PasswordIncorrect => "Password is incorrect",
+            ErrorCode::NotAuthenticated => "You need to be logged in",
+            ErrorCode::RegistrationDisabled => "Registration is disabled on this server",
+            ErrorCode::SecondFactorIncorrect => "The verification code is incorrect",
+            ErrorCode::InvalidAccountData => "The account data is invalid",
+            ErrorCode::UserOffline => "User is offline",
+            ErrorCode::Unrecognized(_) => "Unexpected error",
+        }
+    }
+}
+
+impl Encode for ErrorCode {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        self.code().encode(bytes_buffer);
+    }
+}
+
+impl Decode for ErrorCode {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        u16::decode(bytes_buffer).map(ErrorCode::from_code)
+    }
+}
+
+/// Failure reported by the server. `message` is meant to be shown to the user as is,
+/// `detail` carries extra context for logs.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ErrorInfo {
+    pub code: ErrorCode,
+    pub message: String,
+    pub detail: Option<String>,
+    /// Delay before the command can succeed, set with `ErrorCode::RateLimited`.
+    pub retry_after: Option<Duration>,
+}
+
+impl ErrorInfo {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            detail: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+}
+
+impl From<ErrorCode> for ErrorInfo {
+    fn from(value: ErrorCode) -> Self {
+        Self::new(value, value.default_message())
+    }
+}
@@ -1,3 +1,18 @@
+use std::io::{self, Read, Write};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::{encoding::{read_length_prefixed_bytes, Decode, Encode}, request::RequestId, utils::{bytes_as_u32, u32_as_bytes}};
+
+/// Largest frame accepted from a peer unless configured otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Payloads smaller than this are sent as is, compressing them would not pay off.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Set on the type byte of the frames whose payload is deflate compressed.
+const COMPRESSED_FLAG: u8 = 0x80;
+
 pub struct BytesBuffer {
     bytes: Vec<u8>,
     cursor: usize,
@@ -19,8 +34,12 @@ impl BytesBuffer {
         self.bytes.extend_from_slice(new_bytes);
     }
 
+    pub fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.cursor)
+    }
+
     pub fn read_bytes(&mut self, bytes_count: usize) -> Option<&[u8]> {
-        if self.bytes.len() - self.cursor < bytes_count {
+        if self.remaining() < bytes_count {
             None
         }
         else {
@@ -32,10 +51,141 @@ impl BytesBuffer {
     }
 
     pub fn read_all(&mut self) -> Option<&[u8]> {
-        self.read_bytes(self.bytes.len() - self.cursor)
+        self.read_bytes(self.remaining())
     }
 }
 
+/// Optional protocol features, advertised by both sides in the handshake.
+/// A feature is used only when both peers support it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Encode, Decode)]
+pub struct ProtocolFeatures {
+    pub compression: bool,
+}
+
+impl ProtocolFeatures {
+    /// Features implemented by this version of the protocol.
+    pub fn supported() -> Self {
+        Self {
+            compression: true
+        }
+    }
+
+    pub fn negotiate(self, peer_features: Self) -> Self {
+        Self {
+            compression: self.compression && peer_features.compression
+        }
+    }
+}
+
+pub enum FrameError {
+    /// Nothing was received before the read timeout of the stream.
+    Idle,
+    TooLarge(Option<RequestId>, usize),
+    Io(io::Error),
+}
+
+impl From<io::Error> for FrameError {
+    fn from(value: io::Error) -> Self {
+        FrameError::Io(value)
+    }
+}
+
+impl From<FrameError> for io::Error {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::Idle => io::ErrorKind::TimedOut.into(),
+            FrameError::TooLarge(_, frame_size) => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {frame_size} bytes is too large")
+            ),
+            FrameError::Io(e) => e
+        }
+    }
+}
+
+/// A frame is the request id followed by a message, which is its type byte, the length
+/// of its payload and the payload itself. The payload of large messages is compressed
+/// when `compression` is set, which is flagged on the type byte.
+pub fn write_frame(writer: &mut impl Write, request_id: Option<RequestId>, message: &mut BytesBuffer, compression: bool) -> io::Result<()> {
+    let invalid_message = || io::Error::from(io::ErrorKind::InvalidInput);
+
+    let mut message_type = u8::decode(message).ok_or_else(invalid_message)?;
+    let payload = read_length_prefixed_bytes(message).ok_or_else(invalid_message)?;
+    let mut payload = payload.bytes;
+
+    if message_type & COMPRESSED_FLAG != 0 {
+        return Err(invalid_message());
+    }
+
+    if compression && payload.len() >= COMPRESSION_THRESHOLD {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&payload)?;
+        let compressed_payload = encoder.finish()?;
+
+        if compressed_payload.len() < payload.len() {
+            payload = compressed_payload;
+            message_type |= COMPRESSED_FLAG;
+        }
+    }
+
+    let mut frame = BytesBuffer::empty();
+    frame.write_bytes(&RequestId::to_bytes(request_id));
+    message_type.encode(&mut frame);
+    frame.write_bytes(&u32_as_bytes(payload.len() as u32));
+    frame.write_bytes(&payload);
+
+    writer.write_all(&frame.bytes)?;
+    writer.flush()
+}
+
+/// Reads a frame written by [`write_frame`], returns its request id and its message
+/// with the payload decompressed. Payloads larger than `max_frame_size`, before or
+/// after decompression, are rejected.
+pub fn read_frame(reader: &mut impl Read, max_frame_size: usize) -> Result<(Option<RequestId>, BytesBuffer), FrameError> {
+    let mut request_id_bytes = [0u8; RequestId::size()];
+    match reader.read(&mut request_id_bytes[..1]) {
+        Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        Ok(_) => (),
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+            return Err(FrameError::Idle),
+        Err(e) => return Err(e.into())
+    }
+    reader.read_exact(&mut request_id_bytes[1..])?;
+
+    let request_id = RequestId::from_bytes(&request_id_bytes);
+
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+
+    let message_type = header[0];
+    let payload_length = bytes_as_u32(&[header[1], header[2], header[3], header[4]]) as usize;
+    if payload_length > max_frame_size {
+        return Err(FrameError::TooLarge(request_id, payload_length));
+    }
+
+    let mut payload = vec![0u8; payload_length];
+    reader.read_exact(&mut payload)?;
+
+    if message_type & COMPRESSED_FLAG != 0 {
+        let mut decompressed_payload = Vec::new();
+        DeflateDecoder::new(payload.as_slice())
+            .take(max_frame_size as u64 + 1)
+            .read_to_end(&mut decompressed_payload)?;
+
+        if decompressed_payload.len() > max_frame_size {
+            return Err(FrameError::TooLarge(request_id, decompressed_payload.len()));
+        }
+
+        payload = decompressed_payload;
+    }
+
+    let mut message = BytesBuffer::empty();
+    (message_type & !COMPRESSED_FLAG).encode(&mut message);
+    message.write_bytes(&u32_as_bytes(payload.len() as u32));
+    message.write_bytes(&payload);
+
+    Ok((request_id, message))
+}
 
 #[cfg(test)]
 mod tests {
@@ -58,4 +208,66 @@ mod tests {
         let b = bytes_buffer.read_bytes(5);
         println!("Should be None {b:?}");
     }
+
+    #[test]
+    fn testing_bytes_buffer_bounds() {
+        let mut bytes_buffer = BytesBuffer::from_bytes(vec![1, 2, 3]);
+
+        assert!(bytes_buffer.read_bytes(usize::MAX).is_none());
+        assert!(bytes_buffer.read_bytes(4).is_none());
+        assert_eq!(bytes_buffer.read_bytes(3), Some(&[1u8, 2, 3][..]));
+        assert_eq!(bytes_buffer.remaining(), 0);
+        assert!(bytes_buffer.read_bytes(1).is_none());
+        assert_eq!(bytes_buffer.read_all(), Some(&[][..]));
+    }
+
+    fn frame_round_trip(payload: &[u8], compression: bool) -> (usize, Vec<u8>) {
+        let mut message = BytesBuffer::empty();
+        message.write_bytes(&[3]);
+        message.write_bytes(&u32_as_bytes(payload.len() as u32));
+        message.write_bytes(payload);
+
+        let mut stream = Vec::new();
+        write_frame(&mut stream, RequestId::new(9), &mut message, compression).unwrap();
+        let frame_size = stream.len();
+
+        let Ok((request_id, mut message)) = read_frame(&mut stream.as_slice(), DEFAULT_MAX_FRAME_SIZE) else {
+            panic!("could not read the frame back");
+        };
+        assert_eq!(request_id, RequestId::new(9));
+
+        (frame_size, message.read_all().unwrap().to_vec())
+    }
+
+    #[test]
+    fn test_frame_compression() {
+        let payload = "contact;".repeat(1000).into_bytes();
+
+        let (plain_size, plain_message) = frame_round_trip(&payload, false);
+        let (compressed_size, compressed_message) = frame_round_trip(&payload, true);
+
+        assert_eq!(plain_message, compressed_message);
+        assert_eq!(&plain_message[5..], payload.as_slice());
+        assert!(compressed_size < plain_size / 10);
+
+        let (small_size, _) = frame_round_trip(b"small", true);
+        assert_eq!(small_size, 4 + 5 + 5);
+    }
+
+    #[test]
+    fn test_decompressed_frame_size_is_bounded() {
+        let mut message = BytesBuffer::empty();
+        message.write_bytes(&[3]);
+        message.write_bytes(&u32_as_bytes(1024 * 1024));
+        message.write_bytes(&vec![0; 1024 * 1024]);
+
+        let mut stream = Vec::new();
+        write_frame(&mut stream, None, &mut message, true).unwrap();
+        assert!(stream.len() < DEFAULT_MAX_FRAME_SIZE);
+
+        assert!(matches!(
+            read_frame(&mut stream.as_slice(), DEFAULT_MAX_FRAME_SIZE),
+            Err(FrameError::TooLarge(None, _))
+        ));
+    }
 }
\ No newline at end of file
@@ -1,6 +1,13 @@
+// lets the derive macros refer to `::mxchat_core` from inside this crate
+extern crate self as mxchat_core;
+
 pub mod command;
 pub mod notification;
 pub mod auth;
 pub mod io;
 pub mod utils;
-pub mod messaging;
\ No newline at end of file
+pub mod messaging;
+pub mod request;
+pub mod encoding;
+pub mod error;
+pub mod validation;
\ No newline at end of file
@@ -1,35 +1,38 @@
-use crate::{auth::UserId, io::BytesBuffer};
+use crate::{auth::{User, UserId}, encoding::{Decode, Encode}};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct Contact {
     pub id: UserId,
-    pub nickname: String
+    pub nickname: String,
+    pub bot: bool,
 }
 
-impl Contact {
-    pub fn from_bytes(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
-        let user_id_bytes = bytes_buffer.read_bytes(UserId::size())?;
-        let id = UserId::from_bytes(&[
-            user_id_bytes[0],
-            user_id_bytes[1],
-            user_id_bytes[2],
-            user_id_bytes[3],
-        ]);
-
-        let nickname = String::from_utf8_lossy(bytes_buffer.read_all()?).to_string();
-
-        Some(Self {
-            id,
-            nickname
-        })
-    }
+/// Direct message sent by a user to `recipient`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct OutgoingMessage {
+    pub recipient: UserId,
+    pub content: String,
+}
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
+/// Direct message delivered to its recipient, along with the public data of its sender.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct IncomingMessage {
+    pub sender: User,
+    pub content: String,
+}
 
-        result.extend_from_slice(&self.id.to_bytes());
-        result.extend_from_slice(self.nickname.as_bytes());
+/// Payload of `Command::SearchUsers`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct UserSearch {
+    /// Start or approximation of a username or of a word of a nickname.
+    pub query: String,
+    /// The server also caps the number of results.
+    pub limit: u16,
+}
 
-        result
-    }
-}
\ No newline at end of file
+/// Account found by `Command::SearchUsers`, along with the username `Command::RequestContact` expects.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SearchResult {
+    pub username: String,
+    pub contact: Contact,
+}
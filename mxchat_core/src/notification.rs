@@ -1,59 +1,224 @@
-#[repr(u8)]
-#[derive(Debug, Copy, Clone)]
-pub enum Notification {
-    UnknownCommand,
-    InvalidPayload,
+use crate::{auth::{PrivacySettings, SecondFactorEnrollment, User, UserId}, error::{ErrorCode, ErrorInfo}, encoding::{read_length_prefixed_bytes, write_length_prefixed, Decode, Encode}, io::{BytesBuffer, ProtocolFeatures}, messaging::{Contact, IncomingMessage, SearchResult}};
 
+/// Message sent by the server. On the wire it is its type byte followed by the
+/// length of its payload and the payload itself, which is empty for most of them.
+///
+/// Every failure is reported through `Error`, answering the failed command
+/// through the request id of the frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
     // user notifs
     UserRegistred,
-    UserAlreadyExist,
+    UserConnected(User),
+
+    ReceiveContactInfo(Contact),
+
+    Error(ErrorInfo),
+
+    Ping,
+    Pong,
 
-    UserConnected,
-    UserIsAlreadyConnected,
-    UserNotFound,
-    UserPasswordIncorrect,
+    /// Answer to `Command::Handshake` with the features of the server.
+    Handshake(ProtocolFeatures),
 
-    ReceiveContactInfo,
+    /// Pushed to the recipient of a message, without request id.
+    MessageReceived(IncomingMessage),
+    /// Answer to `Command::SendMessage` once the message is delivered.
+    MessageSent,
+
+    /// Answer to `Command::Connect` for accounts enrolled in two-factor authentication,
+    /// the login finishes with `Command::SecondFactor`.
+    SecondFactorRequired,
+    SecondFactorEnrollment(SecondFactorEnrollment),
+    /// Answer to `Command::ConfirmSecondFactor` with the recovery codes, each of them
+    /// replacing a code of the authenticator app once.
+    SecondFactorEnabled(Vec<String>),
+
+    /// Answer to `Command::ChangePassword` and `Command::ChangeNickname` with the user as updated.
+    AccountUpdated(User),
+    /// Answer to `Command::DeleteAccount`.
+    AccountDeleted,
+    /// Pushed to the users having the contact, without request id, when its nickname changes.
+    ContactUpdated(Contact),
+    /// Pushed to the users having the contact, without request id, once its account is deleted.
+    ContactDeleted(UserId),
+
+    /// Answer to `Command::SearchUsers`, the best matches first.
+    SearchResults(Vec<SearchResult>),
+    PrivacySettings(PrivacySettings),
+    /// The users blocked by the logged in account.
+    BlockedUsers(Vec<Contact>),
 }
 
 impl Notification {
-    pub fn has_payload(self) -> bool {
+    fn notif_type(&self) -> u8 {
+        match self {
+            Notification::UserRegistred => 0,
+            Notification::UserConnected(_) => 1,
+            Notification::ReceiveContactInfo(_) => 2,
+            Notification::Error(_) => 3,
+            Notification::Ping => 4,
+            Notification::Pong => 5,
+            Notification::Handshake(_) => 6,
+            Notification::MessageReceived(_) => 7,
+            Notification::MessageSent => 8,
+            Notification::SecondFactorRequired => 9,
+            Notification::SecondFactorEnrollment(_) => 10,
+            Notification::SecondFactorEnabled(_) => 11,
+            Notification::AccountUpdated(_) => 12,
+            Notification::AccountDeleted => 13,
+            Notification::ContactUpdated(_) => 14,
+            Notification::ContactDeleted(_) => 15,
+            Notification::SearchResults(_) => 16,
+            Notification::PrivacySettings(_) => 17,
+            Notification::BlockedUsers(_) => 18,
+        }
+    }
+
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            Notification::Error(error) => Some(error.code),
+            _ => None
+        }
+    }
+}
+
+impl From<ErrorInfo> for Notification {
+    fn from(value: ErrorInfo) -> Self {
+        Notification::Error(value)
+    }
+}
+
+impl From<ErrorCode> for Notification {
+    fn from(value: ErrorCode) -> Self {
+        Notification::Error(value.into())
+    }
+}
+
+impl Encode for Notification {
+    fn encode(&self, bytes_buffer: &mut BytesBuffer) {
+        self.notif_type().encode(bytes_buffer);
+
         match self {
-            Notification::UnknownCommand => false,
-            Notification::InvalidPayload => false,
-            Notification::UserRegistred => false,
-            Notification::UserAlreadyExist => false,
-            Notification::UserPasswordIncorrect => false,
-            Notification::UserIsAlreadyConnected => false,
-            Notification::UserNotFound => false,
-
-            
-            Notification::UserConnected => true,
-            Notification::ReceiveContactInfo => true,
+            Notification::UserConnected(user) => write_length_prefixed(bytes_buffer, user),
+            Notification::ReceiveContactInfo(contact) => write_length_prefixed(bytes_buffer, contact),
+            Notification::Error(error) => write_length_prefixed(bytes_buffer, error),
+            Notification::Handshake(features) => write_length_prefixed(bytes_buffer, features),
+            Notification::MessageReceived(message) => write_length_prefixed(bytes_buffer, message),
+            Notification::SecondFactorEnrollment(enrollment) => write_length_prefixed(bytes_buffer, enrollment),
+            Notification::SecondFactorEnabled(recovery_codes) => write_length_prefixed(bytes_buffer, recovery_codes),
+            Notification::AccountUpdated(user) => write_length_prefixed(bytes_buffer, user),
+            Notification::ContactUpdated(contact) => write_length_prefixed(bytes_buffer, contact),
+            Notification::ContactDeleted(user_id) => write_length_prefixed(bytes_buffer, user_id),
+            Notification::SearchResults(results) => write_length_prefixed(bytes_buffer, results),
+            Notification::PrivacySettings(settings) => write_length_prefixed(bytes_buffer, settings),
+            Notification::BlockedUsers(blocked_users) => write_length_prefixed(bytes_buffer, blocked_users),
+            _ => write_length_prefixed(bytes_buffer, &()),
         }
     }
 }
 
+impl Decode for Notification {
+    fn decode(bytes_buffer: &mut BytesBuffer) -> Option<Self> {
+        let notif_type = u8::decode(bytes_buffer)?;
+        let mut payload = read_length_prefixed_bytes(bytes_buffer)?;
 
-impl TryFrom<u8> for Notification {
-    type Error = ();
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        [
-            Self::UnknownCommand,
-            Self::InvalidPayload,
-            Self::UserRegistred,
-            Self::UserAlreadyExist,
-            Self::UserConnected,
-            Self::UserIsAlreadyConnected,
-            Self::UserNotFound,
-            Self::UserPasswordIncorrect,
-            Self::ReceiveContactInfo,
-        ]
-        .iter()
-        .find(|variant| **variant as u8 == value)
-        .map(|variant| *variant)
-        .ok_or(())
+        let notification = match notif_type {
+            0 => Notification::UserRegistred,
+            1 => Notification::UserConnected(User::decode(&mut payload)?),
+            2 => Notification::ReceiveContactInfo(Contact::decode(&mut payload)?),
+            3 => Notification::Error(ErrorInfo::decode(&mut payload)?),
+            4 => Notification::Ping,
+            5 => Notification::Pong,
+            6 => Notification::Handshake(ProtocolFeatures::decode(&mut payload)?),
+            7 => Notification::MessageReceived(IncomingMessage::decode(&mut payload)?),
+            8 => Notification::MessageSent,
+            9 => Notification::SecondFactorRequired,
+            10 => Notification::SecondFactorEnrollment(SecondFactorEnrollment::decode(&mut payload)?),
+            11 => Notification::SecondFactorEnabled(Vec::decode(&mut payload)?),
+            12 => Notification::AccountUpdated(User::decode(&mut payload)?),
+            13 => Notification::AccountDeleted,
+            14 => Notification::ContactUpdated(Contact::decode(&mut payload)?),
+            15 => Notification::ContactDeleted(UserId::decode(&mut payload)?),
+            16 => Notification::SearchResults(Vec::decode(&mut payload)?),
+            17 => Notification::PrivacySettings(PrivacySettings::decode(&mut payload)?),
+            18 => Notification::BlockedUsers(Vec::decode(&mut payload)?),
+
+            _ => return None
+        };
+
+        Some(notification)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_notification_round_trip() {
+        let user = User {
+            id: UserId::new(7),
+            username: String::from("user"),
+            nickname: String::from("nick;name"),
+            bot: false,
+        };
+
+        let contact = Contact {
+            id: UserId::new(8),
+            nickname: String::from("contact"),
+            bot: true,
+        };
+
+        let notifications = [
+            Notification::UserRegistred,
+            Notification::UserConnected(user),
+            Notification::ReceiveContactInfo(contact),
+            Notification::Error(ErrorCode::UserNotFound.into()),
+            Notification::Error(ErrorInfo::new(ErrorCode::Unrecognized(999), "message").with_detail("detail")),
+            Notification::Error(ErrorInfo::from(ErrorCode::RateLimited).with_retry_after(Duration::from_millis(1500))),
+            Notification::Ping,
+            Notification::Pong,
+            Notification::Handshake(ProtocolFeatures::supported()),
+            Notification::MessageReceived(IncomingMessage {
+                sender: User { id: UserId::new(9), username: String::from("sender"), nickname: String::new(), bot: true },
+                content: String::from("hello"),
+            }),
+            Notification::MessageSent,
+            Notification::SecondFactorRequired,
+            Notification::SecondFactorEnrollment(SecondFactorEnrollment {
+                provisioning_uri: String::from("otpauth://totp/mxchat:user?secret=ABC"),
+                secret: String::from("ABC"),
+            }),
+            Notification::SecondFactorEnabled(vec![String::from("abcde-fghij")]),
+            Notification::AccountUpdated(User { id: UserId::new(7), username: String::from("user"), nickname: String::from("new nickname"), bot: false }),
+            Notification::AccountDeleted,
+            Notification::ContactUpdated(Contact { id: UserId::new(8), nickname: String::from("new nickname"), bot: false }),
+            Notification::ContactDeleted(UserId::new(8)),
+            Notification::SearchResults(vec![SearchResult {
+                username: String::from("contact"),
+                contact: Contact { id: UserId::new(8), nickname: String::from("contact"), bot: true },
+            }]),
+            Notification::PrivacySettings(PrivacySettings { discoverable: false }),
+            Notification::BlockedUsers(vec![Contact { id: UserId::new(9), nickname: String::from("blocked"), bot: false }]),
+        ];
+
+        let mut bytes_buffer = BytesBuffer::empty();
+        for notification in &notifications {
+            notification.encode(&mut bytes_buffer);
+        }
+
+        for notification in notifications {
+            assert_eq!(Notification::decode(&mut bytes_buffer), Some(notification));
+        }
+        assert_eq!(bytes_buffer.remaining(), 0);
+    }
+
+    #[test]
+    fn test_unknown_notification_is_rejected() {
+        let mut bytes_buffer = BytesBuffer::from_bytes(vec![255, 0, 0, 0, 0]);
+        assert_eq!(Notification::decode(&mut bytes_buffer), None);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{num::NonZeroU32, sync::atomic::{AtomicU32, Ordering}};
+
+use crate::utils::{bytes_as_u32, u32_as_bytes};
+
+/// Identifies a command so that the notification answering it can be matched with it.
+/// On the wire, zero marks the frames that are not tied to a request, such as the
+/// notifications pushed by the server on its own.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub struct RequestId(NonZeroU32);
+
+impl RequestId {
+
+    pub const fn size() -> usize {
+        std::mem::size_of::<u32>()
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::size()]) -> Option<Self> {
+        Self::new(bytes_as_u32(bytes))
+    }
+
+    pub fn to_bytes(request_id: Option<Self>) -> [u8; Self::size()] {
+        u32_as_bytes(request_id.map_or(0, Self::get))
+    }
+
+    pub fn new(value: u32) -> Option<Self> {
+        NonZeroU32::new(value).map(Self)
+    }
+
+    pub fn get(self) -> u32 {
+        self.0.get()
+    }
+}
+
+pub struct RequestIdGenerator {
+    current_id: AtomicU32,
+}
+
+impl RequestIdGenerator {
+    pub fn new() -> Self {
+        Self {
+            current_id: AtomicU32::new(1),
+        }
+    }
+
+    pub fn next_id(&self) -> RequestId {
+        loop {
+            let id = self.current_id.fetch_add(1, Ordering::Relaxed);
+            if let Some(request_id) = RequestId::new(id) {
+                return request_id;
+            }
+        }
+    }
+}
+
+impl Default for RequestIdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_bytes() {
+        let request_id = RequestId::new(570_234);
+
+        let bytes = RequestId::to_bytes(request_id);
+        assert_eq!(RequestId::from_bytes(&bytes), request_id);
+
+        assert_eq!(RequestId::to_bytes(None), [0; 4]);
+        assert_eq!(RequestId::from_bytes(&[0; 4]), None);
+    }
+
+    #[test]
+    fn test_generator_skips_zero() {
+        let generator = RequestIdGenerator {
+            current_id: AtomicU32::new(u32::MAX),
+        };
+
+        assert_eq!(generator.next_id().get(), u32::MAX);
+        assert_eq!(generator.next_id().get(), 1);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+use icu_normalizer::ComposingNormalizerBorrowed;
+
+use crate::{auth::UserRegisterData, error::{ErrorCode, ErrorInfo}};
+
+/// Punctuation allowed in usernames besides letters and digits.
+const USERNAME_PUNCTUATION: [char; 3] = ['_', '-', '.'];
+
+/// Characters accepted in usernames, besides [`USERNAME_PUNCTUATION`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UsernameCharset {
+    /// ASCII letters and digits, which can't be confused with each other once lowercased.
+    Ascii,
+    /// Letters and digits of any script. Lookalikes such as a Cyrillic `а` and a Latin `a`
+    /// make distinct usernames.
+    Unicode,
+}
+
+/// Rules applied to the accounts data, by the server before accepting it and by the
+/// clients before sending it. The clients use the default policy.
+#[derive(Debug, Clone)]
+pub struct ValidationPolicy {
+    pub username_charset: UsernameCharset,
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    /// Refused whatever their case, compared after normalization.
+    pub reserved_usernames: Vec<String>,
+    pub nickname_max_length: usize,
+    pub password_min_length: usize,
+    /// Number of the lowercase, uppercase, digit and other character classes a password
+    /// must mix.
+    pub password_min_classes: usize,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self {
+            username_charset: UsernameCharset::Ascii,
+            username_min_length: 3,
+            username_max_length: 32,
+            reserved_usernames: ["admin", "administrator", "root", "system", "server", "mxchat", "support", "moderator"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            nickname_max_length: 64,
+            password_min_length: 8,
+            password_min_classes: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    UsernameTooShort(usize),
+    UsernameTooLong(usize),
+    UsernameInvalidCharacter(char),
+    UsernameInvalidStart,
+    UsernameReserved,
+    NicknameEmpty,
+    NicknameTooLong(usize),
+    NicknameInvalidCharacter,
+    PasswordTooShort(usize),
+    PasswordTooWeak(usize),
+    PasswordIsUsername,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::UsernameTooShort(min) => write!(f, "The username needs at least {min} characters"),
+            ValidationError::UsernameTooLong(max) => write!(f, "The username can't be longer than {max} characters"),
+            ValidationError::UsernameInvalidCharacter(c) => write!(f, "The username can't contain '{c}'"),
+            ValidationError::UsernameInvalidStart => write!(f, "The username must start with a letter or a digit"),
+            ValidationError::UsernameReserved => write!(f, "This username is reserved"),
+            ValidationError::NicknameEmpty => write!(f, "The nickname can't be empty"),
+            ValidationError::NicknameTooLong(max) => write!(f, "The nickname can't be longer than {max} characters"),
+            ValidationError::NicknameInvalidCharacter => write!(f, "The nickname can't contain control characters"),
+            ValidationError::PasswordTooShort(min) => write!(f, "The password needs at least {min} characters"),
+            ValidationError::PasswordTooWeak(classes) => {
+                write!(f, "The password must mix {classes} of lowercase letters, uppercase letters, digits and symbols")
+            }
+            ValidationError::PasswordIsUsername => write!(f, "The password can't be the username"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for ErrorInfo {
+    fn from(value: ValidationError) -> Self {
+        ErrorInfo::new(ErrorCode::InvalidAccountData, value.to_string())
+    }
+}
+
+/// NFKC normalization, folds the compatibility forms such as fullwidth letters or
+/// ligatures into their usual characters.
+pub fn normalize(text: &str) -> String {
+    ComposingNormalizerBorrowed::new_nfkc().normalize(text).into_owned()
+}
+
+/// Key under which usernames are unique: two usernames with the same key are the same
+/// account.
+pub fn username_key(username: &str) -> String {
+    normalize(username).to_lowercase()
+}
+
+impl ValidationPolicy {
+    /// Returns the normalized username, the form to store.
+    pub fn validate_username(&self, username: &str) -> Result<String, ValidationError> {
+        let username = normalize(username);
+        let length = username.chars().count();
+
+        if length < self.username_min_length {
+            return Err(ValidationError::UsernameTooShort(self.username_min_length));
+        }
+
+        if length > self.username_max_length {
+            return Err(ValidationError::UsernameTooLong(self.username_max_length));
+        }
+
+        let is_alphanumeric = |c: char| match self.username_charset {
+            UsernameCharset::Ascii => c.is_ascii_alphanumeric(),
+            UsernameCharset::Unicode => c.is_alphanumeric(),
+        };
+
+        if let Some(c) = username.chars().find(|&c| !is_alphanumeric(c) && !USERNAME_PUNCTUATION.contains(&c)) {
+            return Err(ValidationError::UsernameInvalidCharacter(c));
+        }
+
+        if !username.starts_with(is_alphanumeric) {
+            return Err(ValidationError::UsernameInvalidStart);
+        }
+
+        let key = username.to_lowercase();
+        if self.reserved_usernames.iter().any(|reserved| username_key(reserved) == key) {
+            return Err(ValidationError::UsernameReserved);
+        }
+
+        Ok(username)
+    }
+
+    /// Returns the trimmed and normalized nickname.
+    pub fn validate_nickname(&self, nickname: &str) -> Result<String, ValidationError> {
+        let nickname = normalize(nickname.trim());
+
+        if nickname.is_empty() {
+            return Err(ValidationError::NicknameEmpty);
+        }
+
+        if nickname.chars().count() > self.nickname_max_length {
+            return Err(ValidationError::NicknameTooLong(self.nickname_max_length));
+        }
+
+        if nickname.chars().any(char::is_control) {
+            return Err(ValidationError::NicknameInvalidCharacter);
+        }
+
+        Ok(nickname)
+    }
+
+    /// Passwords are checked but kept as typed, the authenticators compare them byte for byte.
+    pub fn validate_password(&self, password: &str, username: &str) -> Result<(), ValidationError> {
+        if password.chars().count() < self.password_min_length {
+            return Err(ValidationError::PasswordTooShort(self.password_min_length));
+        }
+
+        let classes = [
+            password.chars().any(char::is_lowercase),
+            password.chars().any(char::is_uppercase),
+            password.chars().any(char::is_numeric),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ];
+
+        if classes.into_iter().filter(|&class| class).count() < self.password_min_classes {
+            return Err(ValidationError::PasswordTooWeak(self.password_min_classes));
+        }
+
+        if username_key(password) == username_key(username) {
+            return Err(ValidationError::PasswordIsUsername);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the data with its username and nickname normalized.
+    pub fn validate_register_data(&self, data: &UserRegisterData) -> Result<UserRegisterData, ValidationError> {
+        let username = self.validate_username(&data.username)?;
+        let nickname = self.validate_nickname(&data.nickname)?;
+        self.validate_password(&data.password, &username)?;
+
+        Ok(UserRegisterData {
+            username,
+            nickname,
+            password: data.password.clone(),
+            bot: data.bot,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username() {
+        let policy = ValidationPolicy::default();
+
+        assert_eq!(policy.validate_username("alice_01"), Ok(String::from("alice_01")));
+        // Fullwidth letters are folded into ASCII ones
+        assert_eq!(policy.validate_username("ａｌｉｃｅ"), Ok(String::from("alice")));
+
+        assert_eq!(policy.validate_username("al"), Err(ValidationError::UsernameTooShort(3)));
+        assert_eq!(policy.validate_username(&"a".repeat(33)), Err(ValidationError::UsernameTooLong(32)));
+        assert_eq!(policy.validate_username("alice bob"), Err(ValidationError::UsernameInvalidCharacter(' ')));
+        assert_eq!(policy.validate_username("alice;"), Err(ValidationError::UsernameInvalidCharacter(';')));
+        assert_eq!(policy.validate_username("аlice"), Err(ValidationError::UsernameInvalidCharacter('а')));
+        assert_eq!(policy.validate_username("_alice"), Err(ValidationError::UsernameInvalidStart));
+        assert_eq!(policy.validate_username("Admin"), Err(ValidationError::UsernameReserved));
+    }
+
+    #[test]
+    fn test_unicode_username() {
+        let policy = ValidationPolicy {
+            username_charset: UsernameCharset::Unicode,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.validate_username("élodie"), Ok(String::from("élodie")));
+        assert_eq!(policy.validate_username("élodie!"), Err(ValidationError::UsernameInvalidCharacter('!')));
+    }
+
+    #[test]
+    fn test_username_key() {
+        assert_eq!(username_key("Alice"), username_key("ＡＬＩＣＥ"));
+        assert_ne!(username_key("alice"), username_key("аlice"));
+    }
+
+    #[test]
+    fn test_nickname() {
+        let policy = ValidationPolicy::default();
+
+        assert_eq!(policy.validate_nickname("  Alice  "), Ok(String::from("Alice")));
+        assert_eq!(policy.validate_nickname("   "), Err(ValidationError::NicknameEmpty));
+        assert_eq!(policy.validate_nickname(&"a".repeat(65)), Err(ValidationError::NicknameTooLong(64)));
+        assert_eq!(policy.validate_nickname("Ali\u{7}ce"), Err(ValidationError::NicknameInvalidCharacter));
+    }
+
+    #[test]
+    fn test_password() {
+        let policy = ValidationPolicy::default();
+
+        assert_eq!(policy.validate_password("correct-horse", "alice"), Ok(()));
+        assert_eq!(policy.validate_password("a", "alice"), Err(ValidationError::PasswordTooShort(8)));
+        assert_eq!(policy.validate_password("abcdefgh", "alice"), Err(ValidationError::PasswordTooWeak(2)));
+        assert_eq!(policy.validate_password("Alice_2024", "alice_2024"), Err(ValidationError::PasswordIsUsername));
+    }
+}
@@ -0,0 +1,95 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index};
+
+/// Derives `mxchat_core::encoding::Encode` for a struct, encoding its fields in declaration order.
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let fields = match struct_fields(&input) {
+        Ok(fields) => fields,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let encode_fields: Vec<TokenStream2> = match fields {
+        Fields::Named(fields) => fields.named
+            .iter()
+            .map(|field| {
+                let name = &field.ident;
+                quote! { ::mxchat_core::encoding::Encode::encode(&self.#name, bytes_buffer); }
+            })
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|index| {
+                let index = Index::from(index);
+                quote! { ::mxchat_core::encoding::Encode::encode(&self.#index, bytes_buffer); }
+            })
+            .collect(),
+        Fields::Unit => vec![],
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::mxchat_core::encoding::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, bytes_buffer: &mut ::mxchat_core::io::BytesBuffer) {
+                #(#encode_fields)*
+            }
+        }
+    }
+    .into()
+}
+
+/// Derives `mxchat_core::encoding::Decode` for a struct, decoding its fields in declaration order.
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let fields = match struct_fields(&input) {
+        Ok(fields) => fields,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let decode_field = quote! { ::mxchat_core::encoding::Decode::decode(bytes_buffer)? };
+
+    let construct = match fields {
+        Fields::Named(fields) => {
+            let names = fields.named.iter().map(|field| &field.ident);
+            quote! { Self { #(#names: #decode_field),* } }
+        }
+        Fields::Unnamed(fields) => {
+            let values = (0..fields.unnamed.len()).map(|index| format_ident!("field_{index}"));
+            let bindings = values.clone();
+            quote! {{
+                #(let #bindings = #decode_field;)*
+                Self(#(#values),*)
+            }}
+        }
+        Fields::Unit => quote! { Self },
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::mxchat_core::encoding::Decode for #name #ty_generics #where_clause {
+            fn decode(bytes_buffer: &mut ::mxchat_core::io::BytesBuffer) -> Option<Self> {
+                Some(#construct)
+            }
+        }
+    }
+    .into()
+}
+
+fn struct_fields(input: &DeriveInput) -> syn::Result<&Fields> {
+    match &input.data {
+        Data::Struct(data) => Ok(&data.fields),
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            "only structs can be derived, implement the trait by hand for enums and unions"
+        )),
+    }
+}
@@ -0,0 +1,43 @@
+mod message;
+mod session;
+
+use std::{io, net::TcpListener, thread};
+
+use clap::Parser;
+
+/// Bridges IRC clients to an mxchat server. Each IRC connection logs in to mxchat with
+/// its nick as username and the password given by `PASS`. The server must list the
+/// address of the gateway in `MXCHAT_TRUSTED_GATEWAYS`, the gateway forwards it the
+/// address of each IRC client so that its rate limits apply to them one by one.
+#[derive(Parser)]
+#[command(name = "mxchat-irc")]
+struct Args {
+    /// Address the IRC clients connect to
+    #[arg(long, default_value = "127.0.0.1:6667")]
+    listen: String,
+
+    /// Address of the mxchat server
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    server: String,
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let listener = TcpListener::bind(&args.listen)?;
+
+    println!("IRC gateway listening on {}, bridging to {}", args.listen, args.server);
+
+    for socket in listener.incoming().filter_map(|socket| socket.ok()) {
+        let server = args.server.clone();
+
+        thread::spawn(move || {
+            let peer_address = socket.peer_addr().ok();
+
+            if let Err(e) = session::run_session(socket, server) {
+                println!("IRC connection with {peer_address:?} closed: {e}");
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+/// A line of the IRC protocol, `[:prefix] COMMAND param... [:trailing]` without its CRLF.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new(command: impl Into<String>, params: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            prefix: None,
+            command: command.into(),
+            params: params.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Returns `None` for empty lines. Commands are uppercased, IRC commands ignore case.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start();
+
+        let prefix = match rest.strip_prefix(':') {
+            Some(prefixed) => {
+                let (prefix, after) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+                rest = after.trim_start();
+                Some(prefix.to_string())
+            }
+            None => None
+        };
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+
+            let (param, after) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = after;
+        }
+
+        Some(Self {
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+}
+
+impl fmt::Display for IrcMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+
+        write!(f, "{}", self.command)?;
+
+        let Some((last, middle)) = self.params.split_last() else {
+            return Ok(());
+        };
+
+        for param in middle {
+            write!(f, " {param}")?;
+        }
+
+        if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+            write!(f, " :{last}")
+        }
+        else {
+            write!(f, " {last}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let message = IrcMessage::parse(":alice!alice@mxchat privmsg bob :hello  there\r\n").unwrap();
+        assert_eq!(message.prefix.as_deref(), Some("alice!alice@mxchat"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, ["bob", "hello  there"]);
+
+        let message = IrcMessage::parse("USER alice 0 * :Alice Liddell").unwrap();
+        assert_eq!(message.params, ["alice", "0", "*", "Alice Liddell"]);
+
+        assert_eq!(IrcMessage::parse("NICK  alice ").unwrap().params, ["alice"]);
+        assert_eq!(IrcMessage::parse("\r\n"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let message = IrcMessage::new("PRIVMSG", ["bob", "hello there"]).with_prefix("alice!alice@mxchat");
+        assert_eq!(message.to_string(), ":alice!alice@mxchat PRIVMSG bob :hello there");
+
+        assert_eq!(IrcMessage::new("PONG", ["mxchat"]).to_string(), "PONG mxchat");
+        assert_eq!(IrcMessage::new("CAP", ["*", "LS", ""]).to_string(), "CAP * LS :");
+    }
+}
@@ -0,0 +1,369 @@
+use std::{collections::HashMap, io::{self, BufRead, BufReader, Write}, net::{IpAddr, TcpStream}, time::Duration};
+
+use mxchat_core::{auth::{UserConnectData, UserId}, error::ErrorCode, notification::Notification};
+use mxchat_sdk::{Client, ClientError};
+
+use crate::message::IrcMessage;
+
+const SERVER_NAME: &str = "mxchat";
+
+/// How often the events of the mxchat connection are forwarded while the IRC client is silent.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const MAX_LINE_LENGTH: usize = 8192;
+
+/// Serves one IRC client until it quits or either connection is lost.
+pub fn run_session(socket: TcpStream, mxchat_address: String) -> io::Result<()> {
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let peer_ip = socket.peer_addr()?.ip();
+    let mut reader = BufReader::new(socket.try_clone()?);
+    let mut session = IrcSession::new(socket, peer_ip, mxchat_address);
+    let mut line = Vec::new();
+
+    loop {
+        // Bytes read before a timeout stay in `line` until the rest of the line arrives
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) if line.ends_with(b"\n") => {
+                let text = String::from_utf8_lossy(&line).into_owned();
+                line.clear();
+
+                let open = match IrcMessage::parse(&text) {
+                    Some(message) => session.handle_message(message)?,
+                    None => true
+                };
+                if !open {
+                    return Ok(());
+                }
+            }
+            Ok(_) => (),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+            Err(e) => return Err(e)
+        }
+
+        if line.len() > MAX_LINE_LENGTH {
+            return session.close("Line too long");
+        }
+
+        if !session.forward_events()? {
+            return Ok(());
+        }
+    }
+}
+
+/// State of an IRC connection. `NICK` and `USER` log in to mxchat with the password given
+/// by `PASS`, the nick being the mxchat username. Private messages are mxchat messages,
+/// the server has no rooms so channels are refused.
+struct IrcSession {
+    socket: TcpStream,
+    /// Forwarded to mxchat, whose rate limits apply to each IRC client rather than to the gateway.
+    peer_ip: IpAddr,
+    mxchat_address: String,
+    nick: Option<String>,
+    password: Option<String>,
+    user_received: bool,
+    client: Option<Client>,
+    contacts: HashMap<String, UserId>,
+}
+
+impl IrcSession {
+    fn new(socket: TcpStream, peer_ip: IpAddr, mxchat_address: String) -> Self {
+        Self {
+            socket,
+            peer_ip,
+            mxchat_address,
+            nick: None,
+            password: None,
+            user_received: false,
+            client: None,
+            contacts: HashMap::new(),
+        }
+    }
+
+    fn send(&mut self, message: IrcMessage) -> io::Result<()> {
+        write!(self.socket, "{message}\r\n")
+    }
+
+    /// Sends a numeric reply, addressed to the nick of the client.
+    fn reply<'a>(&mut self, numeric: &str, params: impl IntoIterator<Item = &'a str>) -> io::Result<()> {
+        let nick = self.nick.clone().unwrap_or_else(|| String::from("*"));
+
+        let mut message = IrcMessage::new(numeric, [nick]).with_prefix(SERVER_NAME);
+        message.params.extend(params.into_iter().map(String::from));
+
+        self.send(message)
+    }
+
+    fn close(&mut self, reason: &str) -> io::Result<()> {
+        self.send(IrcMessage::new("ERROR", [reason]))
+    }
+
+    /// Returns `false` once the connection must be closed.
+    fn handle_message(&mut self, message: IrcMessage) -> io::Result<bool> {
+        let logged_in = self.client.is_some();
+
+        match message.command.as_str() {
+            "CAP" => match message.param(0) {
+                Some("LS") => self.send(IrcMessage::new("CAP", ["*", "LS", ""]).with_prefix(SERVER_NAME))?,
+                Some("REQ") => {
+                    let capabilities = message.param(1).unwrap_or_default().to_string();
+                    self.send(IrcMessage::new("CAP", ["*".to_string(), "NAK".to_string(), capabilities]).with_prefix(SERVER_NAME))?
+                }
+                _ => ()
+            }
+            "PASS" if !logged_in => self.password = message.param(0).map(String::from),
+            "NICK" if !logged_in => {
+                self.nick = message.param(0).map(String::from);
+                return self.try_login();
+            }
+            "USER" if !logged_in => {
+                self.user_received = true;
+                return self.try_login();
+            }
+            "PASS" | "USER" => self.reply("462", ["You may not reregister"])?,
+            "NICK" => self.reply("484", ["Nick changes are not supported, the nick is the mxchat username"])?,
+            "PING" => {
+                let token = message.param(0).unwrap_or(SERVER_NAME).to_string();
+                self.send(IrcMessage::new("PONG", [SERVER_NAME.to_string(), token]).with_prefix(SERVER_NAME))?
+            }
+            "PONG" => (),
+            "QUIT" => {
+                self.close("Closing link")?;
+                return Ok(false);
+            }
+            _ if !logged_in => self.reply("451", ["You have not registered"])?,
+            "PRIVMSG" | "NOTICE" => {
+                let notice = message.command == "NOTICE";
+                let (Some(targets), Some(text)) = (message.param(0), message.param(1)) else {
+                    if !notice {
+                        self.reply("461", [message.command.as_str(), "Not enough parameters"])?;
+                    }
+                    return Ok(true);
+                };
+
+                for target in targets.split(',') {
+                    if !self.send_private_message(target, text, notice)? {
+                        return Ok(false);
+                    }
+                }
+            }
+            "JOIN" | "PART" => {
+                let channels = message.param(0).unwrap_or_default().to_string();
+                for channel in channels.split(',').filter(|channel| !channel.is_empty() && *channel != "0") {
+                    self.reply("403", [channel, "Rooms are not supported by this server"])?;
+                }
+            }
+            "MODE" => match message.param(0) {
+                Some(target) if Some(target) == self.nick.as_deref() => self.reply("221", ["+"])?,
+                Some(target) => {
+                    let target = target.to_string();
+                    self.reply("403", [target.as_str(), "Rooms are not supported by this server"])?
+                }
+                None => self.reply("461", ["MODE", "Not enough parameters"])?,
+            }
+            command => {
+                let command = command.to_string();
+                self.reply("421", [command.as_str(), "Unknown command"])?
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn try_login(&mut self) -> io::Result<bool> {
+        let Some(nick) = self.nick.clone() else {
+            return Ok(true);
+        };
+        if !self.user_received {
+            return Ok(true);
+        }
+
+        let Some(password) = self.password.clone() else {
+            self.reply("464", ["Password required, send PASS with your mxchat password"])?;
+            self.close("Password required")?;
+            return Ok(false);
+        };
+
+        let result = Client::connect_forwarded(&self.mxchat_address, self.peer_ip).and_then(|client| {
+            let user = client.login(UserConnectData {
+                username: nick,
+                password,
+            })?;
+
+            Ok((client, user))
+        });
+
+        let (client, user) = match result {
+            Ok(logged_in) => logged_in,
+            Err(ClientError::Server(error)) if matches!(error.code, ErrorCode::PasswordIncorrect | ErrorCode::UserNotFound) => {
+                self.reply("464", [error.message.as_str()])?;
+                self.close(&error.message)?;
+                return Ok(false);
+            }
+            Err(e) => {
+                self.close(&e.to_string())?;
+                return Ok(false);
+            }
+        };
+
+        self.client = Some(client);
+
+        let welcome = format!("Welcome to mxchat, {}", user.nickname);
+        self.reply("001", [welcome.as_str()])?;
+        self.reply("002", ["Your host is mxchat, bridged over IRC"])?;
+        self.reply("422", ["MOTD File is missing"])?;
+
+        Ok(true)
+    }
+
+    /// Returns `false` when the mxchat connection is lost. Notices are not answered
+    /// with errors, as IRC requires.
+    fn send_private_message(&mut self, target: &str, text: &str, notice: bool) -> io::Result<bool> {
+        if target.starts_with(['#', '&']) {
+            if !notice {
+                self.reply("403", [target, "Rooms are not supported by this server"])?;
+            }
+            return Ok(true);
+        }
+
+        let Some(client) = &self.client else {
+            return Ok(true);
+        };
+
+        let recipient = match self.contacts.get(target) {
+            Some(user_id) => Ok(*user_id),
+            None => client.add_contact(target).map(|contact| contact.id)
+        };
+
+        let result = recipient.and_then(|user_id| {
+            self.contacts.insert(target.to_string(), user_id);
+            client.send_message(user_id, text)
+        });
+
+        match result {
+            Ok(()) => (),
+            Err(ClientError::Server(error)) => {
+                // Ids do not survive a restart of the server, the contact is looked up again next time
+                self.contacts.remove(target);
+                if !notice {
+                    self.reply("401", [target, error.message.as_str()])?;
+                }
+            }
+            Err(ClientError::Disconnected) => {
+                self.close("Connection to mxchat lost")?;
+                return Ok(false);
+            }
+            Err(e) => if !notice {
+                self.reply("401", [target, e.to_string().as_str()])?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Relays the messages received on mxchat, returns `false` once the connection is lost.
+    fn forward_events(&mut self) -> io::Result<bool> {
+        let Some(client) = &self.client else {
+            return Ok(true);
+        };
+
+        let messages: Vec<_> = client.events()
+            .try_iter()
+            .filter_map(|event| match event.notification {
+                Notification::MessageReceived(message) => Some(message),
+                _ => None
+            })
+            .collect();
+        let connected = client.is_connected();
+
+        let nick = self.nick.clone().unwrap_or_default();
+        for message in messages {
+            let sender = &message.sender.username;
+            let prefix = format!("{sender}!{sender}@{SERVER_NAME}");
+
+            for line in message.content.lines().filter(|line| !line.is_empty()) {
+                self.send(IrcMessage::new("PRIVMSG", [nick.as_str(), line]).with_prefix(prefix.as_str()))?;
+            }
+        }
+
+        if !connected {
+            self.close("Connection to mxchat lost")?;
+        }
+
+        Ok(connected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use mxchat_core::{auth::User, command::Command, encoding::Encode, error::ErrorInfo, io::{read_frame, write_frame, BytesBuffer, ProtocolFeatures, DEFAULT_MAX_FRAME_SIZE}};
+
+    use super::*;
+
+    /// Accepts any login, but refuses the handshakes of the connections which did not forward their peer.
+    fn spawn_mxchat_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            for mut socket in listener.incoming().filter_map(|socket| socket.ok()) {
+                thread::spawn(move || {
+                    let mut forwarded_peer = None;
+
+                    while let Ok((request_id, mut message)) = read_frame(&mut socket, DEFAULT_MAX_FRAME_SIZE) {
+                        let reply = match Command::from_bytes(&mut message).unwrap() {
+                            Command::ForwardedPeer(address) => {
+                                forwarded_peer = Some(address);
+                                continue;
+                            }
+                            Command::Handshake(_) if forwarded_peer.as_deref() == Some("127.0.0.1") =>
+                                Notification::Handshake(ProtocolFeatures::default()),
+                            Command::Handshake(_) => ErrorInfo::from(ErrorCode::RateLimited).with_retry_after(Duration::from_secs(10)).into(),
+                            Command::Connect(data) => Notification::UserConnected(User {
+                                id: UserId::new(1),
+                                nickname: data.username.clone(),
+                                username: data.username,
+                                bot: false,
+                            }),
+                            Command::Pong => continue,
+                            _ => ErrorCode::UnknownCommand.into(),
+                        };
+
+                        let mut bytes = BytesBuffer::empty();
+                        reply.encode(&mut bytes);
+                        write_frame(&mut socket, request_id, &mut bytes, false).unwrap();
+                    }
+                });
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_logins_forward_the_irc_client_address() {
+        let mxchat_address = spawn_mxchat_server();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let irc_address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for socket in listener.incoming().filter_map(|socket| socket.ok()) {
+                let mxchat_address = mxchat_address.clone();
+                thread::spawn(move || run_session(socket, mxchat_address));
+            }
+        });
+
+        // More logins than a single address may do in a row
+        for i in 0..5 {
+            let mut socket = TcpStream::connect(irc_address).unwrap();
+            write!(socket, "PASS s3cret-pass\r\nNICK user{i}\r\nUSER user{i} 0 * :User\r\n").unwrap();
+
+            let mut reply = String::new();
+            BufReader::new(socket).read_line(&mut reply).unwrap();
+            assert_eq!(reply, format!(":mxchat 001 user{i} :Welcome to mxchat, user{i}\r\n"));
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{sync::Arc, thread};
+
+use futures_channel::{mpsc::{self, UnboundedReceiver}, oneshot};
+use futures_core::Stream;
+use mxchat_core::{auth::{PasswordChange, PrivacySettings, SecondFactorEnrollment, User, UserConnectData, UserId, UserRegisterData}, command::Command, io::ProtocolFeatures, messaging::{Contact, SearchResult, UserSearch}, request::RequestId};
+
+use crate::{client::Connection, ClientError, Event};
+
+/// Async front-end over the same connection as [`Client`](crate::Client). It does not
+/// depend on a runtime: blocking work runs on its own thread and completes a future.
+pub struct AsyncClient {
+    connection: Arc<Connection>,
+    events: UnboundedReceiver<Event>,
+}
+
+impl AsyncClient {
+    pub async fn connect(address: impl Into<String>) -> Result<Self, ClientError> {
+        let address = address.into();
+
+        run_blocking(move || {
+            let (sender, events) = mpsc::unbounded();
+
+            Connection::open(&address, None, sender)
+                .map(|connection| Self { connection, events })
+        }).await
+    }
+
+    pub fn features(&self) -> ProtocolFeatures {
+        self.connection.features()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    /// Sends a command without waiting, its reply comes back as an event with the returned request id.
+    pub fn send(&self, cmd: &Command) -> Result<RequestId, ClientError> {
+        self.connection.send(cmd)
+    }
+
+    pub async fn register(&self, register_data: UserRegisterData) -> Result<(), ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.register(register_data)).await
+    }
+
+    pub async fn login(&self, connect_data: UserConnectData) -> Result<User, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.login(connect_data)).await
+    }
+
+    pub async fn second_factor(&self, code: impl Into<String>) -> Result<User, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let code = code.into();
+        run_blocking(move || connection.second_factor(code)).await
+    }
+
+    pub async fn enable_second_factor(&self, password: impl Into<String>) -> Result<SecondFactorEnrollment, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let password = password.into();
+        run_blocking(move || connection.enable_second_factor(password)).await
+    }
+
+    pub async fn confirm_second_factor(&self, code: impl Into<String>) -> Result<Vec<String>, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let code = code.into();
+        run_blocking(move || connection.confirm_second_factor(code)).await
+    }
+
+    pub async fn send_message(&self, recipient: UserId, content: impl Into<String>) -> Result<(), ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let content = content.into();
+        run_blocking(move || connection.send_message(recipient, content)).await
+    }
+
+    pub async fn add_contact(&self, username: impl Into<String>) -> Result<Contact, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let username = username.into();
+        run_blocking(move || connection.add_contact(username)).await
+    }
+
+    pub async fn change_password(&self, old: impl Into<String>, new: impl Into<String>) -> Result<User, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let password_change = PasswordChange { old: old.into(), new: new.into() };
+        run_blocking(move || connection.change_password(password_change)).await
+    }
+
+    pub async fn change_nickname(&self, nickname: impl Into<String>) -> Result<User, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let nickname = nickname.into();
+        run_blocking(move || connection.change_nickname(nickname)).await
+    }
+
+    pub async fn delete_account(&self, password: impl Into<String>) -> Result<(), ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let password = password.into();
+        run_blocking(move || connection.delete_account(password)).await
+    }
+
+    /// Accounts whose username or nickname matches the query, at most `limit` of them.
+    pub async fn search_users(&self, query: impl Into<String>, limit: u16) -> Result<Vec<SearchResult>, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        let search = UserSearch { query: query.into(), limit };
+        run_blocking(move || connection.search_users(search)).await
+    }
+
+    pub async fn privacy_settings(&self) -> Result<PrivacySettings, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.privacy_settings()).await
+    }
+
+    pub async fn update_privacy_settings(&self, settings: PrivacySettings) -> Result<PrivacySettings, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.update_privacy_settings(settings)).await
+    }
+
+    /// Returns the users blocked once this one is.
+    pub async fn block(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.block(user_id)).await
+    }
+
+    pub async fn unblock(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.unblock(user_id)).await
+    }
+
+    pub async fn blocked_users(&self) -> Result<Vec<Contact>, ClientError> {
+        let connection = Arc::clone(&self.connection);
+        run_blocking(move || connection.blocked_users_request(&Command::RequestBlockedUsers)).await
+    }
+
+    /// Stream of events, it ends when the connection is lost.
+    pub fn events(&mut self) -> impl Stream<Item = Event> + Unpin + '_ {
+        &mut self.events
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.connection.close();
+    }
+}
+
+async fn run_blocking<T: Send + 'static>(job: impl FnOnce() -> Result<T, ClientError> + Send + 'static) -> Result<T, ClientError> {
+    let (sender, receiver) = oneshot::channel();
+
+    thread::spawn(move || {
+        let _ = sender.send(job());
+    });
+
+    receiver
+        .await
+        .unwrap_or(Err(ClientError::Disconnected))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, StreamExt};
+    use mxchat_core::notification::Notification;
+
+    use crate::client::tests::spawn_test_server;
+
+    use super::*;
+
+    #[test]
+    fn test_async_client() {
+        block_on(async {
+            let mut client = AsyncClient::connect(spawn_test_server()).await.unwrap();
+
+            let user = client.login(UserConnectData {
+                username: String::from("bot"),
+                password: String::from("password"),
+            }).await.unwrap();
+
+            client.send_message(user.id, "hello").await.unwrap();
+
+            let event = client.events().next().await.unwrap();
+            assert_eq!(event.notification, Notification::MessageReceived(mxchat_core::messaging::IncomingMessage {
+                sender: user,
+                content: String::from("hello"),
+            }));
+        });
+    }
+}
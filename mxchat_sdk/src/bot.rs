@@ -0,0 +1,407 @@
+//! Bots answering slash commands sent to them as direct messages.
+//!
+//! Commands are declared with their usage, the leading words are the name of the command
+//! and the words between angle or square brackets its arguments:
+//!
+//! ```no_run
+//! use mxchat_sdk::{bot::{Bot, Rest}, Client};
+//!
+//! # fn main() -> Result<(), mxchat_sdk::ClientError> {
+//! let client = Client::connect("127.0.0.1:8080")?;
+//! Bot::new(client)
+//!     .command("deploy status <environment>", "Shows the last deployment", |ctx, environment: String| {
+//!         ctx.reply(format!("{environment} is up to date"))
+//!     })
+//!     .command("echo <text...>", "Repeats the text", |ctx, Rest(text)| ctx.reply(text))
+//!     .run()
+//! # }
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use mxchat_core::{auth::User, messaging::IncomingMessage, notification::Notification};
+
+use crate::{Client, ClientError};
+
+/// Words of a command left to parse.
+pub struct Args<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Args<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { rest: text }
+    }
+
+    pub fn next_word(&mut self) -> Option<&'a str> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, rest) = rest.split_at(end);
+        self.rest = rest;
+
+        Some(word)
+    }
+
+    pub fn take_rest(&mut self) -> &'a str {
+        let rest = self.rest.trim();
+        self.rest = "";
+        rest
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rest.trim().is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    Missing,
+    Invalid(String),
+    TooMany,
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::Missing => write!(f, "Missing argument"),
+            ArgError::Invalid(word) => write!(f, "Invalid argument \"{word}\""),
+            ArgError::TooMany => write!(f, "Too many arguments"),
+        }
+    }
+}
+
+/// A single typed argument of a command.
+pub trait FromArg: Sized {
+    fn from_word(word: &str) -> Result<Self, ArgError>;
+
+    /// Takes the argument off `args`, a single word by default.
+    fn take(args: &mut Args) -> Result<Self, ArgError> {
+        Self::from_word(args.next_word().ok_or(ArgError::Missing)?)
+    }
+}
+
+fn parse_word<T: FromStr>(word: &str) -> Result<T, ArgError> {
+    word.parse().map_err(|_| ArgError::Invalid(word.to_string()))
+}
+
+macro_rules! impl_from_arg {
+    ($($t:ty),*) => {
+        $(
+            impl FromArg for $t {
+                fn from_word(word: &str) -> Result<Self, ArgError> {
+                    parse_word(word)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_arg!(String, i32, i64, u32, u64, usize, f64);
+
+impl FromArg for bool {
+    fn from_word(word: &str) -> Result<Self, ArgError> {
+        match word.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            _ => Err(ArgError::Invalid(word.to_string()))
+        }
+    }
+}
+
+/// An optional argument, only allowed last.
+impl<T: FromArg> FromArg for Option<T> {
+    fn from_word(word: &str) -> Result<Self, ArgError> {
+        T::from_word(word).map(Some)
+    }
+
+    fn take(args: &mut Args) -> Result<Self, ArgError> {
+        if args.is_empty() {
+            Ok(None)
+        }
+        else {
+            T::take(args).map(Some)
+        }
+    }
+}
+
+/// The rest of the message, spaces included. Can be empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest(pub String);
+
+impl FromArg for Rest {
+    fn from_word(word: &str) -> Result<Self, ArgError> {
+        Ok(Rest(word.to_string()))
+    }
+
+    fn take(args: &mut Args) -> Result<Self, ArgError> {
+        Ok(Rest(args.take_rest().to_string()))
+    }
+}
+
+/// All the arguments of a command: nothing, a single [`FromArg`] or a tuple of them.
+pub trait FromArgs: Sized {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError>;
+}
+
+impl FromArgs for () {
+    fn from_args(_args: &mut Args) -> Result<Self, ArgError> {
+        Ok(())
+    }
+}
+
+impl<T: FromArg> FromArgs for T {
+    fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+        T::take(args)
+    }
+}
+
+macro_rules! impl_from_args_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: FromArg),*> FromArgs for ($($t,)*) {
+            fn from_args(args: &mut Args) -> Result<Self, ArgError> {
+                Ok(($($t::take(args)?,)*))
+            }
+        }
+    };
+}
+
+impl_from_args_tuple!(A, B);
+impl_from_args_tuple!(A, B, C);
+impl_from_args_tuple!(A, B, C, D);
+
+/// Parses the whole text, leftover words are an error.
+pub fn parse_args<A: FromArgs>(text: &str) -> Result<A, ArgError> {
+    let mut args = Args::new(text);
+    let parsed = A::from_args(&mut args)?;
+
+    if !args.is_empty() {
+        return Err(ArgError::TooMany);
+    }
+
+    Ok(parsed)
+}
+
+/// What a handler is given to answer the message that triggered it.
+pub struct BotContext<'a> {
+    client: &'a Client,
+    message: &'a IncomingMessage,
+}
+
+impl BotContext<'_> {
+    pub fn client(&self) -> &Client {
+        self.client
+    }
+
+    pub fn sender(&self) -> &User {
+        &self.message.sender
+    }
+
+    pub fn content(&self) -> &str {
+        &self.message.content
+    }
+
+    /// Answers the sender of the message.
+    pub fn reply(&self, content: impl Into<String>) -> Result<(), ClientError> {
+        self.client.send_message(self.message.sender.id, content)
+    }
+
+    /// Messages have no ids to attach a reaction to, the emoji is sent back as a reply.
+    pub fn react(&self, emoji: &str) -> Result<(), ClientError> {
+        self.reply(emoji)
+    }
+
+    /// Sends a message to another user, looked up by username.
+    pub fn direct_message(&self, username: &str, content: impl Into<String>) -> Result<(), ClientError> {
+        let contact = self.client.add_contact(username)?;
+        self.client.send_message(contact.id, content)
+    }
+}
+
+type CommandHandler = Box<dyn FnMut(&BotContext, &str) -> Result<(), ClientError> + Send>;
+type MessageHandler = Box<dyn FnMut(&BotContext) -> Result<(), ClientError> + Send>;
+
+struct BotCommand {
+    name: Vec<String>,
+    usage: String,
+    description: String,
+    handler: CommandHandler,
+}
+
+/// Dispatches the messages starting with `/` to the handler of the command with the longest
+/// matching name. Arguments that do not parse are answered with the usage of the command,
+/// unknown commands with a pointer to the built-in `/help`.
+pub struct Bot {
+    client: Client,
+    commands: Vec<BotCommand>,
+    on_message: Option<MessageHandler>,
+}
+
+impl Bot {
+    /// `client` must be logged in, preferably with a bot account.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            commands: Vec::new(),
+            on_message: None,
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn command<A: FromArgs>(
+        mut self,
+        usage: &str,
+        description: &str,
+        mut handler: impl FnMut(&BotContext, A) -> Result<(), ClientError> + Send + 'static
+    ) -> Self {
+        let name = usage.split_whitespace()
+            .take_while(|word| !word.starts_with('<') && !word.starts_with('['))
+            .map(str::to_string)
+            .collect();
+
+        let usage = format!("/{usage}");
+        let usage_message = format!("Usage: {usage}");
+
+        self.commands.push(BotCommand {
+            name,
+            usage,
+            description: description.to_string(),
+            handler: Box::new(move |ctx, text| match parse_args(text) {
+                Ok(args) => handler(ctx, args),
+                Err(e) => ctx.reply(format!("{e}. {usage_message}"))
+            }),
+        });
+
+        self
+    }
+
+    /// Called for the messages which are not commands.
+    pub fn on_message(mut self, handler: impl FnMut(&BotContext) -> Result<(), ClientError> + Send + 'static) -> Self {
+        self.on_message = Some(Box::new(handler));
+        self
+    }
+
+    /// Handles the incoming messages until the connection is lost. Errors of the server,
+    /// such as the sender having gone offline, do not stop the bot.
+    pub fn run(mut self) -> Result<(), ClientError> {
+        loop {
+            let event = self.client.events()
+                .recv()
+                .map_err(|_| ClientError::Disconnected)?;
+
+            let Notification::MessageReceived(message) = event.notification else {
+                continue;
+            };
+
+            match self.handle_message(&message) {
+                Ok(()) | Err(ClientError::Server(_)) => {}
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    pub fn handle_message(&mut self, message: &IncomingMessage) -> Result<(), ClientError> {
+        let ctx = BotContext {
+            client: &self.client,
+            message,
+        };
+
+        let Some(text) = message.content.strip_prefix('/') else {
+            return match &mut self.on_message {
+                Some(handler) => handler(&ctx),
+                None => Ok(())
+            };
+        };
+
+        let words: Vec<&str> = text.split_whitespace().collect();
+
+        let command = self.commands.iter_mut()
+            .filter(|command| command.name.len() <= words.len() && command.name.iter().zip(&words).all(|(a, b)| a == b))
+            .max_by_key(|command| command.name.len());
+
+        match command {
+            Some(command) => {
+                let mut args = Args::new(text);
+                for _ in &command.name {
+                    args.next_word();
+                }
+                (command.handler)(&ctx, args.rest)
+            }
+            None if words.first() == Some(&"help") => ctx.reply(help(&self.commands)),
+            None => {
+                let name = words.first().copied().unwrap_or_default();
+                ctx.reply(format!("Unknown command /{name}, try /help"))
+            }
+        }
+    }
+}
+
+fn help(commands: &[BotCommand]) -> String {
+    let mut help = String::from("Commands:");
+    for command in commands {
+        help.push_str(&format!("\n{} - {}", command.usage, command.description));
+    }
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use mxchat_core::auth::UserConnectData;
+
+    use crate::{client::tests::spawn_test_server, REQUEST_TIMEOUT};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse_args::<()>(""), Ok(()));
+        assert_eq!(parse_args::<String>("  production "), Ok(String::from("production")));
+        assert_eq!(parse_args::<(u32, bool)>("3 yes"), Ok((3, true)));
+        assert_eq!(parse_args::<(String, Option<u32>)>("eu"), Ok((String::from("eu"), None)));
+        assert_eq!(parse_args::<(String, Rest)>("alice see you  later"), Ok((String::from("alice"), Rest(String::from("see you  later")))));
+
+        assert_eq!(parse_args::<u32>(""), Err(ArgError::Missing));
+        assert_eq!(parse_args::<u32>("three"), Err(ArgError::Invalid(String::from("three"))));
+        assert_eq!(parse_args::<()>("extra"), Err(ArgError::TooMany));
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let client = Client::connect(&spawn_test_server()).unwrap();
+        let user = client.login(UserConnectData {
+            username: String::from("bot"),
+            password: String::from("password"),
+        }).unwrap();
+
+        let mut bot = Bot::new(client)
+            .command("deploy <environment>", "Deploys", |ctx, environment: String| ctx.reply(format!("deploying {environment}")))
+            .command("deploy status <environment>", "Shows the last deployment", |ctx, environment: String| ctx.reply(format!("{environment} is up to date")))
+            .command("add <a> <b>", "Adds two numbers", |ctx, (a, b): (i64, i64)| ctx.reply((a + b).to_string()));
+
+        let replies = [
+            ("/deploy status eu", "eu is up to date"),
+            ("/deploy us", "deploying us"),
+            ("/add 2 3", "5"),
+            ("/add 2", "Missing argument. Usage: /add <a> <b>"),
+            ("/rollback", "Unknown command /rollback, try /help"),
+        ];
+
+        for (content, reply) in replies {
+            let message = IncomingMessage { sender: user.clone(), content: String::from(content) };
+            bot.handle_message(&message).unwrap();
+
+            // The test server sends the reply back to the bot
+            let event = bot.client().events().recv_timeout(REQUEST_TIMEOUT).unwrap();
+            match event.notification {
+                Notification::MessageReceived(message) => assert_eq!(message.content, reply),
+                notification => panic!("unexpected notification {notification:?}")
+            }
+        }
+    }
+}
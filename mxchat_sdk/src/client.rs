@@ -0,0 +1,567 @@
+use std::{collections::HashMap, io, net::{IpAddr, Shutdown, TcpStream}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, RecvTimeoutError, Sender}, Arc, Mutex}, thread, time::Duration};
+
+use futures_channel::mpsc::UnboundedSender;
+use mxchat_core::{auth::{PasswordChange, PrivacySettings, SecondFactorEnrollment, User, UserConnectData, UserId, UserRegisterData}, command::Command, io::ProtocolFeatures, messaging::{Contact, OutgoingMessage, SearchResult, UserSearch}, notification::Notification, request::{RequestId, RequestIdGenerator}};
+
+use crate::{connection::{connect_to_server, forwarded_handshake, handshake, is_timeout_error, read_notification, send_command, HEARTBEAT_INTERVAL}, ClientError};
+
+/// Time the blocking operations wait for the reply of the server.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A notification read from the server that no operation was waiting for. `request_id`
+/// is `None` for the notifications the server pushes without being asked, such as
+/// `MessageReceived`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub request_id: Option<RequestId>,
+    pub notification: Notification,
+}
+
+/// Where the reader thread sends the events.
+pub(crate) trait EventSink: Send + 'static {
+    /// Returns `false` once nobody listens anymore.
+    fn deliver(&mut self, event: Event) -> bool;
+
+    fn closed(&mut self) {}
+}
+
+struct ChannelSink {
+    sender: Sender<Event>,
+    wake: Option<Box<dyn Fn() + Send>>,
+}
+
+impl ChannelSink {
+    fn wake(&self) {
+        if let Some(wake) = &self.wake {
+            wake();
+        }
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn deliver(&mut self, event: Event) -> bool {
+        let sent = self.sender.send(event).is_ok();
+        self.wake();
+
+        sent
+    }
+
+    fn closed(&mut self) {
+        self.wake();
+    }
+}
+
+impl EventSink for UnboundedSender<Event> {
+    fn deliver(&mut self, event: Event) -> bool {
+        self.unbounded_send(event).is_ok()
+    }
+}
+
+/// State shared by a front-end and its reader thread. The reader answers heartbeats,
+/// hands replies to the operations waiting for them and everything else to the sink.
+pub(crate) struct Connection {
+    writer: Mutex<TcpStream>,
+    features: ProtocolFeatures,
+    request_ids: RequestIdGenerator,
+    waiters: Mutex<HashMap<RequestId, Sender<Notification>>>,
+    alive: AtomicBool,
+}
+
+impl Connection {
+    pub(crate) fn open(address: &str, forwarded_peer: Option<IpAddr>, mut sink: impl EventSink) -> Result<Arc<Self>, ClientError> {
+        let mut socket = connect_to_server(address)?;
+
+        let request_ids = RequestIdGenerator::new();
+        let features = match forwarded_peer {
+            Some(peer) => forwarded_handshake(&mut socket, peer, request_ids.next_id(), request_ids.next_id())?,
+            None => handshake(&mut socket, request_ids.next_id())?
+        };
+        let reader = socket.try_clone()?;
+
+        let connection = Arc::new(Self {
+            writer: Mutex::new(socket),
+            features,
+            request_ids,
+            waiters: Mutex::new(HashMap::new()),
+            alive: AtomicBool::new(true),
+        });
+
+        let reader_connection = Arc::clone(&connection);
+        thread::spawn(move || {
+            // The cause of the disconnection does not matter to the front-ends, they only see the connection as lost
+            let _ = reader_connection.run_reader(reader, &mut sink);
+
+            reader_connection.alive.store(false, Ordering::Relaxed);
+            reader_connection.waiters.lock().unwrap().clear();
+            sink.closed();
+        });
+
+        Ok(connection)
+    }
+
+    /// Returns `Ok` when the sink is gone.
+    fn run_reader(&self, mut socket: TcpStream, sink: &mut impl EventSink) -> io::Result<()> {
+        socket.set_read_timeout(Some(HEARTBEAT_INTERVAL))?;
+
+        let mut awaiting_pong = false;
+
+        loop {
+            let (request_id, notification) = match read_notification(&mut socket) {
+                Ok(received) => received,
+                Err(e) if is_timeout_error(&e) && !awaiting_pong => {
+                    awaiting_pong = true;
+                    self.write_command(None, &Command::Ping)?;
+                    continue;
+                }
+                Err(e) => return Err(e)
+            };
+
+            awaiting_pong = false;
+
+            match notification {
+                Notification::Ping => self.write_command(None, &Command::Pong)?,
+                Notification::Pong => (),
+                notification => {
+                    let waiter = request_id.and_then(|request_id| self.waiters.lock().unwrap().remove(&request_id));
+
+                    match waiter {
+                        Some(waiter) => { let _ = waiter.send(notification); }
+                        None => if !sink.deliver(Event { request_id, notification }) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_command(&self, request_id: Option<RequestId>, cmd: &Command) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        send_command(&mut *writer, request_id, cmd, self.features)
+    }
+
+    pub(crate) fn features(&self) -> ProtocolFeatures {
+        self.features
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn send(&self, cmd: &Command) -> Result<RequestId, ClientError> {
+        let request_id = self.request_ids.next_id();
+        self.send_with_id(request_id, cmd)?;
+
+        Ok(request_id)
+    }
+
+    pub(crate) fn send_with_id(&self, request_id: RequestId, cmd: &Command) -> Result<(), ClientError> {
+        if !self.is_connected() {
+            return Err(ClientError::Disconnected);
+        }
+
+        self.write_command(Some(request_id), cmd)
+            .map_err(ClientError::from)
+    }
+
+    /// Sends the command and waits for its reply, errors sent by the server come back as `ClientError::Server`.
+    pub(crate) fn request(&self, cmd: &Command) -> Result<Notification, ClientError> {
+        let request_id = self.request_ids.next_id();
+        let (sender, receiver) = mpsc::channel();
+
+        // Registered before checking the connection, the reader clears the waiters after marking it dead.
+        self.waiters.lock().unwrap().insert(request_id, sender);
+        let forget_request = || self.waiters.lock().unwrap().remove(&request_id);
+
+        if !self.is_connected() {
+            forget_request();
+            return Err(ClientError::Disconnected);
+        }
+
+        if let Err(e) = self.write_command(Some(request_id), cmd) {
+            forget_request();
+            return Err(e.into());
+        }
+
+        match receiver.recv_timeout(REQUEST_TIMEOUT) {
+            Ok(Notification::Error(error)) => Err(ClientError::Server(error)),
+            Ok(notification) => Ok(notification),
+            Err(RecvTimeoutError::Timeout) => {
+                forget_request();
+                Err(ClientError::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(ClientError::Disconnected)
+        }
+    }
+
+    pub(crate) fn register(&self, register_data: UserRegisterData) -> Result<(), ClientError> {
+        match self.request(&Command::Register(register_data))? {
+            Notification::UserRegistred => Ok(()),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn login(&self, connect_data: UserConnectData) -> Result<User, ClientError> {
+        match self.request(&Command::Connect(connect_data))? {
+            Notification::UserConnected(user) => Ok(user),
+            Notification::SecondFactorRequired => Err(ClientError::SecondFactorRequired),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn second_factor(&self, code: String) -> Result<User, ClientError> {
+        match self.request(&Command::SecondFactor(code))? {
+            Notification::UserConnected(user) => Ok(user),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn enable_second_factor(&self, password: String) -> Result<SecondFactorEnrollment, ClientError> {
+        match self.request(&Command::EnableSecondFactor(password))? {
+            Notification::SecondFactorEnrollment(enrollment) => Ok(enrollment),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn confirm_second_factor(&self, code: String) -> Result<Vec<String>, ClientError> {
+        match self.request(&Command::ConfirmSecondFactor(code))? {
+            Notification::SecondFactorEnabled(recovery_codes) => Ok(recovery_codes),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn send_message(&self, recipient: UserId, content: String) -> Result<(), ClientError> {
+        match self.request(&Command::SendMessage(OutgoingMessage { recipient, content }))? {
+            Notification::MessageSent => Ok(()),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn add_contact(&self, username: String) -> Result<Contact, ClientError> {
+        match self.request(&Command::RequestContact(username))? {
+            Notification::ReceiveContactInfo(contact) => Ok(contact),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn change_password(&self, password_change: PasswordChange) -> Result<User, ClientError> {
+        match self.request(&Command::ChangePassword(password_change))? {
+            Notification::AccountUpdated(user) => Ok(user),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn change_nickname(&self, nickname: String) -> Result<User, ClientError> {
+        match self.request(&Command::ChangeNickname(nickname))? {
+            Notification::AccountUpdated(user) => Ok(user),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn delete_account(&self, password: String) -> Result<(), ClientError> {
+        match self.request(&Command::DeleteAccount(password))? {
+            Notification::AccountDeleted => Ok(()),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn search_users(&self, search: UserSearch) -> Result<Vec<SearchResult>, ClientError> {
+        match self.request(&Command::SearchUsers(search))? {
+            Notification::SearchResults(results) => Ok(results),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn privacy_settings(&self) -> Result<PrivacySettings, ClientError> {
+        match self.request(&Command::RequestPrivacySettings)? {
+            Notification::PrivacySettings(settings) => Ok(settings),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn update_privacy_settings(&self, settings: PrivacySettings) -> Result<PrivacySettings, ClientError> {
+        match self.request(&Command::UpdatePrivacySettings(settings))? {
+            Notification::PrivacySettings(settings) => Ok(settings),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn block(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        self.blocked_users_request(&Command::Block(user_id))
+    }
+
+    pub(crate) fn unblock(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        self.blocked_users_request(&Command::Unblock(user_id))
+    }
+
+    pub(crate) fn blocked_users_request(&self, cmd: &Command) -> Result<Vec<Contact>, ClientError> {
+        match self.request(cmd)? {
+            Notification::BlockedUsers(blocked_users) => Ok(blocked_users),
+            notification => Err(ClientError::UnexpectedReply(notification))
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        let _ = self.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+/// Blocking client. Operations wait for the reply of the server, up to [`REQUEST_TIMEOUT`],
+/// while the other notifications are queued in [`Client::events`].
+pub struct Client {
+    connection: Arc<Connection>,
+    events: Receiver<Event>,
+}
+
+impl Client {
+    /// Connects and negotiates the protocol features with the server.
+    pub fn connect(address: &str) -> Result<Self, ClientError> {
+        Self::open(address, None, None)
+    }
+
+    /// Same as [`Client::connect`], `wake` is called whenever an event is queued or the
+    /// connection is lost, to wake up an event loop polling the client.
+    pub fn connect_with_wake(address: &str, wake: impl Fn() + Send + 'static) -> Result<Self, ClientError> {
+        Self::open(address, None, Some(Box::new(wake)))
+    }
+
+    /// Same as [`Client::connect`] for a gateway relaying the client at `peer`, so that the
+    /// rate limits of the server apply to that client. The server must trust the gateway.
+    pub fn connect_forwarded(address: &str, peer: IpAddr) -> Result<Self, ClientError> {
+        Self::open(address, Some(peer), None)
+    }
+
+    fn open(address: &str, forwarded_peer: Option<IpAddr>, wake: Option<Box<dyn Fn() + Send>>) -> Result<Self, ClientError> {
+        let (sender, events) = mpsc::channel();
+        let connection = Connection::open(address, forwarded_peer, ChannelSink { sender, wake })?;
+
+        Ok(Self {
+            connection,
+            events
+        })
+    }
+
+    pub fn features(&self) -> ProtocolFeatures {
+        self.connection.features()
+    }
+
+    /// Becomes `false` once the connection is lost, it is never re-established.
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    /// Sends a command without waiting, its reply comes back as an event with the returned request id.
+    pub fn send(&self, cmd: &Command) -> Result<RequestId, ClientError> {
+        self.connection.send(cmd)
+    }
+
+    /// Sends a command with a request id chosen by the caller, for callers keeping track
+    /// of their requests across connections. The id must not be in use by an operation
+    /// of this client, which only happens while one is waiting for its reply.
+    pub fn send_with_id(&self, request_id: RequestId, cmd: &Command) -> Result<(), ClientError> {
+        self.connection.send_with_id(request_id, cmd)
+    }
+
+    pub fn register(&self, register_data: UserRegisterData) -> Result<(), ClientError> {
+        self.connection.register(register_data)
+    }
+
+    /// Fails with [`ClientError::SecondFactorRequired`] for accounts enrolled in two-factor
+    /// authentication, the login then finishes with [`Client::second_factor`].
+    pub fn login(&self, connect_data: UserConnectData) -> Result<User, ClientError> {
+        self.connection.login(connect_data)
+    }
+
+    /// Sends the code of the authenticator app, or a recovery code.
+    pub fn second_factor(&self, code: impl Into<String>) -> Result<User, ClientError> {
+        self.connection.second_factor(code.into())
+    }
+
+    /// Starts the enrollment of the logged in account once its password is checked, it
+    /// is enabled once [`Client::confirm_second_factor`] succeeds and then replaces any
+    /// previous second factor.
+    pub fn enable_second_factor(&self, password: impl Into<String>) -> Result<SecondFactorEnrollment, ClientError> {
+        self.connection.enable_second_factor(password.into())
+    }
+
+    /// Returns the recovery codes, the server does not show them again.
+    pub fn confirm_second_factor(&self, code: impl Into<String>) -> Result<Vec<String>, ClientError> {
+        self.connection.confirm_second_factor(code.into())
+    }
+
+    /// Returns once the message is delivered to `recipient`.
+    pub fn send_message(&self, recipient: UserId, content: impl Into<String>) -> Result<(), ClientError> {
+        self.connection.send_message(recipient, content.into())
+    }
+
+    pub fn add_contact(&self, username: impl Into<String>) -> Result<Contact, ClientError> {
+        self.connection.add_contact(username.into())
+    }
+
+    pub fn change_password(&self, old: impl Into<String>, new: impl Into<String>) -> Result<User, ClientError> {
+        self.connection.change_password(PasswordChange { old: old.into(), new: new.into() })
+    }
+
+    /// The users having this one in their contacts are told about the new nickname.
+    pub fn change_nickname(&self, nickname: impl Into<String>) -> Result<User, ClientError> {
+        self.connection.change_nickname(nickname.into())
+    }
+
+    /// The connection stays open but is logged out once the account is deleted.
+    pub fn delete_account(&self, password: impl Into<String>) -> Result<(), ClientError> {
+        self.connection.delete_account(password.into())
+    }
+
+    /// Accounts whose username or nickname matches the query, at most `limit` of them.
+    pub fn search_users(&self, query: impl Into<String>, limit: u16) -> Result<Vec<SearchResult>, ClientError> {
+        self.connection.search_users(UserSearch { query: query.into(), limit })
+    }
+
+    pub fn privacy_settings(&self) -> Result<PrivacySettings, ClientError> {
+        self.connection.privacy_settings()
+    }
+
+    pub fn update_privacy_settings(&self, settings: PrivacySettings) -> Result<PrivacySettings, ClientError> {
+        self.connection.update_privacy_settings(settings)
+    }
+
+    /// Returns the users blocked once this one is.
+    pub fn block(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        self.connection.block(user_id)
+    }
+
+    pub fn unblock(&self, user_id: UserId) -> Result<Vec<Contact>, ClientError> {
+        self.connection.unblock(user_id)
+    }
+
+    pub fn blocked_users(&self) -> Result<Vec<Contact>, ClientError> {
+        self.connection.blocked_users_request(&Command::RequestBlockedUsers)
+    }
+
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.connection.close();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::TcpListener;
+
+    use mxchat_core::{error::ErrorCode, io::{read_frame, write_frame, BytesBuffer, DEFAULT_MAX_FRAME_SIZE}, encoding::Encode, messaging::IncomingMessage};
+
+    use super::*;
+
+    fn write_notification(socket: &mut TcpStream, request_id: Option<RequestId>, notification: &Notification) {
+        let mut message = BytesBuffer::empty();
+        notification.encode(&mut message);
+        write_frame(socket, request_id, &mut message, false).unwrap();
+    }
+
+    /// Answers a single connection the way the server would, messages are sent back to their sender.
+    pub(crate) fn spawn_test_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let user = User {
+                id: UserId::new(1),
+                username: String::from("bot"),
+                nickname: String::from("Bot"),
+                bot: true,
+            };
+
+            while let Ok((request_id, mut message)) = read_frame(&mut socket, DEFAULT_MAX_FRAME_SIZE) {
+                let reply = match Command::from_bytes(&mut message).unwrap() {
+                    Command::Handshake(_) => Notification::Handshake(ProtocolFeatures::default()),
+                    Command::Register(_) => Notification::UserRegistred,
+                    Command::Connect(_) => Notification::UserConnected(user.clone()),
+                    Command::RequestContact(username) if username == user.username =>
+                        Notification::ReceiveContactInfo(Contact { id: user.id, nickname: user.nickname.clone(), bot: user.bot }),
+                    Command::RequestContact(_) => ErrorCode::UserNotFound.into(),
+                    Command::SendMessage(message) => {
+                        let incoming_message = IncomingMessage { sender: user.clone(), content: message.content };
+                        write_notification(&mut socket, None, &Notification::MessageReceived(incoming_message));
+                        Notification::MessageSent
+                    }
+                    Command::EnableSecondFactor(_) | Command::ConfirmSecondFactor(_) | Command::SecondFactor(_) |
+                    Command::ChangePassword(_) | Command::ChangeNickname(_) | Command::DeleteAccount(_) |
+                    Command::SearchUsers(_) | Command::RequestPrivacySettings | Command::UpdatePrivacySettings(_) |
+                    Command::Block(_) | Command::Unblock(_) | Command::RequestBlockedUsers =>
+                        ErrorCode::UnknownCommand.into(),
+                    Command::Ping => Notification::Pong,
+                    Command::Pong | Command::ForwardedPeer(_) => continue,
+                };
+
+                write_notification(&mut socket, request_id, &reply);
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn test_blocking_client() {
+        let client = Client::connect(&spawn_test_server()).unwrap();
+
+        client.register(UserRegisterData {
+            username: String::from("bot"),
+            nickname: String::from("Bot"),
+            password: String::from("password"),
+            bot: true,
+        }).unwrap();
+
+        let user = client.login(UserConnectData {
+            username: String::from("bot"),
+            password: String::from("password"),
+        }).unwrap();
+        assert_eq!(user.nickname, "Bot");
+
+        let contact = client.add_contact("bot").unwrap();
+        assert_eq!(contact.id, user.id);
+
+        match client.add_contact("nobody") {
+            Err(ClientError::Server(error)) => assert_eq!(error.code, ErrorCode::UserNotFound),
+            result => panic!("unexpected result {result:?}")
+        }
+
+        client.send_message(user.id, "hello").unwrap();
+
+        let event = client.events().recv_timeout(REQUEST_TIMEOUT).unwrap();
+        assert_eq!(event.request_id, None);
+        match event.notification {
+            Notification::MessageReceived(message) => assert_eq!(message.content, "hello"),
+            notification => panic!("unexpected notification {notification:?}")
+        }
+    }
+
+    #[test]
+    fn test_refused_forwarded_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+
+            let (Ok((forward_id, mut message)), Ok((request_id, _))) =
+                (read_frame(&mut socket, DEFAULT_MAX_FRAME_SIZE), read_frame(&mut socket, DEFAULT_MAX_FRAME_SIZE)) else {
+                panic!("could not read the frames");
+            };
+            assert_eq!(Command::from_bytes(&mut message).unwrap(), Command::ForwardedPeer(String::from("10.0.0.1")));
+
+            write_notification(&mut socket, forward_id, &ErrorCode::NotAuthenticated.into());
+            write_notification(&mut socket, request_id, &Notification::Handshake(ProtocolFeatures::default()));
+        });
+
+        match Client::connect_forwarded(&address, IpAddr::from([10, 0, 0, 1])) {
+            Err(ClientError::Server(error)) => assert_eq!(error.code, ErrorCode::NotAuthenticated),
+            result => panic!("unexpected result {:?}", result.map(|client| client.features()))
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Frame level helpers, for callers driving a socket by themselves.
+
+use std::{io::{self, Read, Write}, net::{IpAddr, TcpStream, ToSocketAddrs}, time::Duration};
+
+use mxchat_core::{command::Command, encoding::Decode, error::ErrorCode, io::{read_frame, write_frame, BytesBuffer, ProtocolFeatures, DEFAULT_MAX_FRAME_SIZE}, notification::Notification, request::RequestId};
+
+use crate::ClientError;
+
+/// Idle time after which the client pings the server, and then gives up on it if the ping goes unanswered.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connects with a timeout, which also bounds the handshake that follows.
+pub fn connect_to_server(address: &str) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::from(io::ErrorKind::AddrNotAvailable);
+
+    for socket_address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT) {
+            Ok(socket) => {
+                socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+                return Ok(socket);
+            }
+            Err(e) => last_error = e
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Exchanges the supported protocol features with the server, returns the ones both sides support.
+pub fn handshake(socket: &mut TcpStream, request_id: RequestId) -> Result<ProtocolFeatures, ClientError> {
+    let client_features = ProtocolFeatures::supported();
+
+    send_command(socket, Some(request_id), &Command::Handshake(client_features), ProtocolFeatures::default())?;
+
+    match read_reply(socket, request_id)? {
+        Notification::Handshake(server_features) => Ok(client_features.negotiate(server_features)),
+        Notification::Error(error) if error.code == ErrorCode::UnknownCommand => Ok(ProtocolFeatures::default()),
+        Notification::Error(error) => Err(ClientError::Server(error)),
+        notification => Err(ClientError::UnexpectedReply(notification))
+    }
+}
+
+/// Handshake of a gateway relaying the client at `peer`, the server must trust the gateway.
+/// The forwarding is only answered when refused, which comes before the reply to the handshake.
+pub fn forwarded_handshake(socket: &mut TcpStream, peer: IpAddr, forward_id: RequestId, request_id: RequestId) -> Result<ProtocolFeatures, ClientError> {
+    let client_features = ProtocolFeatures::supported();
+
+    send_command(socket, Some(forward_id), &Command::ForwardedPeer(peer.to_string()), ProtocolFeatures::default())?;
+    send_command(socket, Some(request_id), &Command::Handshake(client_features), ProtocolFeatures::default())?;
+
+    loop {
+        match read_notification(socket)? {
+            (Some(reply_id), Notification::Error(error)) if reply_id == forward_id => return Err(ClientError::Server(error)),
+            (Some(reply_id), Notification::Handshake(server_features)) if reply_id == request_id =>
+                return Ok(client_features.negotiate(server_features)),
+            (Some(reply_id), Notification::Error(error)) if reply_id == request_id => return Err(ClientError::Server(error)),
+            (Some(reply_id), notification) if reply_id == request_id => return Err(ClientError::UnexpectedReply(notification)),
+            _ => ()
+        }
+    }
+}
+
+/// Commands are compressed only if `features` allows it, the ones sent before the
+/// handshake are sent with the default features.
+pub fn send_command(writer: &mut impl Write, request_id: Option<RequestId>, cmd: &Command, features: ProtocolFeatures) -> io::Result<()> {
+    let mut message = BytesBuffer::empty();
+    cmd.to_bytes(&mut message);
+
+    write_frame(writer, request_id, &mut message, features.compression)
+}
+
+pub fn read_notification(reader: &mut impl Read) -> io::Result<(Option<RequestId>, Notification)> {
+    let (request_id, mut message) = read_frame(reader, DEFAULT_MAX_FRAME_SIZE)?;
+
+    match Notification::decode(&mut message) {
+        None => Err(io::ErrorKind::InvalidData.into()),
+        Some(notif) => Ok((request_id, notif))
+    }
+}
+
+/// Reads notifications until the one answering `request_id`.
+pub fn read_reply(reader: &mut impl Read, request_id: RequestId) -> io::Result<Notification> {
+    loop {
+        let (reply_id, notification) = read_notification(reader)?;
+        if reply_id == Some(request_id) {
+            return Ok(notification);
+        }
+    }
+}
+
+pub(crate) fn is_timeout_error(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
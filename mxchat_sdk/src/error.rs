@@ -0,0 +1,38 @@
+use std::{fmt, io};
+
+use mxchat_core::{error::ErrorInfo, notification::Notification};
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// The server answered the command with an error.
+    Server(ErrorInfo),
+    UnexpectedReply(Notification),
+    /// The account is enrolled in two-factor authentication, the login finishes once
+    /// the code is sent with `second_factor`.
+    SecondFactorRequired,
+    /// No answer came back before the request timeout.
+    Timeout,
+    Disconnected,
+}
+
+impl From<io::Error> for ClientError {
+    fn from(value: io::Error) -> Self {
+        ClientError::Io(value)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "Could not reach the server: {e}"),
+            ClientError::Server(error) => write!(f, "{}", error.message),
+            ClientError::UnexpectedReply(_) => write!(f, "Unexpected reply from server"),
+            ClientError::SecondFactorRequired => write!(f, "A two-factor authentication code is required"),
+            ClientError::Timeout => write!(f, "The server did not answer in time"),
+            ClientError::Disconnected => write!(f, "Connection to server lost"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
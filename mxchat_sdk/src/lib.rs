@@ -0,0 +1,19 @@
+//! Client library for mxchat servers, used by the GUI and meant for bots and scripts.
+//!
+//! [`Client`] is the blocking front-end, [`AsyncClient`] wraps the same connection for
+//! async code without depending on a particular runtime. Interactive front-ends keep
+//! their session alive with [`session::ConnectionManager`], bots dispatch the commands
+//! sent to them with [`bot::Bot`].
+
+pub mod bot;
+pub mod connection;
+mod client;
+mod async_client;
+mod error;
+pub mod pending_requests;
+pub mod session;
+
+pub use async_client::AsyncClient;
+pub use client::{Client, Event, REQUEST_TIMEOUT};
+pub use error::ClientError;
+pub use mxchat_core;
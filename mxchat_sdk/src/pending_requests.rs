@@ -0,0 +1,93 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use mxchat_core::request::RequestId;
+
+/// Requests sent to the server and still waiting for their reply, along with what the
+/// front-end needs to handle it. A request expires `timeout` after its command was written,
+/// commands queued while offline don't expire until then.
+pub struct PendingRequests<R> {
+    requests: HashMap<RequestId, (R, Option<Instant>)>,
+    timeout: Duration,
+}
+
+impl<R> PendingRequests<R> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            requests: HashMap::new(),
+            timeout
+        }
+    }
+
+    /// Adds a request whose command was written.
+    pub fn insert(&mut self, request_id: RequestId, request: R) {
+        self.requests.insert(request_id, (request, Some(Instant::now() + self.timeout)));
+    }
+
+    /// Adds a request whose command waits for the connection, see [`Self::start_deadlines`].
+    pub fn insert_queued(&mut self, request_id: RequestId, request: R) {
+        self.requests.insert(request_id, (request, None));
+    }
+
+    /// Starts the timeout of the queued requests once their commands were written.
+    pub fn start_deadlines(&mut self, request_ids: impl IntoIterator<Item = RequestId>) {
+        let deadline = Instant::now() + self.timeout;
+
+        for request_id in request_ids {
+            if let Some((_, pending_deadline)) = self.requests.get_mut(&request_id) {
+                pending_deadline.get_or_insert(deadline);
+            }
+        }
+    }
+
+    pub fn take(&mut self, request_id: RequestId) -> Option<R> {
+        self.requests
+            .remove(&request_id)
+            .map(|(request, _)| request)
+    }
+
+    pub fn take_expired(&mut self) -> Vec<R> {
+        let now = Instant::now();
+
+        let expired_ids: Vec<RequestId> = self.requests
+            .iter()
+            .filter(|(_, (_, deadline))| deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(request_id, _)| *request_id)
+            .collect();
+
+        expired_ids
+            .into_iter()
+            .filter_map(|request_id| self.take(request_id))
+            .collect()
+    }
+
+    /// Time left before the next request expires.
+    pub fn next_expiration(&self) -> Option<Duration> {
+        self.requests
+            .values()
+            .filter_map(|(_, deadline)| *deadline)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mxchat_core::request::RequestIdGenerator;
+
+    use super::*;
+
+    #[test]
+    fn test_queued_requests_expire_once_written() {
+        let mut pending_requests = PendingRequests::new(Duration::ZERO);
+        let request_ids = RequestIdGenerator::new();
+        let (queued, written) = (request_ids.next_id(), request_ids.next_id());
+
+        pending_requests.insert_queued(queued, "queued");
+        pending_requests.insert(written, "written");
+        assert_eq!(pending_requests.take_expired(), ["written"]);
+        assert_eq!(pending_requests.next_expiration(), None);
+
+        pending_requests.start_deadlines([queued]);
+        assert_eq!(pending_requests.take_expired(), ["queued"]);
+    }
+}
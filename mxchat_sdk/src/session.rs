@@ -0,0 +1,263 @@
+use std::{collections::VecDeque, hash::{BuildHasher, RandomState}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver, Sender, TryRecvError}, Arc}, thread, time::Duration};
+
+use mxchat_core::{auth::{User, UserConnectData}, command::Command, error::ErrorCode, request::{RequestId, RequestIdGenerator}};
+
+use crate::{Client, ClientError, Event};
+
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+type Wake = Arc<dyn Fn() + Send + Sync>;
+
+pub struct SessionCredentials {
+    pub address: String,
+    pub connect_data: UserConnectData,
+}
+
+pub enum ConnectionState {
+    Connected,
+    Reconnecting(u32),
+    /// The account is enrolled in two-factor authentication, the session is back once a
+    /// code is sent with [`ConnectionManager::send_second_factor`]. Holds why the last
+    /// code was refused.
+    SecondFactorRequired(Option<String>),
+    Failed(String),
+}
+
+enum ReconnectEvent {
+    Attempt(u32),
+    Connected(Client, User),
+    /// The password was accepted, the client waits for a code.
+    SecondFactorRequired(Client, Option<String>),
+    Failed(String),
+}
+
+/// Owns the connection of a logged in user, for interactive front-ends polling it from
+/// their event loop. When the client reports the connection as lost, a background
+/// thread reconnects and re-authenticates with the session credentials, asking the
+/// front-end for a code when the account has a second factor. Commands sent in the
+/// meantime are kept in an outbox.
+pub struct ConnectionManager {
+    wake: Wake,
+    credentials: Arc<SessionCredentials>,
+    request_ids: RequestIdGenerator,
+    client: Client,
+    /// Client logged in with the password, while in `ConnectionState::SecondFactorRequired`.
+    awaiting_second_factor: Option<Client>,
+    state: ConnectionState,
+    outbox: VecDeque<(RequestId, Command)>,
+    /// Commands of the outbox written since the front-end last asked.
+    flushed: Vec<RequestId>,
+    reconnect_events: Option<Receiver<ReconnectEvent>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ConnectionManager {
+    /// `client` must be logged in with `credentials`. `wake` is called whenever the state
+    /// of the connection changes and is given to the clients created when reconnecting.
+    pub fn new(client: Client, credentials: SessionCredentials, wake: impl Fn() + Send + Sync + 'static) -> Self {
+        Self {
+            wake: Arc::new(wake),
+            credentials: Arc::new(credentials),
+            request_ids: RequestIdGenerator::new(),
+            client,
+            awaiting_second_factor: None,
+            state: ConnectionState::Connected,
+            outbox: VecDeque::new(),
+            flushed: Vec::new(),
+            reconnect_events: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// Sends the command or keeps it for when the connection is back, returns the id
+    /// the server will answer with. The ids are kept across reconnections.
+    pub fn send(&mut self, cmd: Command) -> RequestId {
+        let request_id = self.request_ids.next_id();
+
+        let sent = matches!(self.state, ConnectionState::Connected) &&
+            self.client.send_with_id(request_id, &cmd).is_ok();
+
+        if !sent {
+            self.outbox.push_back((request_id, cmd));
+        }
+
+        request_id
+    }
+
+    /// Whether the command is kept in the outbox until the connection is back.
+    pub fn is_queued(&self, request_id: RequestId) -> bool {
+        self.outbox.iter().any(|(queued_id, _)| *queued_id == request_id)
+    }
+
+    /// Ids of the queued commands written since the last call, once the connection is back.
+    pub fn take_flushed(&mut self) -> Vec<RequestId> {
+        std::mem::take(&mut self.flushed)
+    }
+
+    /// Password used by the next reconnections, once the server accepted `Command::ChangePassword`.
+    pub fn update_password(&mut self, password: String) {
+        let connect_data = UserConnectData {
+            username: self.credentials.connect_data.username.clone(),
+            password,
+        };
+
+        self.credentials = Arc::new(SessionCredentials {
+            address: self.credentials.address.clone(),
+            connect_data,
+        });
+    }
+
+    /// Finishes the login of a reconnection in `ConnectionState::SecondFactorRequired`,
+    /// with a code of the authenticator app or a recovery code.
+    pub fn send_second_factor(&mut self, code: String) {
+        let Some(client) = self.awaiting_second_factor.take() else {
+            return;
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        self.reconnect_events = Some(receiver);
+
+        let wake = Arc::clone(&self.wake);
+        thread::spawn(move || {
+            let event = match client.second_factor(code) {
+                Ok(user) => ReconnectEvent::Connected(client, user),
+                Err(e) => ReconnectEvent::SecondFactorRequired(client, Some(e.to_string()))
+            };
+
+            let _ = sender.send(event);
+            wake();
+        });
+    }
+
+    pub fn next_event(&self) -> Option<Event> {
+        self.client.events().try_recv().ok()
+    }
+
+    /// Advances the reconnection process, returns the user data sent back by the server
+    /// once the session has been re-established.
+    pub fn update(&mut self) -> Option<User> {
+        if matches!(self.state, ConnectionState::Connected) && !self.client.is_connected() {
+            self.start_reconnecting();
+        }
+
+        // Lost again while waiting for the code, the password is checked anew
+        if self.awaiting_second_factor.as_ref().is_some_and(|client| !client.is_connected()) {
+            self.awaiting_second_factor = None;
+            self.start_reconnecting();
+        }
+
+        let receiver = self.reconnect_events.as_ref()?;
+
+        loop {
+            match receiver.try_recv() {
+                Ok(ReconnectEvent::Attempt(attempt)) => self.state = ConnectionState::Reconnecting(attempt),
+                Ok(ReconnectEvent::Connected(client, user)) => {
+                    self.reconnect_events = None;
+                    self.on_reconnected(client);
+                    return Some(user);
+                }
+                Ok(ReconnectEvent::SecondFactorRequired(client, error_message)) => {
+                    self.reconnect_events = None;
+                    self.awaiting_second_factor = Some(client);
+                    self.state = ConnectionState::SecondFactorRequired(error_message);
+                    return None;
+                }
+                Ok(ReconnectEvent::Failed(error_message)) => {
+                    self.reconnect_events = None;
+                    self.state = ConnectionState::Failed(error_message);
+                    return None;
+                }
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.reconnect_events = None;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn start_reconnecting(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+
+        self.state = ConnectionState::Reconnecting(0);
+        self.reconnect_events = Some(receiver);
+
+        let wake = Arc::clone(&self.wake);
+        let credentials = Arc::clone(&self.credentials);
+        let cancelled = Arc::clone(&self.cancelled);
+        thread::spawn(move || reconnect(&wake, &credentials, &cancelled, sender));
+    }
+
+    fn on_reconnected(&mut self, client: Client) {
+        self.client = client;
+        self.state = ConnectionState::Connected;
+
+        while let Some((request_id, cmd)) = self.outbox.pop_front() {
+            if self.client.send_with_id(request_id, &cmd).is_err() {
+                self.outbox.push_front((request_id, cmd));
+                break;
+            }
+            self.flushed.push(request_id);
+        }
+    }
+}
+
+impl Drop for ConnectionManager {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+fn reconnect(wake: &Wake, credentials: &SessionCredentials, cancelled: &AtomicBool, events: Sender<ReconnectEvent>) {
+    let mut attempt = 0;
+
+    while !cancelled.load(Ordering::Relaxed) {
+        attempt += 1;
+        if events.send(ReconnectEvent::Attempt(attempt)).is_err() {
+            return;
+        }
+        wake();
+
+        thread::sleep(reconnect_delay(attempt));
+
+        let client_wake = Arc::clone(wake);
+        let client = match Client::connect_with_wake(&credentials.address, move || client_wake()) {
+            Ok(client) => client,
+            Err(_) => continue
+        };
+
+        let event = match client.login(credentials.connect_data.clone()) {
+            Ok(user) => ReconnectEvent::Connected(client, user),
+            Err(ClientError::SecondFactorRequired) => ReconnectEvent::SecondFactorRequired(client, None),
+            Err(e) if is_login_refused(&e) => ReconnectEvent::Failed(e.to_string()),
+            // Servers restarting, overloaded or rate limiting the reconnections are retried
+            Err(_) => continue
+        };
+
+        let _ = events.send(event);
+        wake();
+        return;
+    }
+}
+
+/// Whether the server refused the credentials themselves, retrying them cannot succeed.
+fn is_login_refused(error: &ClientError) -> bool {
+    matches!(error, ClientError::Server(error) if matches!(error.code, ErrorCode::PasswordIncorrect | ErrorCode::UserNotFound))
+}
+
+/// Exponential backoff with equal jitter: half of the delay is fixed, the other half random.
+fn reconnect_delay(attempt: u32) -> Duration {
+    let delay = RECONNECT_BASE_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX_DELAY);
+
+    let half_delay_millis = delay.as_millis() as u64 / 2;
+    let jitter_millis = RandomState::new().hash_one(attempt) % (half_delay_millis + 1);
+
+    Duration::from_millis(half_delay_millis + jitter_millis)
+}
@@ -0,0 +1,473 @@
+use std::{io, net::IpAddr, sync::Arc, thread};
+
+use mxchat_core::{auth::{User, UserRegisterData}, error::{ErrorCode, ErrorInfo}, messaging::OutgoingMessage, notification::Notification};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{command_handler::CommandHandler, server_handler::ServerCommandHandler};
+
+/// JSON schemas of the bodies, served under `/api/schemas/<name>`.
+const SCHEMAS: [(&str, &str); 6] = [
+    ("user", include_str!("../schemas/user.json")),
+    ("user_list", include_str!("../schemas/user_list.json")),
+    ("presence", include_str!("../schemas/presence.json")),
+    ("create_user", include_str!("../schemas/create_user.json")),
+    ("post_message", include_str!("../schemas/post_message.json")),
+    ("error", include_str!("../schemas/error.json")),
+];
+
+pub struct AdminApiConfig {
+    pub port: u16,
+    /// Keys accepted in the `Authorization: Bearer <key>` or `X-Api-Key` headers.
+    pub api_keys: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct UserView {
+    id: u32,
+    username: String,
+    nickname: String,
+    bot: bool,
+    online: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreateUser {
+    username: String,
+    nickname: Option<String>,
+    password: String,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PostMessage {
+    sender: String,
+    recipient: String,
+    content: String,
+}
+
+struct ApiResponse {
+    status: u16,
+    body: Value,
+}
+
+impl ApiResponse {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn created(body: Value) -> Self {
+        Self { status: 201, body }
+    }
+}
+
+impl From<ErrorInfo> for ApiResponse {
+    fn from(error: ErrorInfo) -> Self {
+        Self {
+            status: http_status(error.code),
+            body: json!({
+                "code": error.code.code(),
+                "message": error.message,
+                "detail": error.detail,
+                "retry_after_ms": error.retry_after.map(|retry_after| retry_after.as_millis() as u64),
+            }),
+        }
+    }
+}
+
+impl From<ErrorCode> for ApiResponse {
+    fn from(value: ErrorCode) -> Self {
+        ErrorInfo::from(value).into()
+    }
+}
+
+fn http_status(code: ErrorCode) -> u16 {
+    match code {
+        ErrorCode::UnknownCommand | ErrorCode::UserNotFound => 404,
+        ErrorCode::InvalidPayload | ErrorCode::InvalidAccountData => 400,
+        ErrorCode::FrameTooLarge => 413,
+        ErrorCode::RateLimited => 429,
+        ErrorCode::NotAuthenticated | ErrorCode::PasswordIncorrect | ErrorCode::SecondFactorIncorrect => 401,
+        ErrorCode::RegistrationDisabled => 403,
+        ErrorCode::UserAlreadyExists | ErrorCode::UserAlreadyConnected | ErrorCode::UserOffline => 409,
+        ErrorCode::IdleTimeout | ErrorCode::Internal | ErrorCode::Unrecognized(_) => 500,
+    }
+}
+
+/// Serves the local administration API on its own thread. Accounts and messages go
+/// through the same [`ServerCommandHandler`] as the commands of the clients.
+pub fn run_admin_api(cmd_handler: Arc<ServerCommandHandler>, address: IpAddr, config: AdminApiConfig) -> io::Result<()> {
+    if config.api_keys.iter().any(String::is_empty) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "admin API keys can't be empty"));
+    }
+
+    let server = Server::http((address, config.port)).map_err(io::Error::other)?;
+
+    println!("Admin API listening on port {}", config.port);
+
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let api_response = if is_authorized(&config, &request) {
+                handle_request(&cmd_handler, &mut request)
+            }
+            else {
+                ErrorInfo::new(ErrorCode::NotAuthenticated, "Missing or invalid API key").into()
+            };
+
+            let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+            let response = Response::from_string(api_response.body.to_string())
+                .with_status_code(api_response.status)
+                .with_header(content_type);
+
+            if let Err(e) = request.respond(response) {
+                println!("Could not answer admin API request: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn is_authorized(config: &AdminApiConfig, request: &Request) -> bool {
+    let api_key = request.headers()
+        .iter()
+        .find_map(|header| {
+            let value = header.value.as_str();
+
+            if header.field.equiv("X-Api-Key") {
+                Some(value)
+            }
+            else if header.field.equiv("Authorization") {
+                value.strip_prefix("Bearer ")
+            }
+            else {
+                None
+            }
+        });
+
+    accepts_key(config, api_key)
+}
+
+fn accepts_key(config: &AdminApiConfig, api_key: Option<&str>) -> bool {
+    api_key
+        .filter(|api_key| !api_key.is_empty())
+        .is_some_and(|api_key| config.api_keys.iter().any(|key| constant_time_eq(key.as_bytes(), api_key.as_bytes())))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn handle_request(cmd_handler: &ServerCommandHandler, request: &mut Request) -> ApiResponse {
+    let mut body = String::new();
+    if request.as_reader().read_to_string(&mut body).is_err() {
+        return ErrorInfo::new(ErrorCode::InvalidPayload, "The body must be UTF-8 JSON").into();
+    }
+
+    let method = request.method().clone();
+    route(cmd_handler, &method, request.url(), &body)
+}
+
+fn route(cmd_handler: &ServerCommandHandler, method: &Method, url: &str, body: &str) -> ApiResponse {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (Method::Get, ["api", "users"]) => {
+            let query = query_parameter(query, "query");
+            let users = cmd_handler.find_users(query.as_deref());
+            ApiResponse::ok(json!({ "users": user_views(cmd_handler, users) }))
+        }
+        (Method::Post, ["api", "users"]) => match parse_body(body) {
+            Ok(create_user) => create_account(cmd_handler, create_user),
+            Err(response) => response
+        }
+        (Method::Get, ["api", "users", username]) => match cmd_handler.find_user(&percent_decode(username)) {
+            Some(user) => ApiResponse::ok(json!(user_view(cmd_handler, user))),
+            None => ErrorCode::UserNotFound.into()
+        }
+        (Method::Get, ["api", "presence"]) => {
+            let online = cmd_handler.find_users(None)
+                .into_iter()
+                .filter(|user| cmd_handler.is_online(user.id))
+                .collect();
+            ApiResponse::ok(json!({ "online": user_views(cmd_handler, online) }))
+        }
+        (Method::Post, ["api", "messages"]) => match parse_body(body) {
+            Ok(post_message) => post_message_as_bot(cmd_handler, post_message),
+            Err(response) => response
+        }
+        (Method::Get, ["api", "schemas"]) => {
+            let names: Vec<&str> = SCHEMAS.iter().map(|(name, _)| *name).collect();
+            ApiResponse::ok(json!({ "schemas": names }))
+        }
+        (Method::Get, ["api", "schemas", name]) => SCHEMAS.iter()
+            .find(|(schema_name, _)| schema_name == name)
+            .and_then(|(_, schema)| serde_json::from_str(schema).ok())
+            .map(ApiResponse::ok)
+            .unwrap_or_else(|| ErrorInfo::new(ErrorCode::UnknownCommand, "Unknown schema").into()),
+        _ => ErrorInfo::new(ErrorCode::UnknownCommand, format!("No route for {method} {path}")).into()
+    }
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(body: &str) -> Result<T, ApiResponse> {
+    serde_json::from_str(body)
+        .map_err(|e| ErrorInfo::from(ErrorCode::InvalidPayload).with_detail(e.to_string()).into())
+}
+
+fn create_account(cmd_handler: &ServerCommandHandler, create_user: CreateUser) -> ApiResponse {
+    let username = create_user.username.clone();
+    let user_register_data = UserRegisterData {
+        nickname: create_user.nickname.unwrap_or_else(|| create_user.username.clone()),
+        username: create_user.username,
+        password: create_user.password,
+        bot: create_user.bot,
+    };
+
+    match cmd_handler.handle_register_cmd(user_register_data).into_notification() {
+        Notification::Error(error) => error.into(),
+        _ => match cmd_handler.find_user(&username) {
+            Some(user) => ApiResponse::created(json!(user_view(cmd_handler, user))),
+            None => ErrorCode::Internal.into()
+        }
+    }
+}
+
+fn post_message_as_bot(cmd_handler: &ServerCommandHandler, post_message: PostMessage) -> ApiResponse {
+    let Some(sender) = cmd_handler.find_user(&post_message.sender) else {
+        return ErrorCode::UserNotFound.into();
+    };
+
+    if !sender.bot {
+        return ErrorInfo::new(ErrorCode::NotAuthenticated, "Only bot accounts can post through the API").into();
+    }
+
+    let Some(recipient) = cmd_handler.find_user(&post_message.recipient) else {
+        return ErrorCode::UserNotFound.into();
+    };
+
+    let message = OutgoingMessage {
+        recipient: recipient.id,
+        content: post_message.content,
+    };
+
+    match cmd_handler.send_message_as(sender.id, message).into_notification() {
+        Notification::Error(error) => error.into(),
+        _ => ApiResponse::created(json!({ "delivered": true }))
+    }
+}
+
+fn user_view(cmd_handler: &ServerCommandHandler, user: User) -> UserView {
+    UserView {
+        id: user.id.get(),
+        online: cmd_handler.is_online(user.id),
+        username: user.username,
+        nickname: user.nickname,
+        bot: user.bot,
+    }
+}
+
+fn user_views(cmd_handler: &ServerCommandHandler, users: Vec<User>) -> Vec<UserView> {
+    users.into_iter()
+        .map(|user| user_view(cmd_handler, user))
+        .collect()
+}
+
+fn query_parameter(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::auth::AuthConfig;
+
+    use super::*;
+
+    fn request(cmd_handler: &ServerCommandHandler, method: Method, url: &str, body: Value) -> (u16, Value) {
+        let response = route(cmd_handler, &method, url, &body.to_string());
+        (response.status, response.body)
+    }
+
+    #[test]
+    fn test_accounts_and_messages() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+
+        let (status, user) = request(&cmd_handler, Method::Post, "/api/users", json!({ "username": "deploy-bot", "password": "s3cret-pass", "bot": true }));
+        assert_eq!(status, 201);
+        assert_eq!(user["nickname"], "deploy-bot");
+        assert_eq!(user["online"], false);
+
+        let (status, error) = request(&cmd_handler, Method::Post, "/api/users", json!({ "username": "deploy-bot", "password": "s3cret-pass" }));
+        assert_eq!((status, error["code"].as_u64()), (409, Some(200)));
+
+        let (status, _) = request(&cmd_handler, Method::Post, "/api/users", json!({ "username": "alice", "password": "s3cret-pass", "admin": true }));
+        assert_eq!(status, 400);
+
+        let (status, error) = request(&cmd_handler, Method::Post, "/api/users", json!({ "username": "alice", "password": "secret" }));
+        assert_eq!((status, error["code"].as_u64()), (400, Some(207)));
+
+        request(&cmd_handler, Method::Post, "/api/users", json!({ "username": "alice", "nickname": "Alice", "password": "s3cret-pass" }));
+
+        let (_, users) = request(&cmd_handler, Method::Get, "/api/users?query=ALI", Value::Null);
+        assert_eq!(users["users"].as_array().unwrap().len(), 1);
+        assert_eq!(users["users"][0]["username"], "alice");
+
+        let (status, _) = request(&cmd_handler, Method::Get, "/api/users/bob", Value::Null);
+        assert_eq!(status, 404);
+
+        let (status, _) = request(&cmd_handler, Method::Post, "/api/messages", json!({ "sender": "alice", "recipient": "deploy-bot", "content": "hi" }));
+        assert_eq!(status, 401);
+
+        let (status, error) = request(&cmd_handler, Method::Post, "/api/messages", json!({ "sender": "deploy-bot", "recipient": "alice", "content": "hi" }));
+        assert_eq!((status, error["code"].as_u64()), (409, Some(300)));
+
+        let (_, presence) = request(&cmd_handler, Method::Get, "/api/presence", Value::Null);
+        assert_eq!(presence["online"], json!([]));
+    }
+
+    /// Checks the keywords used by the schemas of the API, `$ref` names another of them.
+    fn schema_violation(schema: &Value, value: &Value) -> Option<String> {
+        if let Some(name) = schema["$ref"].as_str() {
+            return schema_violation(&named_schema(name), value);
+        }
+
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(schema_type) => vec![schema_type.as_str()],
+            Value::Array(schema_types) => schema_types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let type_matches = |schema_type: &str| match schema_type {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "string" => value.is_string(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        };
+        if !types.is_empty() && !types.iter().any(|schema_type| type_matches(schema_type)) {
+            return Some(format!("{value} is not of type {types:?}"));
+        }
+
+        if let (Some(minimum), Some(number)) = (schema["minimum"].as_f64(), value.as_f64()) {
+            if number < minimum {
+                return Some(format!("{value} is below {minimum}"));
+            }
+        }
+
+        if let Some(items) = value.as_array() {
+            return items.iter().find_map(|item| schema_violation(&schema["items"], item));
+        }
+
+        let fields = value.as_object()?;
+        let properties = schema["properties"].as_object();
+        if let Some(required) = schema["required"].as_array() {
+            if let Some(missing) = required.iter().filter_map(Value::as_str).find(|field| !fields.contains_key(*field)) {
+                return Some(format!("missing field {missing}"));
+            }
+        }
+
+        fields.iter().find_map(|(field, field_value)| match properties.and_then(|properties| properties.get(field)) {
+            Some(field_schema) => schema_violation(field_schema, field_value).map(|violation| format!("{field}: {violation}")),
+            None if schema["additionalProperties"] == Value::Bool(false) => Some(format!("unknown field {field}")),
+            None => None
+        })
+    }
+
+    fn named_schema(name: &str) -> Value {
+        let (_, schema) = SCHEMAS.iter().find(|(schema_name, _)| *schema_name == name).unwrap();
+        serde_json::from_str(schema).unwrap()
+    }
+
+    fn assert_matches_schema(name: &str, value: &Value) {
+        if let Some(violation) = schema_violation(&named_schema(name), value) {
+            panic!("{value} doesn't match the {name} schema: {violation}");
+        }
+    }
+
+    #[test]
+    fn test_responses_match_schemas() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+
+        let create_user = json!({ "username": "deploy-bot", "nickname": "Deploy", "password": "s3cret-pass", "bot": true });
+        assert_matches_schema("create_user", &create_user);
+        let (_, user) = request(&cmd_handler, Method::Post, "/api/users", create_user);
+        assert_matches_schema("user", &user);
+
+        let (_, users) = request(&cmd_handler, Method::Get, "/api/users", Value::Null);
+        assert_matches_schema("user_list", &users);
+        let (_, presence) = request(&cmd_handler, Method::Get, "/api/presence", Value::Null);
+        assert_matches_schema("presence", &presence);
+
+        let post_message = json!({ "sender": "deploy-bot", "recipient": "alice", "content": "hi" });
+        assert_matches_schema("post_message", &post_message);
+        let (_, error) = request(&cmd_handler, Method::Post, "/api/messages", post_message);
+        assert_matches_schema("error", &error);
+
+        let rate_limited = ErrorInfo::new(ErrorCode::RateLimited, "Too many requests").with_retry_after(Duration::from_secs(2));
+        let error = ApiResponse::from(rate_limited).body;
+        assert_matches_schema("error", &error);
+        assert_eq!(error["retry_after_ms"], 2000);
+
+        // The check does catch the fields the schemas don't list
+        assert!(schema_violation(&named_schema("user"), &json!({ "id": 1, "username": "alice", "nickname": "alice", "bot": false, "online": true, "admin": true })).is_some());
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let config = AdminApiConfig { port: 0, api_keys: vec![String::from("s3cret-key")] };
+        assert!(accepts_key(&config, Some("s3cret-key")));
+        assert!(!accepts_key(&config, Some("s3cret")));
+        assert!(!accepts_key(&config, None));
+
+        // An empty header never matches, even with an empty key configured
+        let config = AdminApiConfig { port: 0, api_keys: vec![String::new()] };
+        assert!(!accepts_key(&config, Some("")));
+
+        let cmd_handler = Arc::new(ServerCommandHandler::new(AuthConfig::default()));
+        assert!(run_admin_api(cmd_handler, IpAddr::from([127, 0, 0, 1]), config).is_err());
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("deploy%2Dbot+one%"), "deploy-bot one%");
+    }
+}
@@ -0,0 +1,91 @@
+use mxchat_core::{auth::UserConnectData, error::{ErrorCode, ErrorInfo}, validation::{ValidationError, ValidationPolicy}};
+
+use crate::user::UserData;
+
+pub use htpasswd::HtpasswdAuthenticator;
+pub use ldap::{LdapAuthenticator, LdapConfig};
+pub use second_factor::{SecondFactor, SecondFactorError, TotpSecret};
+
+mod htpasswd;
+mod ldap;
+mod second_factor;
+
+/// Backend name of the accounts registered on this server.
+pub const LOCAL_BACKEND: &str = "local";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// The backend does not know the username, the next one is tried.
+    UserNotFound,
+    PasswordIncorrect,
+    /// The backend could not be reached or failed, the reason is only logged.
+    Unavailable(String),
+    /// The backend knows the username, but this server does not accept it.
+    InvalidUsername(ValidationError),
+}
+
+impl From<AuthError> for ErrorInfo {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::UserNotFound => ErrorCode::UserNotFound.into(),
+            AuthError::PasswordIncorrect => ErrorCode::PasswordIncorrect.into(),
+            AuthError::Unavailable(_) => ErrorInfo::new(ErrorCode::Internal, "Authentication service unavailable"),
+            AuthError::InvalidUsername(error) => error.into(),
+        }
+    }
+}
+
+/// What a backend knows about an account once the password is checked.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub nickname: String,
+}
+
+/// Checks the credentials of `Connect` commands. Accounts of external backends are added
+/// to the user repository the first time they log in.
+pub trait Authenticator: Send + Sync {
+    /// Recorded on the users this backend authenticated, see [`UserData::backend`].
+    fn backend(&self) -> &'static str;
+
+    /// `account` is the user already recorded for this backend with the username, if any.
+    fn authenticate(&self, credentials: &UserConnectData, account: Option<&UserData>) -> Result<Identity, AuthError>;
+}
+
+/// Passwords of the accounts registered on the server.
+pub struct LocalAuthenticator;
+
+impl Authenticator for LocalAuthenticator {
+    fn backend(&self) -> &'static str {
+        LOCAL_BACKEND
+    }
+
+    fn authenticate(&self, credentials: &UserConnectData, account: Option<&UserData>) -> Result<Identity, AuthError> {
+        let account = account.ok_or(AuthError::UserNotFound)?;
+
+        if account.password.as_deref() == Some(credentials.password.as_str()) {
+            Ok(Identity { nickname: account.user.nickname.clone() })
+        }
+        else {
+            Err(AuthError::PasswordIncorrect)
+        }
+    }
+}
+
+pub struct AuthConfig {
+    /// Tried in order until one of them knows the username.
+    pub authenticators: Vec<Box<dyn Authenticator>>,
+    /// `Register` commands are refused when the accounts come from an external directory.
+    pub registration_enabled: bool,
+    /// Checks the usernames, nicknames and passwords of the local accounts.
+    pub validation: ValidationPolicy,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            authenticators: vec![Box::new(LocalAuthenticator)],
+            registration_enabled: true,
+            validation: ValidationPolicy::default(),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mxchat_core::{auth::UserConnectData, validation::username_key};
+use sha1::{Digest, Sha1};
+
+use crate::user::UserData;
+
+use super::{AuthError, Authenticator, Identity};
+
+/// Accounts of an htpasswd file, read once at startup. Hashes made by `htpasswd -B`
+/// (bcrypt), `-s` (`{SHA}`) and the crypt formats `$1$`, `$5$` and `$6$` are supported,
+/// the default `$apr1$` of older htpasswd versions is not.
+pub struct HtpasswdAuthenticator {
+    /// Keyed by [`username_key`], as usernames are matched by the user repository.
+    hashes: HashMap<String, String>,
+}
+
+impl HtpasswdAuthenticator {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        fs::read_to_string(path).map(|contents| Self::parse(&contents))
+    }
+
+    /// Reads the `username:hash` lines, ignoring blank lines and `#` comments.
+    pub fn parse(contents: &str) -> Self {
+        let hashes = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .filter(|(username, hash)| {
+                let supported = !hash.starts_with("$apr1$");
+                if !supported {
+                    println!("Ignoring htpasswd entry of {username}, $apr1$ hashes are not supported");
+                }
+                supported
+            })
+            .map(|(username, hash)| (username_key(username), hash.to_string()))
+            .collect();
+
+        Self { hashes }
+    }
+}
+
+impl Authenticator for HtpasswdAuthenticator {
+    fn backend(&self) -> &'static str {
+        "htpasswd"
+    }
+
+    fn authenticate(&self, credentials: &UserConnectData, _account: Option<&UserData>) -> Result<Identity, AuthError> {
+        let hash = self.hashes
+            .get(&username_key(&credentials.username))
+            .ok_or(AuthError::UserNotFound)?;
+
+        if verify(&credentials.password, hash) {
+            Ok(Identity { nickname: credentials.username.clone() })
+        }
+        else {
+            Err(AuthError::PasswordIncorrect)
+        }
+    }
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    match hash.strip_prefix("{SHA}") {
+        Some(digest) => STANDARD.encode(Sha1::digest(password.as_bytes())) == digest,
+        None => pwhash::unix::verify(password, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(username: &str, password: &str) -> UserConnectData {
+        UserConnectData {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn test_htpasswd_hashes() {
+        let contents = format!(
+            "# accounts\nalice:{}\nbob:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n\ncarol:$1$5pZSV9va$azfrPr6af3Fc7dLblQXVa0\ndave:$apr1$x$y\n",
+            pwhash::bcrypt::hash("secret").unwrap()
+        );
+        let authenticator = HtpasswdAuthenticator::parse(&contents);
+
+        assert_eq!(authenticator.authenticate(&credentials("alice", "secret"), None), Ok(Identity { nickname: "alice".into() }));
+        assert_eq!(authenticator.authenticate(&credentials("alice", "password"), None), Err(AuthError::PasswordIncorrect));
+        assert!(authenticator.authenticate(&credentials("bob", "password"), None).is_ok());
+        assert!(authenticator.authenticate(&credentials("carol", "password"), None).is_ok());
+        assert_eq!(authenticator.authenticate(&credentials("dave", "password"), None), Err(AuthError::UserNotFound));
+
+        // Usernames match whatever their case, as in the user repository
+        assert!(authenticator.authenticate(&credentials("Bob", "password"), None).is_ok());
+        assert!(HtpasswdAuthenticator::parse("Erin:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=").authenticate(&credentials("erin", "password"), None).is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use ldap3::{dn_escape, LdapConn, LdapConnSettings, LdapError, Scope, SearchEntry};
+use mxchat_core::auth::UserConnectData;
+
+use crate::user::UserData;
+
+use super::{AuthError, Authenticator, Identity};
+
+/// Result codes of RFC 4511 telling the username or the password apart.
+const NO_SUCH_OBJECT: u32 = 32;
+const INVALID_CREDENTIALS: u32 = 49;
+
+pub struct LdapConfig {
+    /// Such as `ldap://127.0.0.1:389`.
+    pub url: String,
+    /// DN bound with the password of the user, `{username}` being replaced by the escaped
+    /// username, such as `uid={username},ou=people,dc=example,dc=org`.
+    pub bind_dn: String,
+    /// Attribute of the user entry used as nickname, the username when it is missing.
+    pub nickname_attribute: String,
+    pub timeout: Duration,
+}
+
+/// Checks the passwords with a simple bind as the user. Most directories answer
+/// unknown DNs with invalid credentials, so the backends after this one are only
+/// tried when the directory reports the entry as missing.
+pub struct LdapAuthenticator {
+    config: LdapConfig,
+}
+
+impl LdapAuthenticator {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    fn nickname(&self, connection: &mut LdapConn, dn: &str) -> Option<String> {
+        let attribute = self.config.nickname_attribute.as_str();
+
+        let (entries, _) = connection
+            .with_timeout(self.config.timeout)
+            .search(dn, Scope::Base, "(objectClass=*)", vec![attribute])
+            .and_then(|result| result.success())
+            .ok()?;
+
+        let entry = SearchEntry::construct(entries.into_iter().next()?);
+
+        // Directories may return the attribute name with another case
+        entry.attrs
+            .into_iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+            .and_then(|(_, values)| values.into_iter().next())
+    }
+}
+
+impl Authenticator for LdapAuthenticator {
+    fn backend(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate(&self, credentials: &UserConnectData, _account: Option<&UserData>) -> Result<Identity, AuthError> {
+        // An empty password makes an unauthenticated bind, which succeeds on most directories
+        if credentials.password.is_empty() {
+            return Err(AuthError::PasswordIncorrect);
+        }
+
+        let dn = self.config.bind_dn.replace("{username}", &dn_escape(credentials.username.as_str()));
+
+        let settings = LdapConnSettings::new().set_conn_timeout(self.config.timeout);
+        let mut connection = LdapConn::with_settings(settings, &self.config.url).map_err(unavailable)?;
+
+        let result = connection
+            .with_timeout(self.config.timeout)
+            .simple_bind(&dn, &credentials.password)
+            .map_err(unavailable)?;
+
+        match result.rc {
+            0 => (),
+            NO_SUCH_OBJECT => return Err(AuthError::UserNotFound),
+            INVALID_CREDENTIALS => return Err(AuthError::PasswordIncorrect),
+            _ => return Err(AuthError::Unavailable(format!("LDAP bind of {dn} failed: {result}")))
+        }
+
+        let nickname = self.nickname(&mut connection, &dn)
+            .unwrap_or_else(|| credentials.username.clone());
+        let _ = connection.unbind();
+
+        Ok(Identity { nickname })
+    }
+}
+
+fn unavailable(error: LdapError) -> AuthError {
+    AuthError::Unavailable(format!("LDAP server unavailable: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, thread};
+
+    use super::*;
+
+    const ALICE_DN: &[u8] = b"uid=alice,ou=people,dc=example,dc=org";
+
+    /// Encodes a BER element, the length in short or long form.
+    fn element(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![tag];
+        let length = content.len().to_be_bytes();
+
+        if content.len() < 0x80 {
+            bytes.push(content.len() as u8);
+        }
+        else {
+            let length: Vec<u8> = length.into_iter().skip_while(|byte| *byte == 0).collect();
+            bytes.push(0x80 | length.len() as u8);
+            bytes.extend(length);
+        }
+
+        bytes.extend(content);
+        bytes
+    }
+
+    fn read_length(mut next_byte: impl FnMut() -> Option<u8>) -> Option<usize> {
+        let first = next_byte()?;
+        if first < 0x80 {
+            return Some(first as usize);
+        }
+
+        (0..first & 0x7f).try_fold(0, |length, _| Some(length << 8 | next_byte()? as usize))
+    }
+
+    fn read_element(socket: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut next_byte = || {
+            let mut byte = [0];
+            socket.read_exact(&mut byte).ok().map(|_| byte[0])
+        };
+
+        let tag = next_byte()?;
+        let length = read_length(&mut next_byte)?;
+
+        let mut content = vec![0; length];
+        socket.read_exact(&mut content).ok()?;
+        Some((tag, content))
+    }
+
+    fn split_elements(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut elements = Vec::new();
+
+        while let Some((&tag, rest)) = bytes.split_first() {
+            let mut rest = rest.iter().copied();
+            let length = read_length(|| rest.next()).unwrap();
+            let content: Vec<u8> = rest.by_ref().take(length).collect();
+
+            bytes = &bytes[bytes.len() - rest.len()..];
+            elements.push((tag, content));
+        }
+
+        elements
+    }
+
+    fn ldap_result(code: u8) -> Vec<u8> {
+        [element(0x0a, &[code]), element(0x04, b""), element(0x04, b"")].concat()
+    }
+
+    /// Answers binds as alice with `secret`, refuses the other passwords of alice and
+    /// reports the other DNs as missing. Searches return a `displayName`.
+    fn serve(mut socket: TcpStream) {
+        while let Some((_, message)) = read_element(&mut socket) {
+            let parts = split_elements(&message);
+            let message_id = element(0x02, &parts[0].1);
+            let (operation, content) = &parts[1];
+
+            let responses = match operation {
+                0x60 => {
+                    let fields = split_elements(content);
+                    let code = match (fields[1].1.as_slice(), fields[2].1.as_slice()) {
+                        (ALICE_DN, b"secret") => 0,
+                        (ALICE_DN, _) => INVALID_CREDENTIALS as u8,
+                        _ => NO_SUCH_OBJECT as u8
+                    };
+                    vec![element(0x61, &ldap_result(code))]
+                }
+                0x63 => {
+                    let value = element(0x31, &element(0x04, b"Alice Liddell"));
+                    let attribute = element(0x30, &[element(0x04, b"displayName"), value].concat());
+                    let entry = element(0x64, &[element(0x04, ALICE_DN), element(0x30, &attribute)].concat());
+                    vec![entry, element(0x65, &ldap_result(0))]
+                }
+                _ => return
+            };
+
+            for response in responses {
+                let message = element(0x30, &[message_id.clone(), response].concat());
+                if socket.write_all(&message).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn spawn_stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for socket in listener.incoming().flatten() {
+                thread::spawn(move || serve(socket));
+            }
+        });
+
+        url
+    }
+
+    fn authenticate(url: &str, username: &str, password: &str) -> Result<Identity, AuthError> {
+        let authenticator = LdapAuthenticator::new(LdapConfig {
+            url: url.to_string(),
+            bind_dn: String::from("uid={username},ou=people,dc=example,dc=org"),
+            nickname_attribute: String::from("displayName"),
+            timeout: Duration::from_secs(5),
+        });
+
+        let credentials = UserConnectData {
+            username: username.into(),
+            password: password.into(),
+        };
+
+        authenticator.authenticate(&credentials, None)
+    }
+
+    #[test]
+    fn test_bind() {
+        let url = spawn_stand_in();
+
+        assert_eq!(authenticate(&url, "alice", "secret"), Ok(Identity { nickname: "Alice Liddell".into() }));
+        assert_eq!(authenticate(&url, "alice", "guess"), Err(AuthError::PasswordIncorrect));
+        assert_eq!(authenticate(&url, "alice", ""), Err(AuthError::PasswordIncorrect));
+        assert_eq!(authenticate(&url, "bob", "secret"), Err(AuthError::UserNotFound));
+    }
+
+    #[test]
+    fn test_unreachable_directory() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        assert!(matches!(authenticate(&url, "alice", "secret"), Err(AuthError::Unavailable(_))));
+    }
+}
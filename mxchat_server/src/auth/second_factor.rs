@@ -0,0 +1,276 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use mxchat_core::{auth::SecondFactorEnrollment, error::{ErrorCode, ErrorInfo}};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+const ISSUER: &str = "mxchat";
+const SECRET_LENGTH: usize = 20;
+const PERIOD: u64 = 30;
+const DIGITS: usize = 6;
+/// Codes of the periods around the current one are accepted too, for clocks drifting apart.
+const ALLOWED_DRIFT: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const LOCKOUT_DURATION: Duration = Duration::from_secs(300);
+
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SecondFactorError {
+    Incorrect,
+    /// Too many incorrect codes, the account refuses codes for the duration.
+    Locked(Duration),
+}
+
+impl From<SecondFactorError> for ErrorInfo {
+    fn from(value: SecondFactorError) -> Self {
+        match value {
+            SecondFactorError::Incorrect => ErrorCode::SecondFactorIncorrect.into(),
+            SecondFactorError::Locked(retry_after) => ErrorInfo::new(
+                ErrorCode::RateLimited,
+                format!("Too many incorrect codes, retry in {} seconds", retry_after.as_secs().max(1))
+            ).with_retry_after(retry_after),
+        }
+    }
+}
+
+/// Shared secret of RFC 6238 codes, HMAC-SHA1 over 30 second periods with 6 digits,
+/// the defaults of the authenticator apps.
+#[derive(Clone)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        Self(random_bytes(SECRET_LENGTH))
+    }
+
+    pub fn enrollment(&self, username: &str) -> SecondFactorEnrollment {
+        let secret = base32::encode(BASE32, &self.0);
+        let label = percent_encode(&format!("{ISSUER}:{username}"));
+
+        SecondFactorEnrollment {
+            provisioning_uri: format!("otpauth://totp/{label}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}"),
+            secret,
+        }
+    }
+
+    fn code(&self, step: u64) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.0)
+            .expect("HMAC takes keys of any size");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+        format!("{:0width$}", binary % 10u32.pow(DIGITS as u32), width = DIGITS)
+    }
+
+    #[cfg(test)]
+    pub fn current_code(&self) -> String {
+        self.code(unix_time() / PERIOD)
+    }
+
+    /// Returns the period of the code, among the ones around `unix_time`.
+    fn matching_step(&self, code: &str, unix_time: u64) -> Option<u64> {
+        let current_step = unix_time / PERIOD;
+
+        (current_step.saturating_sub(ALLOWED_DRIFT)..=current_step + ALLOWED_DRIFT)
+            .find(|step| self.code(*step) == code)
+    }
+}
+
+/// Second factor of an enrolled account: its TOTP secret and the hashes of the recovery
+/// codes not used yet.
+#[derive(Clone)]
+pub struct SecondFactor {
+    secret: TotpSecret,
+    recovery_codes: Vec<[u8; 32]>,
+    /// Period of the last accepted code, so a code cannot be replayed.
+    last_step: Option<u64>,
+    failed_attempts: u32,
+    locked_until: Option<Instant>,
+}
+
+impl SecondFactor {
+    /// Enrolls once `code` proves the authenticator app has the secret, returns the
+    /// recovery codes to hand to the user, they are only kept hashed.
+    pub fn enroll(secret: TotpSecret, code: &str) -> Result<(Self, Vec<String>), SecondFactorError> {
+        let code = normalize(code);
+        let step = secret.matching_step(&code, unix_time())
+            .ok_or(SecondFactorError::Incorrect)?;
+
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+
+        let second_factor = Self {
+            secret,
+            recovery_codes: recovery_codes.iter().map(|code| hash(&normalize(code))).collect(),
+            last_step: Some(step),
+            failed_attempts: 0,
+            locked_until: None,
+        };
+
+        Ok((second_factor, recovery_codes))
+    }
+
+    /// Accepts a code of the authenticator app or consumes a recovery code.
+    pub fn verify(&mut self, code: &str) -> Result<(), SecondFactorError> {
+        self.verify_at(code, unix_time(), Instant::now())
+    }
+
+    fn verify_at(&mut self, code: &str, unix_time: u64, now: Instant) -> Result<(), SecondFactorError> {
+        if let Some(locked_until) = self.locked_until.filter(|locked_until| *locked_until > now) {
+            return Err(SecondFactorError::Locked(locked_until - now));
+        }
+
+        let code = normalize(code);
+        let accepted = if code.len() == DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+            self.accept_totp_code(&code, unix_time)
+        }
+        else {
+            self.accept_recovery_code(&code)
+        };
+
+        if accepted {
+            self.failed_attempts = 0;
+            return Ok(());
+        }
+
+        self.failed_attempts += 1;
+        if self.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            self.failed_attempts = 0;
+            self.locked_until = Some(now + LOCKOUT_DURATION);
+        }
+
+        Err(SecondFactorError::Incorrect)
+    }
+
+    fn accept_totp_code(&mut self, code: &str, unix_time: u64) -> bool {
+        let step = self.secret
+            .matching_step(code, unix_time)
+            .filter(|step| self.last_step.is_none_or(|last_step| *step > last_step));
+
+        if step.is_some() {
+            self.last_step = step;
+        }
+
+        step.is_some()
+    }
+
+    fn accept_recovery_code(&mut self, code: &str) -> bool {
+        let code_hash = hash(code);
+        let count = self.recovery_codes.len();
+        self.recovery_codes.retain(|recovery_code| *recovery_code != code_hash);
+
+        self.recovery_codes.len() < count
+    }
+}
+
+/// Codes are compared without their separators and case, as users type them.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn hash(code: &str) -> [u8; 32] {
+    Sha256::digest(code.as_bytes()).into()
+}
+
+/// Ten base32 characters split in two groups, such as `k3vq7-m2xha`.
+fn generate_recovery_code() -> String {
+    let code = base32::encode(BASE32, &random_bytes(RECOVERY_CODE_LENGTH))
+        .to_ascii_lowercase();
+    let (first, second) = code[..RECOVERY_CODE_LENGTH].split_at(RECOVERY_CODE_LENGTH / 2);
+
+    format!("{first}-{second}")
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    getrandom::fill(&mut bytes).expect("the system provides random numbers");
+    bytes
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn test_rfc_6238_codes() {
+        let secret = rfc_secret();
+
+        assert_eq!(secret.code(59 / PERIOD), "287082");
+        assert_eq!(secret.code(1111111109 / PERIOD), "081804");
+        assert_eq!(secret.code(2000000000 / PERIOD), "279037");
+
+        let enrollment = secret.enrollment("alice smith");
+        assert_eq!(enrollment.secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/mxchat%3Aalice%20smith?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=mxchat"));
+    }
+
+    #[test]
+    fn test_codes_are_accepted_once() {
+        let secret = rfc_secret();
+        let (mut second_factor, recovery_codes) = SecondFactor::enroll(secret.clone(), &secret.current_code()).unwrap();
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+
+        let time = unix_time() + 4 * PERIOD;
+        let now = Instant::now();
+        let code = secret.code(time / PERIOD);
+
+        assert_eq!(second_factor.verify_at(&code, time, now), Ok(()));
+        assert_eq!(second_factor.verify_at(&code, time, now), Err(SecondFactorError::Incorrect));
+
+        let recovery_code = recovery_codes[0].to_uppercase().replace('-', " ");
+        assert_eq!(second_factor.verify_at(&recovery_code, time, now), Ok(()));
+        assert_eq!(second_factor.verify_at(&recovery_codes[0], time, now), Err(SecondFactorError::Incorrect));
+        assert_eq!(second_factor.verify_at(&recovery_codes[1], time, now), Ok(()));
+    }
+
+    #[test]
+    fn test_attempts_are_limited() {
+        let secret = rfc_secret();
+        let (mut second_factor, _) = SecondFactor::enroll(secret.clone(), &secret.current_code()).unwrap();
+
+        let time = unix_time() + 4 * PERIOD;
+        let now = Instant::now();
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert_eq!(second_factor.verify_at("000000", time, now), Err(SecondFactorError::Incorrect));
+        }
+
+        let code = secret.code(time / PERIOD);
+        assert_eq!(second_factor.verify_at(&code, time, now), Err(SecondFactorError::Locked(LOCKOUT_DURATION)));
+        assert_eq!(second_factor.verify_at(&code, time, now + LOCKOUT_DURATION), Ok(()));
+    }
+}
@@ -1,55 +1,88 @@
-use std::{io::Read, net::TcpStream, sync::Arc};
+use std::{net::TcpStream, sync::Arc};
 
-use mxchat_core::{auth::{UserConnectData, UserRegisterData}, command::Command, io::BytesBuffer, utils::bytes_as_u32};
+use mxchat_core::{auth::{PasswordChange, PrivacySettings, UserConnectData, UserId, UserRegisterData}, command::{Command, CommandParsingError}, error::{ErrorCode, ErrorInfo}, io::{read_frame, BytesBuffer, FrameError, ProtocolFeatures}, messaging::{OutgoingMessage, UserSearch}, notification::Notification, request::RequestId};
 
-use crate::server::{ServerConnectionData, ServerError, ServerResponse};
+use crate::server::{ConnectionWriter, ServerConnectionData, ServerError, ServerResponse};
 
 pub trait CommandHandler: Send + Sync {
     fn handle_register_cmd(&self, user_register_data: UserRegisterData) -> ServerResponse;
     fn handle_connect_cmd(&self, user_connect_data: UserConnectData, connection_data: &mut ServerConnectionData) -> ServerResponse;
-    fn handle_request_contact_cmd(&self, username: &str) -> ServerResponse;
+    fn handle_request_contact_cmd(&self, username: &str, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_send_message_cmd(&self, message: OutgoingMessage, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_enable_second_factor_cmd(&self, password: &str, connection_data: &mut ServerConnectionData) -> ServerResponse;
+    fn handle_confirm_second_factor_cmd(&self, code: &str, connection_data: &mut ServerConnectionData) -> ServerResponse;
+    fn handle_second_factor_cmd(&self, code: &str, connection_data: &mut ServerConnectionData) -> ServerResponse;
+    fn handle_change_password_cmd(&self, password_change: PasswordChange, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_change_nickname_cmd(&self, nickname: &str, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_delete_account_cmd(&self, password: &str, connection_data: &mut ServerConnectionData) -> ServerResponse;
+    fn handle_search_users_cmd(&self, search: UserSearch, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_request_privacy_settings_cmd(&self, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_update_privacy_settings_cmd(&self, settings: PrivacySettings, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_block_cmd(&self, blocked_id: UserId, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_unblock_cmd(&self, blocked_id: UserId, connection_data: &ServerConnectionData) -> ServerResponse;
+    fn handle_request_blocked_users_cmd(&self, connection_data: &ServerConnectionData) -> ServerResponse;
+    /// Called when the connection which logged `user_id` in, writing to `writer`, is closed.
+    fn handle_disconnect(&self, user_id: UserId, writer: &ConnectionWriter);
 }
 
 pub type CommandHandlerRef = Arc<dyn CommandHandler>;
 
 
-pub fn fetch_command(socket: &mut TcpStream) -> Result<Command, ServerError> {
-    let mut data_bytes = read_command_data(socket)?;
-
-    Command::from_bytes(&mut data_bytes)
-        .map_err(|e| ServerError::CommandParsingError(e))
+/// Read half of a connection, whatever carries the frames.
+pub trait FrameSource: Send {
+    fn read_frame(&mut self, max_frame_size: usize) -> Result<(Option<RequestId>, BytesBuffer), FrameError>;
 }
 
-pub fn handle_command(cmd: Command, command_handler: &CommandHandlerRef, connection_data: &mut ServerConnectionData) -> ServerResponse {
-    match cmd {
-        Command::Register(user_register_data) => command_handler.handle_register_cmd(user_register_data),
-        Command::Connect(user_connect_data) => command_handler.handle_connect_cmd(user_connect_data, connection_data),
-        Command::RequestContact(username) => command_handler.handle_request_contact_cmd(&username),
+impl FrameSource for TcpStream {
+    fn read_frame(&mut self, max_frame_size: usize) -> Result<(Option<RequestId>, BytesBuffer), FrameError> {
+        read_frame(self, max_frame_size)
     }
 }
 
-fn read_command_data(socket: &mut TcpStream) -> Result<BytesBuffer, ServerError> {
-
-    let mut byte = [0u8; 1];
-    if socket.read(&mut byte)? == 0 {
-        return Err(ServerError::MissingCommandData);
-    }
-
-    let mut length_bytes = [0u8; 4];
-    if socket.read(&mut length_bytes)? == 0 {
-        return Err(ServerError::MissingCommandData);
-    }
+pub struct CommandFrame {
+    pub request_id: Option<RequestId>,
+    pub command: Result<Command, CommandParsingError>,
+}
 
-    let payload_length = bytes_as_u32(&length_bytes);
+pub fn fetch_command(source: &mut dyn FrameSource, max_frame_size: usize) -> Result<CommandFrame, ServerError> {
+    let (request_id, mut data_bytes) = source.read_frame(max_frame_size)?;
 
-    let mut payload_bytes = vec![0u8; payload_length as usize];
-    if socket.read(&mut payload_bytes)? == 0 {
-        return Err(ServerError::MissingCommandData);
-    }
+    Ok(CommandFrame {
+        request_id,
+        command: Command::from_bytes(&mut data_bytes)
+    })
+}
 
-    let mut bytes_buffer = BytesBuffer::empty();
-    bytes_buffer.write_bytes(&byte);
-    bytes_buffer.write_bytes(&payload_bytes);
+pub fn handle_command(cmd: Command, command_handler: &CommandHandlerRef, connection_data: &mut ServerConnectionData, server_features: ProtocolFeatures) -> Option<ServerResponse> {
+    let server_response = match cmd {
+        // Bots get larger rate limits, so their accounts are only created through the admin API
+        Command::Register(user_register_data) if user_register_data.bot =>
+            ErrorInfo::new(ErrorCode::InvalidAccountData, "Bot accounts are created by the server administrators").into(),
+        Command::Register(user_register_data) => command_handler.handle_register_cmd(user_register_data),
+        Command::Connect(user_connect_data) => command_handler.handle_connect_cmd(user_connect_data, connection_data),
+        Command::RequestContact(username) => command_handler.handle_request_contact_cmd(&username, connection_data),
+        Command::SendMessage(message) => command_handler.handle_send_message_cmd(message, connection_data),
+        Command::EnableSecondFactor(password) => command_handler.handle_enable_second_factor_cmd(&password, connection_data),
+        Command::ConfirmSecondFactor(code) => command_handler.handle_confirm_second_factor_cmd(&code, connection_data),
+        Command::SecondFactor(code) => command_handler.handle_second_factor_cmd(&code, connection_data),
+        Command::ChangePassword(password_change) => command_handler.handle_change_password_cmd(password_change, connection_data),
+        Command::ChangeNickname(nickname) => command_handler.handle_change_nickname_cmd(&nickname, connection_data),
+        Command::DeleteAccount(password) => command_handler.handle_delete_account_cmd(&password, connection_data),
+        Command::SearchUsers(search) => command_handler.handle_search_users_cmd(search, connection_data),
+        Command::RequestPrivacySettings => command_handler.handle_request_privacy_settings_cmd(connection_data),
+        Command::UpdatePrivacySettings(settings) => command_handler.handle_update_privacy_settings_cmd(settings, connection_data),
+        Command::Block(user_id) => command_handler.handle_block_cmd(user_id, connection_data),
+        Command::Unblock(user_id) => command_handler.handle_unblock_cmd(user_id, connection_data),
+        Command::RequestBlockedUsers => command_handler.handle_request_blocked_users_cmd(connection_data),
+        // Answered by the server loop, which knows the trusted gateways
+        Command::ForwardedPeer(_) => ErrorInfo::new(ErrorCode::NotAuthenticated, "Only trusted gateways can forward a peer address").into(),
+        Command::Ping => Notification::Pong.into(),
+        Command::Handshake(client_features) => {
+            connection_data.features = server_features.negotiate(client_features);
+            Notification::Handshake(server_features).into()
+        }
+        Command::Pong => return None,
+    };
 
-    Ok(bytes_buffer)
-}
\ No newline at end of file
+    Some(server_response)
+}
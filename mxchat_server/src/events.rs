@@ -0,0 +1,65 @@
+use std::sync::{mpsc::{self, Receiver, Sender}, Mutex};
+
+use mxchat_core::auth::User;
+
+/// Something that happened on the server, published once the command succeeded.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    UserRegistered(User),
+    UserConnected(User),
+    UserDisconnected(User),
+    /// The user changed its nickname.
+    UserUpdated(User),
+    UserDeleted(User),
+    MessageSent {
+        sender: User,
+        recipient: User,
+        content: String,
+    },
+    /// The requester looked up a user which was not among its contacts yet.
+    ContactAdded {
+        requester: User,
+        contact: User,
+    },
+}
+
+impl ServerEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerEvent::UserRegistered(_) => "user_registered",
+            ServerEvent::UserConnected(_) => "user_connected",
+            ServerEvent::UserDisconnected(_) => "user_disconnected",
+            ServerEvent::UserUpdated(_) => "user_updated",
+            ServerEvent::UserDeleted(_) => "user_deleted",
+            ServerEvent::MessageSent { .. } => "message_sent",
+            ServerEvent::ContactAdded { .. } => "contact_added",
+        }
+    }
+}
+
+/// Hands every published event to all the subscribers, each one reading them from its
+/// own channel so a slow subscriber never delays the commands.
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<ServerEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<ServerEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: ServerEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -1,21 +1,83 @@
-use std::{net::IpAddr, str::FromStr};
+use std::{env, net::IpAddr, str::FromStr, sync::Arc, time::Duration};
 
+use mxchat_core::io::{ProtocolFeatures, DEFAULT_MAX_FRAME_SIZE};
+use admin_api::{run_admin_api, AdminApiConfig};
+use auth::{AuthConfig, HtpasswdAuthenticator, LdapAuthenticator, LdapConfig};
 use server_handler::ServerCommandHandler;
+use webhooks::{RetryPolicy, WebhookConfig, WebhookDispatcher};
 use server::{run_server, ServerConfig};
+use rate_limit::RateLimitConfig;
 
+mod admin_api;
+mod auth;
+mod events;
 mod server;
 mod command_handler;
 mod server_handler;
 mod user;
+mod rate_limit;
+mod search;
+mod webhooks;
+mod websocket;
 
 fn main() {
 
     let config = ServerConfig {
         address: IpAddr::from_str("127.0.0.1").unwrap(),
-        port: 8080
+        port: 8080,
+        websocket_port: Some(8081),
+        rate_limit: RateLimitConfig::default(),
+        max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        idle_timeout: Duration::from_secs(30),
+        features: ProtocolFeatures::supported(),
+        // Comma separated, such as the address of mxchat-irc
+        trusted_gateways: env::var("MXCHAT_TRUSTED_GATEWAYS")
+            .map(|addresses| addresses.split(',').map(|address| IpAddr::from_str(address.trim()).unwrap()).collect())
+            .unwrap_or_default(),
     };
 
-    let cmd_handler = ServerCommandHandler::new();
+    let mut auth = AuthConfig::default();
+
+    if let Ok(path) = env::var("MXCHAT_HTPASSWD_FILE") {
+        auth.authenticators.push(Box::new(HtpasswdAuthenticator::load(path).unwrap()));
+    }
+
+    if let (Ok(url), Ok(bind_dn)) = (env::var("MXCHAT_LDAP_URL"), env::var("MXCHAT_LDAP_BIND_DN")) {
+        let ldap_config = LdapConfig {
+            url,
+            bind_dn,
+            nickname_attribute: env::var("MXCHAT_LDAP_NICKNAME_ATTRIBUTE").unwrap_or_else(|_| String::from("displayName")),
+            timeout: Duration::from_secs(5),
+        };
+        auth.authenticators.push(Box::new(LdapAuthenticator::new(ldap_config)));
+    }
+
+    // Set when the accounts come from an external directory
+    auth.registration_enabled = env::var_os("MXCHAT_DISABLE_REGISTRATION").is_none();
+
+    let cmd_handler = Arc::new(ServerCommandHandler::new(auth));
+
+    // The admin API is only served when a key is configured
+    if let Ok(api_key) = env::var("MXCHAT_ADMIN_API_KEY") {
+        let admin_api_config = AdminApiConfig {
+            port: 8082,
+            api_keys: vec![api_key],
+        };
+        if let Err(e) = run_admin_api(Arc::clone(&cmd_handler), config.address, admin_api_config) {
+            println!("Admin API not started: {e}");
+        }
+    }
+
+    if let (Ok(url), Ok(secret)) = (env::var("MXCHAT_WEBHOOK_URL"), env::var("MXCHAT_WEBHOOK_SECRET")) {
+        let webhook = WebhookConfig {
+            url,
+            secret,
+            events: Vec::new(),
+            keywords: Vec::new(),
+        };
+        WebhookDispatcher::new(vec![webhook], RetryPolicy::default(), "webhook_dead_letters.jsonl")
+            .start(cmd_handler.events().subscribe());
+    }
 
     println!("Running server...");
     run_server(cmd_handler, config).unwrap();
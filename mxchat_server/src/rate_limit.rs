@@ -0,0 +1,498 @@
+use std::{collections::HashMap, hash::Hash, net::IpAddr, sync::Mutex, time::{Duration, Instant}};
+
+use mxchat_core::{auth::UserId, command::Command, validation::username_key};
+
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+const MAX_TRACKED_LOGIN_FAILURES: usize = 10_000;
+
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum CommandKind {
+    Register,
+    Connect,
+    RequestContact,
+    Ping,
+    Pong,
+    Handshake,
+    SendMessage,
+    EnableSecondFactor,
+    ConfirmSecondFactor,
+    SecondFactor,
+    ChangePassword,
+    ChangeNickname,
+    DeleteAccount,
+    SearchUsers,
+    RequestPrivacySettings,
+    UpdatePrivacySettings,
+    Block,
+    Unblock,
+    RequestBlockedUsers,
+    ForwardedPeer,
+}
+
+impl From<&Command> for CommandKind {
+    fn from(value: &Command) -> Self {
+        match value {
+            Command::Register(_) => CommandKind::Register,
+            Command::Connect(_) => CommandKind::Connect,
+            Command::RequestContact(_) => CommandKind::RequestContact,
+            Command::Ping => CommandKind::Ping,
+            Command::Pong => CommandKind::Pong,
+            Command::Handshake(_) => CommandKind::Handshake,
+            Command::SendMessage(_) => CommandKind::SendMessage,
+            Command::EnableSecondFactor(_) => CommandKind::EnableSecondFactor,
+            Command::ConfirmSecondFactor(_) => CommandKind::ConfirmSecondFactor,
+            Command::SecondFactor(_) => CommandKind::SecondFactor,
+            Command::ChangePassword(_) => CommandKind::ChangePassword,
+            Command::ChangeNickname(_) => CommandKind::ChangeNickname,
+            Command::DeleteAccount(_) => CommandKind::DeleteAccount,
+            Command::SearchUsers(_) => CommandKind::SearchUsers,
+            Command::RequestPrivacySettings => CommandKind::RequestPrivacySettings,
+            Command::UpdatePrivacySettings(_) => CommandKind::UpdatePrivacySettings,
+            Command::Block(_) => CommandKind::Block,
+            Command::Unblock(_) => CommandKind::Unblock,
+            Command::RequestBlockedUsers => CommandKind::RequestBlockedUsers,
+            Command::ForwardedPeer(_) => CommandKind::ForwardedPeer,
+        }
+    }
+}
+
+/// Commands are keyed by peer address until the connection is authenticated,
+/// and by user id afterwards. Bot accounts have their own limits.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum RateLimitKey {
+    Peer(IpAddr),
+    User(UserId),
+    Bot(UserId),
+}
+
+/// Account whose credentials a command checks. Logins name it, the other commands act on
+/// the account of the connection.
+#[derive(Clone, Hash, Eq, PartialEq, Debug)]
+pub enum LoginAccount {
+    Username(String),
+    User(UserId),
+}
+
+impl LoginAccount {
+    /// Usernames differing only in case or width name the same account.
+    pub fn username(username: &str) -> Self {
+        LoginAccount::Username(username_key(username))
+    }
+}
+
+/// A bucket holding at most `capacity` tokens, refilled with one token every `refill_interval`.
+#[derive(Copy, Clone, Debug)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+impl RateLimit {
+    pub fn new(capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            capacity,
+            refill_interval
+        }
+    }
+}
+
+pub struct RateLimitConfig {
+    pub limits: HashMap<CommandKind, RateLimit>,
+    /// Limits of the commands sent by bot accounts, commands missing here get the ones of `limits`.
+    pub bot_limits: HashMap<CommandKind, RateLimit>,
+    /// Failed logins of a peer on an account before it is locked out of it.
+    pub max_failed_logins: u32,
+    /// How long the lockout lasts, and how long a failure is remembered.
+    pub lockout_duration: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let limits = HashMap::from([
+            (CommandKind::Register, RateLimit::new(3, Duration::from_secs(60))),
+            (CommandKind::Connect, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::RequestContact, RateLimit::new(10, Duration::from_secs(2))),
+            (CommandKind::Ping, RateLimit::new(5, Duration::from_secs(1))),
+            (CommandKind::Pong, RateLimit::new(5, Duration::from_secs(1))),
+            (CommandKind::Handshake, RateLimit::new(3, Duration::from_secs(10))),
+            (CommandKind::SendMessage, RateLimit::new(20, Duration::from_millis(250))),
+            (CommandKind::EnableSecondFactor, RateLimit::new(3, Duration::from_secs(10))),
+            (CommandKind::ConfirmSecondFactor, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::SecondFactor, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::ChangePassword, RateLimit::new(3, Duration::from_secs(60))),
+            (CommandKind::ChangeNickname, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::DeleteAccount, RateLimit::new(3, Duration::from_secs(60))),
+            // Clients search as the user types
+            (CommandKind::SearchUsers, RateLimit::new(10, Duration::from_millis(500))),
+            (CommandKind::RequestPrivacySettings, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::UpdatePrivacySettings, RateLimit::new(5, Duration::from_secs(10))),
+            (CommandKind::Block, RateLimit::new(10, Duration::from_secs(2))),
+            (CommandKind::Unblock, RateLimit::new(10, Duration::from_secs(2))),
+            (CommandKind::RequestBlockedUsers, RateLimit::new(5, Duration::from_secs(10))),
+        ]);
+
+        // Bots answer many users at once, they get larger bursts
+        let bot_limits = HashMap::from([
+            (CommandKind::RequestContact, RateLimit::new(30, Duration::from_millis(500))),
+            (CommandKind::SendMessage, RateLimit::new(60, Duration::from_millis(100))),
+        ]);
+
+        Self {
+            limits,
+            bot_limits,
+            max_failed_logins: 5,
+            lockout_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: u32,
+    last_refill: Instant,
+    last_used: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity,
+            last_refill: now,
+            last_used: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill_count = elapsed.as_nanos() / limit.refill_interval.as_nanos().max(1);
+        let refill_count = refill_count.min(limit.capacity as u128) as u32;
+
+        if refill_count == 0 {
+            return;
+        }
+
+        self.tokens = (self.tokens + refill_count).min(limit.capacity);
+        self.last_refill = if self.tokens == limit.capacity {
+            now
+        }
+        else {
+            self.last_refill + limit.refill_interval * refill_count
+        };
+    }
+
+    fn try_acquire(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        self.last_used = now;
+
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            Ok(())
+        }
+        else {
+            let elapsed = now.saturating_duration_since(self.last_refill);
+            Err(limit.refill_interval.saturating_sub(elapsed))
+        }
+    }
+
+    fn is_full(&self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let missing_tokens = limit.capacity - self.tokens;
+
+        elapsed >= limit.refill_interval * missing_tokens
+    }
+}
+
+struct LoginFailures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl LoginFailures {
+    /// Whether the entry no longer changes anything, the lockout is over or the failures are forgotten.
+    fn is_expired(&self, lockout_duration: Duration, now: Instant) -> bool {
+        match self.locked_until {
+            Some(locked_until) => locked_until <= now,
+            None => now.saturating_duration_since(self.last_failure) >= lockout_duration,
+        }
+    }
+}
+
+type LoginFailuresKey = (IpAddr, LoginAccount);
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<(RateLimitKey, CommandKind), TokenBucket>>,
+    login_failures: Mutex<HashMap<LoginFailuresKey, LoginFailures>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            login_failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for the command, or returns how long to wait before retrying.
+    pub fn check(&self, key: RateLimitKey, command_kind: CommandKind) -> Result<(), Duration> {
+        self.check_at(key, command_kind, Instant::now())
+    }
+
+    fn limit(&self, key: RateLimitKey, command_kind: CommandKind) -> Option<&RateLimit> {
+        match key {
+            RateLimitKey::Bot(_) => self.config.bot_limits
+                .get(&command_kind)
+                .or_else(|| self.config.limits.get(&command_kind)),
+            RateLimitKey::Peer(_) | RateLimitKey::User(_) => self.config.limits.get(&command_kind),
+        }
+    }
+
+    fn check_at(&self, key: RateLimitKey, command_kind: CommandKind, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit(key, command_kind) else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_BUCKETS && !buckets.contains_key(&(key, command_kind)) {
+            // Full buckets behave like new ones, the stalest go next when they are not enough
+            buckets.retain(|(key, kind), bucket| {
+                self.limit(*key, *kind)
+                    .is_some_and(|limit| !bucket.is_full(limit, now))
+            });
+            evict_stalest(&mut buckets, MAX_TRACKED_BUCKETS, |bucket| bucket.last_used);
+        }
+
+        buckets
+            .entry((key, command_kind))
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .try_acquire(limit, now)
+    }
+
+    /// Returns how long the peer is still locked out of the account.
+    pub fn check_login_lockout(&self, peer_address: IpAddr, account: &LoginAccount) -> Result<(), Duration> {
+        self.check_login_lockout_at(peer_address, account, Instant::now())
+    }
+
+    fn check_login_lockout_at(&self, peer_address: IpAddr, account: &LoginAccount, now: Instant) -> Result<(), Duration> {
+        let mut login_failures = self.login_failures.lock().unwrap();
+        let key = (peer_address, account.clone());
+
+        let Some(failures) = login_failures.get(&key) else {
+            return Ok(());
+        };
+
+        match failures.locked_until {
+            Some(locked_until) if locked_until > now => Err(locked_until - now),
+            _ => {
+                if failures.is_expired(self.config.lockout_duration, now) {
+                    login_failures.remove(&key);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_login_failure(&self, peer_address: IpAddr, account: LoginAccount) {
+        self.record_login_failure_at(peer_address, account, Instant::now());
+    }
+
+    fn record_login_failure_at(&self, peer_address: IpAddr, account: LoginAccount, now: Instant) {
+        let lockout_duration = self.config.lockout_duration;
+        let mut login_failures = self.login_failures.lock().unwrap();
+        let key = (peer_address, account);
+
+        if login_failures.len() >= MAX_TRACKED_LOGIN_FAILURES && !login_failures.contains_key(&key) {
+            login_failures.retain(|_, failures| !failures.is_expired(lockout_duration, now));
+            evict_stalest(&mut login_failures, MAX_TRACKED_LOGIN_FAILURES, |failures| failures.last_failure);
+        }
+
+        let failures = login_failures.entry(key).or_insert(LoginFailures {
+            count: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        if failures.is_expired(lockout_duration, now) {
+            failures.count = 0;
+            failures.locked_until = None;
+        }
+
+        failures.count += 1;
+        failures.last_failure = now;
+        if failures.count >= self.config.max_failed_logins {
+            failures.locked_until = Some(now + lockout_duration);
+        }
+    }
+
+    /// Forgets the failures of the peer on the account it just logged into, its guesses on
+    /// other accounts still count.
+    pub fn record_login_success(&self, peer_address: IpAddr, account: LoginAccount) {
+        self.login_failures
+            .lock()
+            .unwrap()
+            .remove(&(peer_address, account));
+    }
+}
+
+/// Removes the least recently used entries until a quarter of `max_len` is free, so that
+/// the next insertions don't have to scan the map again.
+fn evict_stalest<K: Clone + Eq + Hash, V>(map: &mut HashMap<K, V>, max_len: usize, last_used: impl Fn(&V) -> Instant) {
+    let target_len = max_len - max_len / 4;
+    if map.len() <= target_len {
+        return;
+    }
+
+    let mut entries: Vec<(Instant, K)> = map
+        .iter()
+        .map(|(key, value)| (last_used(value), key.clone()))
+        .collect();
+    let evicted_count = entries.len() - target_len;
+    entries.select_nth_unstable_by_key(evicted_count - 1, |(last_used, _)| *last_used);
+
+    for (_, key) in entries.drain(..evicted_count) {
+        map.remove(&key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            limits: HashMap::from([
+                (CommandKind::Register, RateLimit::new(2, Duration::from_secs(10))),
+                (CommandKind::Handshake, RateLimit::new(1, Duration::from_secs(10))),
+            ]),
+            bot_limits: HashMap::from([
+                (CommandKind::Register, RateLimit::new(4, Duration::from_secs(10))),
+            ]),
+            max_failed_logins: 3,
+            lockout_duration: Duration::from_secs(60),
+        })
+    }
+
+    const PEER: RateLimitKey = RateLimitKey::Peer(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+    #[test]
+    fn test_bucket_exhaustion_and_refill() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.check_at(PEER, CommandKind::Register, now).is_ok());
+        assert!(limiter.check_at(PEER, CommandKind::Register, now).is_ok());
+
+        let retry_after = limiter.check_at(PEER, CommandKind::Register, now + Duration::from_secs(4));
+        assert_eq!(retry_after, Err(Duration::from_secs(6)));
+
+        assert!(limiter.check_at(PEER, CommandKind::Register, now + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn test_keys_and_commands_are_independent() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let user = RateLimitKey::User(UserId::new(1));
+
+        limiter.check_at(PEER, CommandKind::Register, now).unwrap();
+        limiter.check_at(PEER, CommandKind::Register, now).unwrap();
+
+        assert!(limiter.check_at(user, CommandKind::Register, now).is_ok());
+        assert!(limiter.check_at(PEER, CommandKind::RequestContact, now).is_ok());
+    }
+
+    #[test]
+    fn test_bots_have_their_own_limits() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let bot = RateLimitKey::Bot(UserId::new(1));
+
+        for _ in 0..4 {
+            assert!(limiter.check_at(bot, CommandKind::Register, now).is_ok());
+        }
+        assert!(limiter.check_at(bot, CommandKind::Register, now).is_err());
+
+        // Commands without a bot limit keep the usual one
+        assert!(limiter.check_at(bot, CommandKind::Handshake, now).is_ok());
+        assert!(limiter.check_at(bot, CommandKind::Handshake, now).is_err());
+
+        assert!(limiter.check_at(bot, CommandKind::SendMessage, now).is_ok());
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let alice = LoginAccount::username("alice");
+
+        for _ in 0..3 {
+            assert!(limiter.check_login_lockout_at(address, &alice, now).is_ok());
+            limiter.record_login_failure_at(address, alice.clone(), now);
+        }
+
+        assert_eq!(limiter.check_login_lockout_at(address, &LoginAccount::username("ALICE"), now), Err(Duration::from_secs(60)));
+        assert!(limiter.check_login_lockout_at(address, &LoginAccount::username("bob"), now).is_ok());
+        assert!(limiter.check_login_lockout_at(address, &alice, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_login_success_on_another_account_keeps_failures() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let (victim, attacker) = (LoginAccount::username("alice"), LoginAccount::username("mallory"));
+
+        for _ in 0..3 {
+            assert!(limiter.check_login_lockout_at(address, &victim, now).is_ok());
+            limiter.record_login_failure_at(address, victim.clone(), now);
+            limiter.record_login_success(address, attacker.clone());
+        }
+
+        assert!(limiter.check_login_lockout_at(address, &victim, now).is_err());
+    }
+
+    #[test]
+    fn test_old_login_failures_expire() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let alice = LoginAccount::User(UserId::new(1));
+
+        limiter.record_login_failure_at(address, alice.clone(), now);
+        limiter.record_login_failure_at(address, alice.clone(), now);
+
+        // Forgotten after the lockout duration, a single new failure doesn't lock
+        let later = now + Duration::from_secs(60);
+        limiter.record_login_failure_at(address, alice.clone(), later);
+        assert!(limiter.check_login_lockout_at(address, &alice, later).is_ok());
+
+        assert!(limiter.check_login_lockout_at(address, &alice, later + Duration::from_secs(60)).is_ok());
+        assert!(limiter.login_failures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tracked_entries_are_bounded() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let peer = |index: u32| IpAddr::V4(Ipv4Addr::from(index));
+
+        for index in 0..MAX_TRACKED_BUCKETS as u32 + 10 {
+            let now = now + Duration::from_millis(index as u64);
+            limiter.check_at(RateLimitKey::Peer(peer(index)), CommandKind::Register, now).unwrap();
+            limiter.record_login_failure_at(peer(index), LoginAccount::username("alice"), now);
+        }
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() <= MAX_TRACKED_BUCKETS);
+        // The stalest go first
+        assert!(!buckets.contains_key(&(RateLimitKey::Peer(peer(0)), CommandKind::Register)));
+        assert!(buckets.contains_key(&(RateLimitKey::Peer(peer(MAX_TRACKED_BUCKETS as u32 + 9)), CommandKind::Register)));
+
+        assert!(limiter.login_failures.lock().unwrap().len() <= MAX_TRACKED_LOGIN_FAILURES);
+    }
+}
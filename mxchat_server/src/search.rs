@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use mxchat_core::{auth::{User, UserId}, validation::username_key};
+
+/// Queries shorter than this only match by prefix, fuzzy matches would be noise.
+const FUZZY_MIN_QUERY_LENGTH: usize = 3;
+
+/// How well a term of a user matches the query, the best first.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum MatchRank {
+    Exact,
+    Prefix,
+    /// Edit distance between the query and the closest start of the term.
+    Fuzzy(usize),
+}
+
+/// Index of the users by the terms of their username and nickname, both normalized and
+/// lowercased. A nickname gives a term for itself and one for each of its words.
+///
+/// Prefix matches walk the sorted terms, fuzzy matches scan all of them.
+pub struct SearchIndex {
+    terms: BTreeMap<String, HashSet<UserId>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self {
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, user: &User) {
+        for term in terms(user) {
+            self.terms.entry(term).or_default().insert(user.id);
+        }
+    }
+
+    pub fn remove(&mut self, user: &User) {
+        for term in terms(user) {
+            if let Some(user_ids) = self.terms.get_mut(&term) {
+                user_ids.remove(&user.id);
+                if user_ids.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Returns the users matching the query, the best matches first.
+    pub fn search(&self, query: &str) -> Vec<UserId> {
+        let query = username_key(query.trim());
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut ranks: HashMap<UserId, MatchRank> = HashMap::new();
+        let mut rank_users = |user_ids: &HashSet<UserId>, rank: MatchRank| {
+            for user_id in user_ids {
+                ranks.entry(*user_id)
+                    .and_modify(|best| *best = (*best).min(rank))
+                    .or_insert(rank);
+            }
+        };
+
+        for (term, user_ids) in self.terms.range(query.clone()..).take_while(|(term, _)| term.starts_with(&query)) {
+            let rank = if *term == query { MatchRank::Exact } else { MatchRank::Prefix };
+            rank_users(user_ids, rank);
+        }
+
+        let query_chars: Vec<char> = query.chars().collect();
+        if query_chars.len() >= FUZZY_MIN_QUERY_LENGTH {
+            let max_distance = if query_chars.len() <= 5 { 1 } else { 2 };
+
+            for (term, user_ids) in &self.terms {
+                if term.starts_with(&query) {
+                    continue;
+                }
+
+                let distance = prefix_distance(&query_chars, term);
+                if distance <= max_distance {
+                    rank_users(user_ids, MatchRank::Fuzzy(distance));
+                }
+            }
+        }
+
+        let mut results: Vec<(UserId, MatchRank)> = ranks.into_iter().collect();
+        results.sort_by_key(|(user_id, rank)| (*rank, user_id.get()));
+        results.into_iter().map(|(user_id, _)| user_id).collect()
+    }
+}
+
+fn terms(user: &User) -> HashSet<String> {
+    let nickname = username_key(&user.nickname);
+
+    let mut terms: HashSet<String> = nickname.split_whitespace().map(String::from).collect();
+    terms.insert(nickname);
+    terms.insert(username_key(&user.username));
+    terms.remove("");
+    terms
+}
+
+/// Smallest edit distance between the query and a start of the term, counting the
+/// transposition of two adjacent characters as one edit.
+fn prefix_distance(query: &[char], term: &str) -> usize {
+    let term: Vec<char> = term.chars().collect();
+
+    // rows[i][j] is the distance between the first i characters of the query and the first j of the term
+    let mut rows = vec![vec![0; term.len() + 1]; query.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=term.len()).collect();
+
+    for i in 1..=query.len() {
+        for j in 1..=term.len() {
+            let substitution = usize::from(query[i - 1] != term[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + substitution);
+
+            if i > 1 && j > 1 && query[i - 1] == term[j - 2] && query[i - 2] == term[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[query.len()].iter().copied().min().unwrap_or(query.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u32, username: &str, nickname: &str) -> User {
+        User { id: UserId::new(id), username: username.into(), nickname: nickname.into(), bot: false }
+    }
+
+    #[test]
+    fn test_prefix_distance() {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+
+        assert_eq!(prefix_distance(&chars("ali"), "alice"), 0);
+        assert_eq!(prefix_distance(&chars("alcie"), "alice"), 1);
+        assert_eq!(prefix_distance(&chars("alce"), "alice"), 1);
+        assert_eq!(prefix_distance(&chars("bob"), "alice"), 3);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = SearchIndex::new();
+        index.insert(&user(0, "alice", "Alice Liddell"));
+        index.insert(&user(1, "alicia", "Alicia"));
+        index.insert(&user(2, "bob", "Bob the Builder"));
+
+        assert_eq!(index.search("alice"), [UserId::new(0), UserId::new(1)]);
+        assert_eq!(index.search("ALI"), [UserId::new(0), UserId::new(1)]);
+        assert_eq!(index.search("lidd"), [UserId::new(0)]);
+        assert_eq!(index.search("buidler"), [UserId::new(2)]);
+        assert!(index.search("  ").is_empty());
+        assert!(index.search("zed").is_empty());
+
+        index.remove(&user(0, "alice", "Alice Liddell"));
+        assert_eq!(index.search("alice"), [UserId::new(1)]);
+        assert!(index.search("lidd").is_empty());
+    }
+}
@@ -1,17 +1,35 @@
-use std::{io::{self, Write}, net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs}, sync::Arc, thread};
+use std::{io::{self, Write}, net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, sync::{Arc, Mutex}, thread, time::Duration};
 
-use mxchat_core::{auth::UserId, command::CommandParsingError, io::BytesBuffer, notification::Notification};
+use mxchat_core::{auth::UserId, command::{Command, CommandParsingError}, encoding::Encode, error::{ErrorCode, ErrorInfo}, io::{write_frame, BytesBuffer, FrameError, ProtocolFeatures}, notification::Notification, request::RequestId};
 
-use crate::command_handler::{self, handle_command, CommandHandler, CommandHandlerRef};
+use crate::{auth::TotpSecret, command_handler::{self, handle_command, CommandFrame, CommandHandlerRef, FrameSource}, rate_limit::{CommandKind, LoginAccount, RateLimitConfig, RateLimitKey, RateLimiter}, websocket};
+
+/// Write half of a connection, shared with the handlers pushing notifications to it.
+/// Each frame is written then flushed while holding the lock.
+pub type ConnectionWriter = Arc<Mutex<dyn Write + Send>>;
+
+/// Longest a write may block on a peer which stops reading, so that it can't stall the
+/// handlers pushing notifications to it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct ServerConnectionData {
-    pub socket: TcpStream,
-    pub user_id: Option<UserId>
+    pub reader: Box<dyn FrameSource>,
+    pub writer: ConnectionWriter,
+    pub peer_address: SocketAddr,
+    pub user_id: Option<UserId>,
+    /// Set once logged in with a bot account.
+    pub bot: bool,
+    /// Features negotiated with the client, none until it sends its handshake.
+    pub features: ProtocolFeatures,
+    /// User whose password was accepted, until the second factor finishes the login.
+    pub awaiting_second_factor: Option<UserId>,
+    /// Secret handed out by `EnableSecondFactor`, until a code confirms the enrollment.
+    pub second_factor_enrollment: Option<TotpSecret>,
 }
 
 pub enum ServerError {
-    MissingCommandData,
-    CommandParsingError(CommandParsingError),
+    IdleTimeout,
+    FrameTooLarge(Option<RequestId>, usize),
     IoError(std::io::Error)
 }
 
@@ -21,9 +39,30 @@ impl From<std::io::Error> for ServerError {
     }
 }
 
+impl From<FrameError> for ServerError {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::Idle => ServerError::IdleTimeout,
+            FrameError::TooLarge(request_id, frame_size) => ServerError::FrameTooLarge(request_id, frame_size),
+            FrameError::Io(e) => ServerError::IoError(e)
+        }
+    }
+}
+
 pub struct ServerConfig {
     pub address: IpAddr,
-    pub port: u16
+    pub port: u16,
+    /// Port of the WebSocket listener for browser clients, carrying the same frames
+    /// as the TCP one, one per binary message. Disabled when `None`.
+    pub websocket_port: Option<u16>,
+    pub rate_limit: RateLimitConfig,
+    pub max_frame_size: usize,
+    pub idle_timeout: Duration,
+    /// Features offered to the clients in the handshake.
+    pub features: ProtocolFeatures,
+    /// Addresses of the gateways, such as mxchat-irc, allowed to send `Command::ForwardedPeer`
+    /// for the clients they relay.
+    pub trusted_gateways: Vec<IpAddr>,
 }
 
 impl ServerConfig {
@@ -33,81 +72,427 @@ impl ServerConfig {
 }
 
 pub struct ServerResponse {
+    request_id: Option<RequestId>,
     notification: Notification,
-    data_bytes: BytesBuffer
 }
 
 impl ServerResponse {
-    pub fn new(notification: Notification, data_bytes: BytesBuffer) -> Self {
+    pub fn new(notification: Notification) -> Self {
         Self {
+            request_id: None,
             notification,
-            data_bytes
         }
     }
+
+    pub fn with_request_id(mut self, request_id: Option<RequestId>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    pub fn into_notification(self) -> Notification {
+        self.notification
+    }
 }
 
 impl From<Notification> for ServerResponse {
     fn from(value: Notification) -> Self {
-        Self::new(value, BytesBuffer::empty())
+        Self::new(value)
     }
 }
 
+impl From<ErrorInfo> for ServerResponse {
+    fn from(value: ErrorInfo) -> Self {
+        Self::new(value.into())
+    }
+}
 
-pub fn run_server(cmd_handler: impl CommandHandler + 'static, config: ServerConfig) -> io::Result<()> {
+impl From<ErrorCode> for ServerResponse {
+    fn from(value: ErrorCode) -> Self {
+        Self::new(value.into())
+    }
+}
 
-    let cmd_handler = Arc::new(cmd_handler);
+
+struct ServerContext {
+    cmd_handler: CommandHandlerRef,
+    rate_limiter: RateLimiter,
+    max_frame_size: usize,
+    idle_timeout: Duration,
+    features: ProtocolFeatures,
+    trusted_gateways: Vec<IpAddr>,
+}
+
+#[derive(Clone, Copy)]
+enum Transport {
+    Tcp,
+    WebSocket,
+}
+
+impl Transport {
+    fn open(self, socket: TcpStream, max_frame_size: usize) -> io::Result<(Box<dyn FrameSource>, ConnectionWriter)> {
+        match self {
+            Transport::Tcp => {
+                let writer = socket.try_clone()?;
+                Ok((Box::new(socket), Arc::new(Mutex::new(writer))))
+            }
+            Transport::WebSocket => websocket::open_connection(socket, max_frame_size)
+        }
+    }
+}
+
+pub fn run_server(cmd_handler: CommandHandlerRef, config: ServerConfig) -> io::Result<()> {
+
+    let listener = TcpListener::bind(config.as_socket_addr())?;
+    let websocket_listener = config.websocket_port
+        .map(|port| TcpListener::bind((config.address, port)))
+        .transpose()?;
 
     println!("Server with ip address {} listening on port {}", config.address, config.port);
 
-    TcpListener::bind(config.as_socket_addr())?
+    serve(cmd_handler, config, listener, websocket_listener);
+
+    Ok(())
+}
+
+/// Accepts the connections of the listeners already bound for `config`, until the TCP listener fails.
+pub(crate) fn serve(cmd_handler: CommandHandlerRef, config: ServerConfig, listener: TcpListener, websocket_listener: Option<TcpListener>) {
+    let context = Arc::new(ServerContext {
+        cmd_handler,
+        rate_limiter: RateLimiter::new(config.rate_limit),
+        max_frame_size: config.max_frame_size,
+        idle_timeout: config.idle_timeout,
+        features: config.features,
+        trusted_gateways: config.trusted_gateways,
+    });
+
+    if let Some(websocket_listener) = websocket_listener {
+        if let Ok(address) = websocket_listener.local_addr() {
+            println!("Accepting WebSocket connections on port {}", address.port());
+        }
+
+        let context = Arc::clone(&context);
+        thread::spawn(move || accept_connections(websocket_listener, Transport::WebSocket, &context));
+    }
+
+    accept_connections(listener, Transport::Tcp, &context);
+}
+
+fn accept_connections(listener: TcpListener, transport: Transport, context: &Arc<ServerContext>) {
+    listener
         .incoming()
         .filter_map(|socket|socket.ok())
         .for_each(|socket| {
-            let cmd_handler = Arc::clone(&cmd_handler);
-            thread::spawn(|| {
+            let Ok(peer_address) = socket.peer_addr() else {
+                return;
+            };
+
+            let context = Arc::clone(context);
+            thread::spawn(move || {
+                // The timeouts also bound the WebSocket handshake
+                let opened = socket.set_read_timeout(Some(context.idle_timeout))
+                    .and_then(|_| socket.set_write_timeout(Some(WRITE_TIMEOUT)))
+                    .and_then(|_| transport.open(socket, context.max_frame_size));
+
+                let (reader, writer) = match opened {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        println!("Could not open connection with {peer_address}: {e}");
+                        return;
+                    }
+                };
+
                 let mut connection_data = ServerConnectionData {
-                    socket,
-                    user_id: None
+                    reader,
+                    writer,
+                    peer_address,
+                    user_id: None,
+                    bot: false,
+                    features: ProtocolFeatures::default(),
+                    awaiting_second_factor: None,
+                    second_factor_enrollment: None,
                 };
-                if let Err(e) = handle_connection(cmd_handler, &mut connection_data) {
-                    println!("Error occured {e}");
-                    println!("User with id {:?} is disconnected", connection_data.user_id);
+                if let Err(e) = handle_connection(&context, &mut connection_data) {
+                    println!("Connection with {} closed: {e}", connection_data.peer_address);
                 }
-            });
-        });   
 
-    Ok(())
+                if let Some(user_id) = connection_data.user_id {
+                    context.cmd_handler.handle_disconnect(user_id, &connection_data.writer);
+                    println!("User with id {:?} is disconnected", user_id);
+                }
+            });
+        });
 }
 
-fn handle_connection(cmd_handler: CommandHandlerRef, connection_data: &mut ServerConnectionData) -> io::Result<()> {
+fn handle_connection(context: &ServerContext, connection_data: &mut ServerConnectionData) -> io::Result<()> {
 
-    println!("New connection from address {:?}", connection_data.socket.peer_addr());
+    println!("New connection from address {}", connection_data.peer_address);
+
+    let mut awaiting_pong = false;
 
     loop {
-        let cmd = command_handler::fetch_command(&mut connection_data.socket);
-
-        let mut server_response = match cmd {
-            Ok(cmd) => handle_command(cmd, &cmd_handler, connection_data),
-            Err(ServerError::MissingCommandData) => 
-                    Notification::InvalidPayload.into(),
-            Err(ServerError::CommandParsingError(e)) => 
-                    handle_cmd_parsing_error(e).into(),
-            Err(ServerError::IoError(e)) =>  Err(e)?
+        let frame = command_handler::fetch_command(connection_data.reader.as_mut(), context.max_frame_size);
+
+        let idle = matches!(frame, Err(ServerError::IdleTimeout));
+        if idle && awaiting_pong {
+            let _ = send_response(connection_data, ErrorCode::IdleTimeout.into());
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "peer stopped answering heartbeats"));
+        }
+        awaiting_pong = idle;
+
+        let server_response = match frame {
+            Ok(CommandFrame { request_id, command }) => {
+                let server_response = match command {
+                    Ok(cmd) => handle_rate_limited_command(cmd, context, connection_data),
+                    Err(e) => Some(handle_cmd_parsing_error(e).into())
+                };
+
+                server_response.map(|server_response| server_response.with_request_id(request_id))
+            }
+            Err(ServerError::IdleTimeout) => Some(Notification::Ping.into()),
+            Err(ServerError::FrameTooLarge(request_id, frame_size)) => {
+                let message = format!("frame of {frame_size} bytes exceeds the maximum of {} bytes", context.max_frame_size);
+                let server_response = ServerResponse::from(ErrorInfo::from(ErrorCode::FrameTooLarge).with_detail(&message))
+                    .with_request_id(request_id);
+                send_response(connection_data, server_response)?;
+
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            Err(ServerError::IoError(e)) => {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    let error = ErrorInfo::from(ErrorCode::Internal).with_detail(e.to_string());
+                    let _ = send_response(connection_data, error.into());
+                }
+
+                return Err(e);
+            }
         };
 
-        connection_data.socket.write(&[server_response.notification as u8])?;
-        if let Some(data) = server_response.data_bytes.read_all() {
-            connection_data.socket.write(data)?;
+        if let Some(server_response) = server_response {
+            send_response(connection_data, server_response)?;
         }
+    }
+}
 
-        connection_data.socket.flush()?;
+fn send_response(connection_data: &mut ServerConnectionData, server_response: ServerResponse) -> io::Result<()> {
+    write_notification(
+        &connection_data.writer,
+        server_response.request_id,
+        &server_response.notification,
+        connection_data.features.compression
+    )
+}
+
+pub fn write_notification(writer: &ConnectionWriter, request_id: Option<RequestId>, notification: &Notification, compression: bool) -> io::Result<()> {
+    let mut message = BytesBuffer::empty();
+    notification.encode(&mut message);
 
+    let mut writer = writer.lock().unwrap();
+    write_frame(&mut &mut *writer, request_id, &mut message, compression)
+}
+
+fn handle_rate_limited_command(cmd: Command, context: &ServerContext, connection_data: &mut ServerConnectionData) -> Option<ServerResponse> {
+    // Checked before the rate limits, which are shared by all the clients of a gateway until then
+    if let Command::ForwardedPeer(address) = &cmd {
+        return forward_peer(address, context, connection_data).err().map(ServerResponse::from);
+    }
+
+    let rate_limiter = &context.rate_limiter;
+    let peer_ip = connection_data.peer_address.ip();
+    let key = match connection_data.user_id {
+        None => RateLimitKey::Peer(peer_ip),
+        Some(user_id) if connection_data.bot => RateLimitKey::Bot(user_id),
+        Some(user_id) => RateLimitKey::User(user_id),
+    };
+    // Commands checking a password share the lockout of the logins, so they cannot be used to guess it
+    let login_account = match &cmd {
+        Command::Connect(user_connect_data) => Some(LoginAccount::username(&user_connect_data.username)),
+        Command::SecondFactor(_) => connection_data.awaiting_second_factor.map(LoginAccount::User),
+        Command::EnableSecondFactor(_) | Command::ChangePassword(_) | Command::DeleteAccount(_) =>
+            connection_data.user_id.map(LoginAccount::User),
+        _ => None
+    };
+
+    let allowed = rate_limiter
+        .check(key, CommandKind::from(&cmd))
+        .and_then(|_| match &login_account {
+            Some(account) => rate_limiter.check_login_lockout(peer_ip, account),
+            None => Ok(())
+        });
+
+    if let Err(retry_after) = allowed {
+        println!("Rate limiting {:?} for {:?}", key, retry_after);
+        let message = format!("Too many requests, retry in {} seconds", retry_after.as_secs().max(1));
+        let error = ErrorInfo::new(ErrorCode::RateLimited, message).with_retry_after(retry_after);
+
+        return Some(error.into());
+    }
+
+    let server_response = handle_command(cmd, &context.cmd_handler, connection_data, context.features);
+
+    if let (Some(account), Some(server_response)) = (login_account, &server_response) {
+        match &server_response.notification {
+            Notification::UserConnected(_) => rate_limiter.record_login_success(peer_ip, account),
+            notification if matches!(notification.error_code(), Some(ErrorCode::PasswordIncorrect | ErrorCode::SecondFactorIncorrect)) =>
+                rate_limiter.record_login_failure(peer_ip, account),
+            _ => ()
+        }
+    }
+
+    server_response
+}
+
+/// Makes the connection of a trusted gateway count as the client it relays.
+fn forward_peer(address: &str, context: &ServerContext, connection_data: &mut ServerConnectionData) -> Result<(), ErrorInfo> {
+    let gateway_address = connection_data.peer_address;
+
+    if !context.trusted_gateways.contains(&gateway_address.ip()) {
+        return Err(ErrorInfo::new(ErrorCode::NotAuthenticated, "Only trusted gateways can forward a peer address"));
+    }
+
+    if connection_data.user_id.is_some() || connection_data.awaiting_second_factor.is_some() {
+        return Err(ErrorInfo::new(ErrorCode::InvalidPayload, "The peer address is forwarded before logging in"));
     }
+
+    let peer_ip = address.parse::<IpAddr>()
+        .map_err(|e| ErrorInfo::from(ErrorCode::InvalidPayload).with_detail(e.to_string()))?;
+
+    connection_data.peer_address = SocketAddr::new(peer_ip, gateway_address.port());
+    println!("Connection from {gateway_address} relays {peer_ip}");
+
+    Ok(())
 }
 
-fn handle_cmd_parsing_error(error: CommandParsingError) -> Notification {
+fn handle_cmd_parsing_error(error: CommandParsingError) -> ErrorCode {
     match error {
-        CommandParsingError::UnknownCommand => Notification::UnknownCommand,
-        CommandParsingError::InvalidPayload => Notification::InvalidPayload,
+        CommandParsingError::UnknownCommand => ErrorCode::UnknownCommand,
+        CommandParsingError::InvalidPayload => ErrorCode::InvalidPayload,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use mxchat_core::{auth::{UserConnectData, UserRegisterData}, encoding::Decode, io::{read_frame, DEFAULT_MAX_FRAME_SIZE}};
+
+    use crate::{auth::AuthConfig, events::ServerEvent, server_handler::ServerCommandHandler};
+
+    use super::*;
+
+    fn request(socket: &mut TcpStream, cmd: &Command) -> Notification {
+        let mut message = BytesBuffer::empty();
+        cmd.to_bytes(&mut message);
+        write_frame(socket, None, &mut message, false).unwrap();
+
+        read_notification(socket)
     }
-}
\ No newline at end of file
+
+    fn read_notification(socket: &mut TcpStream) -> Notification {
+        let Ok((_, mut message)) = read_frame(socket, DEFAULT_MAX_FRAME_SIZE) else {
+            panic!("could not read the frame");
+        };
+        Notification::decode(&mut message).unwrap()
+    }
+
+    fn start_server(idle_timeout: Duration, trusted_gateways: Vec<IpAddr>) -> (Arc<ServerCommandHandler>, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            address: address.ip(),
+            port: address.port(),
+            websocket_port: None,
+            rate_limit: RateLimitConfig::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            idle_timeout,
+            features: ProtocolFeatures::default(),
+            trusted_gateways,
+        };
+        let cmd_handler = Arc::new(ServerCommandHandler::new(AuthConfig::default()));
+        let server_cmd_handler: CommandHandlerRef = cmd_handler.clone();
+        thread::spawn(move || serve(server_cmd_handler, config, listener, None));
+
+        (cmd_handler, address)
+    }
+
+    /// Sends a command which is only answered when refused.
+    fn send(socket: &mut TcpStream, cmd: &Command) {
+        let mut message = BytesBuffer::empty();
+        cmd.to_bytes(&mut message);
+        write_frame(socket, None, &mut message, false).unwrap();
+    }
+
+    #[test]
+    fn test_trusted_gateway_forwards_peer_addresses() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let (_, address) = start_server(Duration::from_secs(30), vec![localhost]);
+        let handshake = Command::Handshake(ProtocolFeatures::default());
+
+        // More clients than the handshake limit of a single address
+        for i in 0..5 {
+            let mut socket = TcpStream::connect(address).unwrap();
+            send(&mut socket, &Command::ForwardedPeer(format!("10.0.0.{i}")));
+            assert!(matches!(request(&mut socket, &handshake), Notification::Handshake(_)));
+        }
+
+        let mut socket = TcpStream::connect(address).unwrap();
+        send(&mut socket, &Command::ForwardedPeer("not an address".into()));
+        assert_eq!(read_notification(&mut socket).error_code(), Some(ErrorCode::InvalidPayload));
+    }
+
+    #[test]
+    fn test_untrusted_peer_cannot_forward_addresses() {
+        let (_, address) = start_server(Duration::from_secs(30), Vec::new());
+        let handshake = Command::Handshake(ProtocolFeatures::default());
+
+        let mut socket = TcpStream::connect(address).unwrap();
+        let forwarded = request(&mut socket, &Command::ForwardedPeer("10.0.0.1".into()));
+        assert_eq!(forwarded.error_code(), Some(ErrorCode::NotAuthenticated));
+
+        // The handshakes still count for the address of the connection
+        for _ in 0..3 {
+            let mut socket = TcpStream::connect(address).unwrap();
+            send(&mut socket, &Command::ForwardedPeer("10.0.0.1".into()));
+            read_notification(&mut socket);
+            assert!(matches!(request(&mut socket, &handshake), Notification::Handshake(_)));
+        }
+        let mut socket = TcpStream::connect(address).unwrap();
+        assert_eq!(request(&mut socket, &handshake).error_code(), Some(ErrorCode::RateLimited));
+    }
+
+    #[test]
+    fn test_silent_peer_is_disconnected() {
+        let (cmd_handler, address) = start_server(Duration::from_millis(200), Vec::new());
+        let events = cmd_handler.events().subscribe();
+
+        let mut socket = TcpStream::connect(address).unwrap();
+        let register = Command::Register(UserRegisterData {
+            username: "alice".into(),
+            nickname: "alice".into(),
+            password: "s3cret-pass".into(),
+            bot: false,
+        });
+        assert_eq!(request(&mut socket, &register), Notification::UserRegistred);
+        let connect = Command::Connect(UserConnectData { username: "alice".into(), password: "s3cret-pass".into() });
+        let Notification::UserConnected(alice) = request(&mut socket, &connect) else {
+            panic!("alice logs in");
+        };
+        assert!(cmd_handler.is_online(alice.id));
+
+        // The first idle period asks for a heartbeat, the second one closes the connection
+        assert_eq!(read_notification(&mut socket), Notification::Ping);
+        assert_eq!(read_notification(&mut socket).error_code(), Some(ErrorCode::IdleTimeout));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match events.recv_timeout(timeout) {
+                Ok(ServerEvent::UserDisconnected(user)) => {
+                    assert_eq!(user.id, alice.id);
+                    break;
+                }
+                Ok(_) => (),
+                Err(e) => panic!("alice was not disconnected: {e}"),
+            }
+        }
+        assert!(!cmd_handler.is_online(alice.id));
+    }
+}
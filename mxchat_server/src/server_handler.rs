@@ -1,24 +1,43 @@
-use std::{collections::HashMap, net::TcpStream, sync::RwLock};
+use std::{collections::{HashMap, HashSet}, sync::{Arc, RwLock}};
 
-use mxchat_core::{auth::{User, UserConnectData, UserId}, io::BytesBuffer, messaging::Contact, notification::Notification, utils::u32_as_bytes};
+use mxchat_core::{auth::{PasswordChange, PrivacySettings, User, UserConnectData, UserId}, error::{ErrorCode, ErrorInfo}, messaging::{Contact, IncomingMessage, OutgoingMessage, SearchResult, UserSearch}, notification::Notification};
 
-use crate::{command_handler::CommandHandler, server::{ServerConnectionData, ServerResponse}, user::{InMemoryUserRepository, UserData, UserIdGenerator, UserRepository}};
+use crate::{auth::{AuthConfig, AuthError, Identity, SecondFactor, SecondFactorError, TotpSecret, LOCAL_BACKEND}, command_handler::CommandHandler, events::{EventBus, ServerEvent}, server::{write_notification, ConnectionWriter, ServerConnectionData, ServerResponse}, user::{InMemoryUserRepository, UserData, UserIdGenerator, UserRepository}};
+
+/// Cap of the results of `Command::SearchUsers`, whatever the limit asked for.
+const MAX_SEARCH_RESULTS: usize = 50;
 
 pub struct ServerCommandHandler {
     users_repo: Box<RwLock<dyn UserRepository>>,
     ids_generator: UserIdGenerator,
-    users_sockets: RwLock<HashMap<UserId, TcpStream>>
+    users_sockets: RwLock<HashMap<UserId, ConnectionWriter>>,
+    events: EventBus,
+    auth: AuthConfig,
 }
 
 impl ServerCommandHandler {
-    pub fn new() -> Self {
+    pub fn new(auth: AuthConfig) -> Self {
         Self {
             users_repo: Box::new(RwLock::new(InMemoryUserRepository::new())),
             ids_generator: UserIdGenerator::new(),
             users_sockets: RwLock::new(HashMap::new()),
+            events: EventBus::new(),
+            auth,
         }
     }
 
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    fn find_user_with_id(&self, user_id: UserId) -> Option<User> {
+        self.users_repo
+            .read()
+            .unwrap()
+            .find_user_with_id(user_id)
+            .map(|user| user.user.clone())
+    }
+
     fn add_user(&self, user: UserData) {
         self
             .users_repo
@@ -27,11 +46,228 @@ impl ServerCommandHandler {
             .add_user(user);
     }
 
-    fn register_socket(&self, user_id: UserId, socket: TcpStream) {
+    /// Tries the authenticators in turn. A username belongs to the backend which first
+    /// authenticated it, so an external directory cannot take over a local account.
+    fn authenticate(&self, credentials: &UserConnectData) -> Result<User, AuthError> {
+        let account = self.users_repo
+            .read()
+            .unwrap()
+            .find_user_with_username(&credentials.username)
+            .cloned();
+
+        for authenticator in &self.auth.authenticators {
+            let backend = authenticator.backend();
+            if account.as_ref().is_some_and(|account| account.backend != backend) {
+                continue;
+            }
+
+            match authenticator.authenticate(credentials, account.as_ref()) {
+                Ok(identity) => return match account {
+                    Some(account) => Ok(account.user),
+                    None => self.provision_user(&credentials.username, identity, backend)
+                },
+                Err(AuthError::UserNotFound) => continue,
+                Err(AuthError::Unavailable(reason)) => {
+                    println!("Authentication with the {backend} backend failed: {reason}");
+                    return Err(AuthError::Unavailable(reason));
+                }
+                Err(error) => return Err(error)
+            }
+        }
+
+        Err(AuthError::UserNotFound)
+    }
+
+    /// Records an account of an external backend on its first login. The username is
+    /// validated as for a registration, so the backend cannot bring in a reserved one.
+    fn provision_user(&self, username: &str, identity: Identity, backend: &'static str) -> Result<User, AuthError> {
+        let username = self.auth.validation
+            .validate_username(username)
+            .map_err(AuthError::InvalidUsername)?;
+
+        let mut users_repo = self.users_repo.write().unwrap();
+
+        // Another connection may have logged the same account in meanwhile
+        if let Some(account) = users_repo.find_user_with_username(&username) {
+            return Ok(account.user.clone());
+        }
+
+        let user = User {
+            id: self.ids_generator.next_id(),
+            username,
+            nickname: identity.nickname,
+            bot: false,
+        };
+
+        users_repo.add_user(UserData {
+            user: user.clone(),
+            password: None,
+            backend,
+            second_factor: None,
+            contact_of: HashSet::new(),
+            privacy: PrivacySettings::default(),
+            blocked: HashSet::new(),
+        });
+        drop(users_repo);
+
+        self.events.publish(ServerEvent::UserRegistered(user.clone()));
+        Ok(user)
+    }
+
+    fn complete_login(&self, user: User, connection_data: &mut ServerConnectionData) -> ServerResponse {
+        connection_data.user_id = Some(user.id);
+        connection_data.bot = user.bot;
+        self.register_socket(user.id, Arc::clone(&connection_data.writer));
+        self.events.publish(ServerEvent::UserConnected(user.clone()));
+
+        Notification::UserConnected(user).into()
+    }
+
+    fn register_socket(&self, user_id: UserId, writer: ConnectionWriter) {
         self.users_sockets
             .write()
             .unwrap()
-            .insert(user_id, socket);
+            .insert(user_id, writer);
+    }
+
+    /// Records that `user_id` has `contact_id` in its contacts.
+    /// Returns whether the relation is new.
+    fn add_contact_relation(&self, user_id: UserId, contact_id: UserId) -> bool {
+        if user_id == contact_id {
+            return false;
+        }
+
+        // Users blocked by the contact are not told about its changes
+        match self.users_repo.write().unwrap().find_user_with_id_mut(contact_id) {
+            Some(contact) if !contact.blocked.contains(&user_id) => contact.contact_of.insert(user_id),
+            _ => false
+        }
+    }
+
+    /// Pushes the notification to the users who are connected, without request id.
+    fn notify_users(&self, user_ids: &HashSet<UserId>, notification: &Notification) {
+        // Cloned so that slow sockets don't hold the lock
+        let writers: Vec<ConnectionWriter> = {
+            let users_sockets = self.users_sockets.read().unwrap();
+            user_ids.iter().filter_map(|user_id| users_sockets.get(user_id)).cloned().collect()
+        };
+
+        for writer in &writers {
+            // Users who just went away miss the notification, their clients refresh the contacts on login
+            let _ = write_notification(writer, None, notification, false);
+        }
+    }
+
+    /// Users whose username or nickname contains `query`, ignoring case. All of them without a query.
+    pub fn find_users(&self, query: Option<&str>) -> Vec<User> {
+        let query = query.map(str::to_lowercase);
+
+        self.users_repo
+            .read()
+            .unwrap()
+            .users()
+            .map(|user| &user.user)
+            .filter(|user| query.as_ref().is_none_or(|query| {
+                user.username.to_lowercase().contains(query) || user.nickname.to_lowercase().contains(query)
+            }))
+            .cloned()
+            .collect()
+    }
+
+    pub fn find_user(&self, username: &str) -> Option<User> {
+        self.users_repo
+            .read()
+            .unwrap()
+            .find_user_with_username(username)
+            .map(|user| user.user.clone())
+    }
+
+    fn is_blocked_by(&self, user_id: UserId, blocking_user_id: UserId) -> bool {
+        self.users_repo
+            .read()
+            .unwrap()
+            .find_user_with_id(blocking_user_id)
+            .is_some_and(|account| account.blocked.contains(&user_id))
+    }
+
+    /// Answer to the commands managing the blocked users.
+    fn blocked_users(&self, user_id: UserId) -> ServerResponse {
+        let users_repo = self.users_repo.read().unwrap();
+        let Some(account) = users_repo.find_user_with_id(user_id) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        let blocked_users = account.blocked
+            .iter()
+            .filter_map(|blocked_id| users_repo.find_user_with_id(*blocked_id))
+            .map(|blocked| Contact {
+                id: blocked.user.id,
+                nickname: blocked.user.nickname.clone(),
+                bot: blocked.user.bot,
+            })
+            .collect();
+
+        Notification::BlockedUsers(blocked_users).into()
+    }
+
+    pub fn is_online(&self, user_id: UserId) -> bool {
+        self.users_sockets
+            .read()
+            .unwrap()
+            .contains_key(&user_id)
+    }
+
+    /// Delivers a message the way `SendMessage` does, for senders which are not connected
+    /// themselves.
+    pub fn send_message_as(&self, sender_id: UserId, message: OutgoingMessage) -> ServerResponse {
+        // The lock is released before writing, a recipient which stops reading must not block the other commands
+        let (sender, recipient, blocked) = {
+            let users_repo = self.users_repo.read().unwrap();
+            let Some(sender) = users_repo.find_user_with_id(sender_id) else {
+                return ErrorCode::NotAuthenticated.into();
+            };
+            let Some(recipient) = users_repo.find_user_with_id(message.recipient) else {
+                return ErrorCode::UserNotFound.into();
+            };
+
+            (sender.user.clone(), recipient.user.clone(), recipient.blocked.contains(&sender_id))
+        };
+
+        // The sender is not told it is blocked
+        if blocked {
+            return Notification::MessageSent.into();
+        }
+
+        let recipient_writer = self.users_sockets
+            .read()
+            .unwrap()
+            .get(&recipient.id)
+            .cloned();
+
+        let Some(recipient_writer) = recipient_writer else {
+            return ErrorCode::UserOffline.into();
+        };
+
+        let incoming_message = IncomingMessage {
+            sender,
+            content: message.content,
+        };
+
+        if write_notification(&recipient_writer, None, &Notification::MessageReceived(incoming_message.clone()), false).is_err() {
+            return ErrorCode::UserOffline.into();
+        }
+
+        // The client of the recipient adds the sender to its contacts
+        self.add_contact_relation(sender_id, recipient.id);
+        self.add_contact_relation(recipient.id, sender_id);
+
+        self.events.publish(ServerEvent::MessageSent {
+            sender: incoming_message.sender,
+            recipient,
+            content: incoming_message.content,
+        });
+
+        Notification::MessageSent.into()
     }
 }
 
@@ -40,6 +276,15 @@ impl CommandHandler for ServerCommandHandler {
 
         println!("Registering user");
 
+        if !self.auth.registration_enabled {
+            return ErrorCode::RegistrationDisabled.into();
+        }
+
+        let user_register_data = match self.auth.validation.validate_register_data(&user_register_data) {
+            Ok(user_register_data) => user_register_data,
+            Err(error) => return ErrorInfo::from(error).into()
+        };
+
         let user_registered = self.users_repo
             .read()
             .unwrap()
@@ -47,19 +292,27 @@ impl CommandHandler for ServerCommandHandler {
             .is_some();
 
         if user_registered {
-            return Notification::UserAlreadyExist.into();
+            return ErrorCode::UserAlreadyExists.into();
         }
 
         let user_data = UserData {
             user: User {
                 id: self.ids_generator.next_id(),
                 username: user_register_data.username.clone(),
-                nickname: user_register_data.nickname.clone()
+                nickname: user_register_data.nickname.clone(),
+                bot: user_register_data.bot,
             },
-            password: user_register_data.password.clone()
+            password: Some(user_register_data.password.clone()),
+            backend: LOCAL_BACKEND,
+            second_factor: None,
+            contact_of: HashSet::new(),
+            privacy: PrivacySettings::default(),
+            blocked: HashSet::new(),
         };
 
+        let user = user_data.user.clone();
         self.add_user(user_data);
+        self.events.publish(ServerEvent::UserRegistered(user));
 
         Notification::UserRegistred.into()
     }
@@ -67,59 +320,727 @@ impl CommandHandler for ServerCommandHandler {
     fn handle_connect_cmd(&self, user_connect_data: UserConnectData, connection_data: &mut ServerConnectionData) -> ServerResponse {
 
         if connection_data.user_id.is_some() {
-            return Notification::UserIsAlreadyConnected.into()
+            return ErrorCode::UserAlreadyConnected.into()
         }
 
-        let result = self.users_repo
+        let user = match self.authenticate(&user_connect_data) {
+            Ok(user) => user,
+            Err(error) => return ErrorInfo::from(error).into()
+        };
+
+        let second_factor_enrolled = self.users_repo
             .read()
             .unwrap()
-            .find_user_with_username(&user_connect_data.username)
-            .ok_or(Notification::UserNotFound)
-            .and_then(|user| {
-                if user.password == user_connect_data.password {
-                    Ok(user)
-                }
-                else {
-                    Err(Notification::UserPasswordIncorrect)
-                }
-            })
-            .map(|user|{
-                connection_data.user_id = Some(user.user.id);
-                self.register_socket(user.user.id, connection_data.socket.try_clone().unwrap());
+            .find_user_with_id(user.id)
+            .is_some_and(|account| account.second_factor.is_some());
 
-                let mut bytes_buffer = BytesBuffer::empty();
-                let user_bytes = user.user.to_bytes();
-                bytes_buffer.write_bytes(&u32_as_bytes(user_bytes.len() as u32));
-                bytes_buffer.write_bytes(&user_bytes);
+        if second_factor_enrolled {
+            connection_data.awaiting_second_factor = Some(user.id);
+            return Notification::SecondFactorRequired.into();
+        }
 
-                ServerResponse::new(Notification::UserConnected, bytes_buffer)
-            });
+        self.complete_login(user, connection_data)
+    }
+    
+    fn handle_disconnect(&self, user_id: UserId, writer: &ConnectionWriter) {
+        let mut users_sockets = self.users_sockets.write().unwrap();
+
+        // A later session of the user replaced the writer of this connection, it stays online
+        if !users_sockets.get(&user_id).is_some_and(|registered| Arc::ptr_eq(registered, writer)) {
+            return;
+        }
+        users_sockets.remove(&user_id);
+        drop(users_sockets);
+
+        if let Some(user) = self.find_user_with_id(user_id) {
+            self.events.publish(ServerEvent::UserDisconnected(user));
+        }
+    }
+
+    fn handle_request_contact_cmd(&self, username: &str, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user) = self.find_user(username) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        // Blocked users can't tell the account from a missing one
+        if connection_data.user_id.is_some_and(|requester_id| self.is_blocked_by(requester_id, user.id)) {
+            return ErrorCode::UserNotFound.into();
+        }
+
+        let contact = Contact {
+            id: user.id,
+            nickname: user.nickname.clone(),
+            bot: user.bot,
+        };
+
+        // Contacts looked up again, as clients do when reconnecting or before sending, are not new
+        let requester = connection_data.user_id
+            .filter(|requester_id| self.add_contact_relation(*requester_id, user.id))
+            .and_then(|requester_id| self.find_user_with_id(requester_id));
+
+        if let Some(requester) = requester {
+            self.events.publish(ServerEvent::ContactAdded { requester, contact: user });
+        }
+
+        Notification::ReceiveContactInfo(contact).into()
+    }
+
+    fn handle_send_message_cmd(&self, message: OutgoingMessage, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(sender_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        self.send_message_as(sender_id, message)
+    }
+
+    fn handle_enable_second_factor_cmd(&self, password: &str, connection_data: &mut ServerConnectionData) -> ServerResponse {
+        let Some(user) = connection_data.user_id.and_then(|user_id| self.find_user_with_id(user_id)) else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        // The enrollment replaces the existing second factor, a stolen session alone can't do it
+        let credentials = UserConnectData {
+            username: user.username.clone(),
+            password: password.to_string(),
+        };
+        if let Err(error) = self.authenticate(&credentials) {
+            return ErrorInfo::from(error).into();
+        }
+
+        let secret = TotpSecret::generate();
+        let enrollment = secret.enrollment(&user.username);
+        connection_data.second_factor_enrollment = Some(secret);
 
+        Notification::SecondFactorEnrollment(enrollment).into()
+    }
+
+    fn handle_confirm_second_factor_cmd(&self, code: &str, connection_data: &mut ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        let Some(secret) = connection_data.second_factor_enrollment.clone() else {
+            return ErrorInfo::new(ErrorCode::SecondFactorIncorrect, "No enrollment is pending, enable two-factor authentication first").into();
+        };
+
+        let (second_factor, recovery_codes) = match SecondFactor::enroll(secret, code) {
+            Ok(enrolled) => enrolled,
+            Err(error) => return ErrorInfo::from(error).into()
+        };
+
+        match self.users_repo.write().unwrap().find_user_with_id_mut(user_id) {
+            Some(account) => account.second_factor = Some(second_factor),
+            None => return ErrorCode::UserNotFound.into()
+        }
+        connection_data.second_factor_enrollment = None;
+
+        Notification::SecondFactorEnabled(recovery_codes).into()
+    }
+
+    fn handle_second_factor_cmd(&self, code: &str, connection_data: &mut ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.awaiting_second_factor else {
+            return ErrorInfo::new(ErrorCode::NotAuthenticated, "No login is waiting for a second factor").into();
+        };
+
+        let result = self.users_repo
+            .write()
+            .unwrap()
+            .find_user_with_id_mut(user_id)
+            .ok_or(ErrorInfo::from(ErrorCode::UserNotFound))
+            .and_then(|account| {
+                let second_factor = account.second_factor
+                    .as_mut()
+                    .ok_or(ErrorInfo::from(SecondFactorError::Incorrect))?;
+
+                second_factor.verify(code)?;
+                Ok(account.user.clone())
+            });
 
         match result {
-            Ok(response) => response,
-            Err(notif) => notif.into()
+            Ok(user) => {
+                connection_data.awaiting_second_factor = None;
+                self.complete_login(user, connection_data)
+            }
+            Err(error) => error.into()
         }
     }
-    
-    fn handle_request_contact_cmd(&self, username: &str) -> ServerResponse {
-        self.users_repo
+
+    fn handle_change_password_cmd(&self, password_change: PasswordChange, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        let mut users_repo = self.users_repo.write().unwrap();
+        let Some(account) = users_repo.find_user_with_id_mut(user_id) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        let Some(password) = &mut account.password else {
+            let message = format!("The password of this account is managed by the {} backend", account.backend);
+            return ErrorInfo::new(ErrorCode::InvalidAccountData, message).into();
+        };
+
+        if *password != password_change.old {
+            return ErrorCode::PasswordIncorrect.into();
+        }
+
+        if let Err(error) = self.auth.validation.validate_password(&password_change.new, &account.user.username) {
+            return ErrorInfo::from(error).into();
+        }
+
+        *password = password_change.new;
+
+        Notification::AccountUpdated(account.user.clone()).into()
+    }
+
+    fn handle_change_nickname_cmd(&self, nickname: &str, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        let nickname = match self.auth.validation.validate_nickname(nickname) {
+            Ok(nickname) => nickname,
+            Err(error) => return ErrorInfo::from(error).into()
+        };
+
+        let mut users_repo = self.users_repo.write().unwrap();
+        let Some(account) = users_repo.set_nickname(user_id, nickname) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        let user = account.user.clone();
+        let contact_of = account.contact_of.clone();
+        drop(users_repo);
+
+        let contact = Contact {
+            id: user.id,
+            nickname: user.nickname.clone(),
+            bot: user.bot,
+        };
+        self.notify_users(&contact_of, &Notification::ContactUpdated(contact));
+        self.events.publish(ServerEvent::UserUpdated(user.clone()));
+
+        Notification::AccountUpdated(user).into()
+    }
+
+    fn handle_delete_account_cmd(&self, password: &str, connection_data: &mut ServerConnectionData) -> ServerResponse {
+        let Some(user) = connection_data.user_id.and_then(|user_id| self.find_user_with_id(user_id)) else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        // Checked by the backend of the account, external accounts have no password here
+        let credentials = UserConnectData {
+            username: user.username.clone(),
+            password: password.to_string(),
+        };
+        if let Err(error) = self.authenticate(&credentials) {
+            return ErrorInfo::from(error).into();
+        }
+
+        let Some(account) = self.users_repo.write().unwrap().remove_user(user.id) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        self.users_sockets
+            .write()
+            .unwrap()
+            .remove(&user.id);
+        connection_data.user_id = None;
+        connection_data.bot = false;
+        connection_data.second_factor_enrollment = None;
+
+        self.notify_users(&account.contact_of, &Notification::ContactDeleted(user.id));
+        self.events.publish(ServerEvent::UserDeleted(user));
+
+        Notification::AccountDeleted.into()
+    }
+
+    fn handle_search_users_cmd(&self, search: UserSearch, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        // No username or nickname is longer, refused before paying for the normalization and the fuzzy matching
+        let max_length = self.auth.validation.nickname_max_length;
+        if search.query.chars().count() > max_length {
+            return ErrorInfo::new(ErrorCode::InvalidPayload, format!("Search queries are limited to {max_length} characters")).into();
+        }
+
+        let limit = usize::from(search.limit).min(MAX_SEARCH_RESULTS);
+        let results = self.users_repo
             .read()
             .unwrap()
-            .find_user_with_username(username)
-            .map(|user| &user.user)
-            .map(|user| Contact {
-                id: user.id,
-                nickname: user.nickname.clone(),
+            .search_users(&search.query)
+            .into_iter()
+            .filter(|account| account.user.id != user_id && account.privacy.discoverable && !account.blocked.contains(&user_id))
+            .take(limit)
+            .map(|account| SearchResult {
+                username: account.user.username.clone(),
+                contact: Contact {
+                    id: account.user.id,
+                    nickname: account.user.nickname.clone(),
+                    bot: account.user.bot,
+                },
             })
-            .map(|contact| {
-                let mut bytes_buffer = BytesBuffer::empty();
-                let bytes = contact.to_bytes();
-                bytes_buffer.write_bytes(&u32_as_bytes(bytes.len() as u32));
-                bytes_buffer.write_bytes(&bytes);
+            .collect();
+
+        Notification::SearchResults(results).into()
+    }
+
+    fn handle_request_privacy_settings_cmd(&self, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        match self.users_repo.read().unwrap().find_user_with_id(user_id) {
+            Some(account) => Notification::PrivacySettings(account.privacy).into(),
+            None => ErrorCode::UserNotFound.into()
+        }
+    }
+
+    fn handle_update_privacy_settings_cmd(&self, settings: PrivacySettings, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        let mut users_repo = self.users_repo.write().unwrap();
+        let Some(account) = users_repo.find_user_with_id_mut(user_id) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        account.privacy = settings;
+
+        Notification::PrivacySettings(settings).into()
+    }
+
+    fn handle_block_cmd(&self, blocked_id: UserId, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        if blocked_id == user_id {
+            return ErrorInfo::new(ErrorCode::InvalidPayload, "You can't block yourself").into();
+        }
+
+        let mut users_repo = self.users_repo.write().unwrap();
+        if users_repo.find_user_with_id(blocked_id).is_none() {
+            return ErrorCode::UserNotFound.into();
+        }
+
+        let Some(account) = users_repo.find_user_with_id_mut(user_id) else {
+            return ErrorCode::UserNotFound.into();
+        };
+
+        account.blocked.insert(blocked_id);
+        // The blocked user is no longer told about the changes of the account
+        account.contact_of.remove(&blocked_id);
+        drop(users_repo);
+
+        self.blocked_users(user_id)
+    }
+
+    fn handle_unblock_cmd(&self, blocked_id: UserId, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        match self.users_repo.write().unwrap().find_user_with_id_mut(user_id) {
+            Some(account) => account.blocked.remove(&blocked_id),
+            None => return ErrorCode::UserNotFound.into()
+        };
+
+        self.blocked_users(user_id)
+    }
+
+    fn handle_request_blocked_users_cmd(&self, connection_data: &ServerConnectionData) -> ServerResponse {
+        let Some(user_id) = connection_data.user_id else {
+            return ErrorCode::NotAuthenticated.into();
+        };
+
+        self.blocked_users(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{self, Write}, net::SocketAddr, sync::Mutex};
+
+    use mxchat_core::{auth::UserRegisterData, command::Command, encoding::Decode, io::{read_frame, BytesBuffer, FrameError, ProtocolFeatures, DEFAULT_MAX_FRAME_SIZE}, request::RequestId, validation::ValidationPolicy};
+
+    use crate::{auth::{Authenticator, HtpasswdAuthenticator, LocalAuthenticator}, command_handler::{handle_command, CommandHandlerRef, FrameSource}};
+
+    use super::*;
+
+    struct NoFrames;
+
+    impl FrameSource for NoFrames {
+        fn read_frame(&mut self, _max_frame_size: usize) -> Result<(Option<RequestId>, BytesBuffer), FrameError> {
+            Err(FrameError::Idle)
+        }
+    }
+
+    fn connection_data() -> ServerConnectionData {
+        ServerConnectionData {
+            reader: Box::new(NoFrames),
+            writer: Arc::new(Mutex::new(io::sink())),
+            peer_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+            user_id: None,
+            bot: false,
+            features: ProtocolFeatures::default(),
+            awaiting_second_factor: None,
+            second_factor_enrollment: None,
+        }
+    }
+
+    fn connect(cmd_handler: &ServerCommandHandler, username: &str, password: &str) -> Notification {
+        let credentials = UserConnectData {
+            username: username.into(),
+            password: password.into(),
+        };
+
+        cmd_handler.handle_connect_cmd(credentials, &mut connection_data()).into_notification()
+    }
+
+    fn error_code(notification: Notification) -> Option<ErrorCode> {
+        match notification {
+            Notification::Error(error) => Some(error.code),
+            _ => None
+        }
+    }
 
-                ServerResponse::new(Notification::ReceiveContactInfo, bytes_buffer)
+    #[test]
+    fn test_authenticators_are_composed() {
+        let htpasswd = HtpasswdAuthenticator::parse("alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\nbob:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n");
+        let authenticators: Vec<Box<dyn Authenticator>> = vec![Box::new(LocalAuthenticator), Box::new(htpasswd)];
+        let cmd_handler = ServerCommandHandler::new(AuthConfig {
+            authenticators,
+            registration_enabled: true,
+            validation: ValidationPolicy::default(),
+        });
+
+        cmd_handler.handle_register_cmd(UserRegisterData {
+            username: "alice".into(),
+            nickname: "Alice".into(),
+            password: "l0cal-pass".into(),
+            bot: false,
+        });
+
+        // The local account keeps its password, the htpasswd entry cannot take it over
+        assert_eq!(error_code(connect(&cmd_handler, "alice", "password")), Some(ErrorCode::PasswordIncorrect));
+        assert!(matches!(connect(&cmd_handler, "alice", "l0cal-pass"), Notification::UserConnected(_)));
+
+        let Notification::UserConnected(bob) = connect(&cmd_handler, "bob", "password") else {
+            panic!("bob is in the htpasswd file");
+        };
+        assert_eq!(cmd_handler.users_repo.read().unwrap().find_user_with_id(bob.id).unwrap().backend, "htpasswd");
+        assert!(matches!(connect(&cmd_handler, "bob", "password"), Notification::UserConnected(user) if user.id == bob.id));
+
+        assert_eq!(error_code(connect(&cmd_handler, "carol", "password")), Some(ErrorCode::UserNotFound));
+    }
+
+    #[test]
+    fn test_external_usernames_are_validated() {
+        let hash = "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=";
+        let htpasswd = HtpasswdAuthenticator::parse(&format!("Bob:{hash}\nadmin:{hash}\ncarol dave:{hash}\n"));
+        let cmd_handler = ServerCommandHandler::new(AuthConfig {
+            authenticators: vec![Box::new(LocalAuthenticator), Box::new(htpasswd)],
+            registration_enabled: true,
+            validation: ValidationPolicy::default(),
+        });
+
+        let Notification::UserConnected(bob) = connect(&cmd_handler, "bob", "password") else {
+            panic!("Bob is in the htpasswd file");
+        };
+        assert!(matches!(connect(&cmd_handler, "BOB", "password"), Notification::UserConnected(user) if user.id == bob.id));
+
+        // Reserved and invalid usernames are refused as they are for a registration
+        assert_eq!(error_code(connect(&cmd_handler, "admin", "password")), Some(ErrorCode::InvalidAccountData));
+        assert_eq!(error_code(connect(&cmd_handler, "carol dave", "password")), Some(ErrorCode::InvalidAccountData));
+        assert!(cmd_handler.find_user("admin").is_none());
+    }
+
+    #[test]
+    fn test_registration_can_be_disabled() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig {
+            authenticators: vec![Box::new(LocalAuthenticator)],
+            registration_enabled: false,
+            validation: ValidationPolicy::default(),
+        });
+
+        let response = cmd_handler.handle_register_cmd(UserRegisterData {
+            username: "alice".into(),
+            nickname: "Alice".into(),
+            password: "s3cret-pass".into(),
+            bot: false,
+        });
+
+        assert_eq!(error_code(response.into_notification()), Some(ErrorCode::RegistrationDisabled));
+        assert!(cmd_handler.find_user("alice").is_none());
+    }
+
+    #[test]
+    fn test_second_factor_login() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        cmd_handler.handle_register_cmd(UserRegisterData {
+            username: "alice".into(),
+            nickname: "Alice".into(),
+            password: "s3cret-pass".into(),
+            bot: false,
+        });
+
+        let mut enrolling = connection_data();
+        cmd_handler.handle_connect_cmd(UserConnectData { username: "alice".into(), password: "s3cret-pass".into() }, &mut enrolling);
+
+        let response = cmd_handler.handle_enable_second_factor_cmd("guess", &mut enrolling).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::PasswordIncorrect));
+
+        let response = cmd_handler.handle_enable_second_factor_cmd("s3cret-pass", &mut enrolling).into_notification();
+        assert!(matches!(response, Notification::SecondFactorEnrollment(enrollment) if enrollment.provisioning_uri.contains("mxchat%3Aalice")));
+
+        let response = cmd_handler.handle_confirm_second_factor_cmd("000000", &mut enrolling).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::SecondFactorIncorrect));
+
+        let code = enrolling.second_factor_enrollment.as_ref().unwrap().current_code();
+        let Notification::SecondFactorEnabled(recovery_codes) = cmd_handler.handle_confirm_second_factor_cmd(&code, &mut enrolling).into_notification() else {
+            panic!("the code of the enrollment is accepted");
+        };
+
+        let mut connection_data = connection_data();
+        let credentials = UserConnectData { username: "alice".into(), password: "s3cret-pass".into() };
+        let response = cmd_handler.handle_connect_cmd(credentials, &mut connection_data).into_notification();
+        assert_eq!(response, Notification::SecondFactorRequired);
+        assert_eq!(connection_data.user_id, None);
+
+        // The code of the enrollment cannot be replayed
+        let response = cmd_handler.handle_second_factor_cmd(&code, &mut connection_data).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::SecondFactorIncorrect));
+
+        let response = cmd_handler.handle_second_factor_cmd(&recovery_codes[0], &mut connection_data).into_notification();
+        assert!(matches!(response, Notification::UserConnected(user) if user.username == "alice"));
+        assert!(connection_data.user_id.is_some());
+    }
+
+    #[test]
+    fn test_register_validation() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let register = |username: &str, password: &str| cmd_handler.handle_register_cmd(UserRegisterData {
+            username: username.into(),
+            nickname: "Alice".into(),
+            password: password.into(),
+            bot: false,
+        }).into_notification();
+
+        assert_eq!(error_code(register("alice bob", "s3cret-pass")), Some(ErrorCode::InvalidAccountData));
+        assert_eq!(error_code(register("admin", "s3cret-pass")), Some(ErrorCode::InvalidAccountData));
+        assert_eq!(error_code(register("alice", "secret")), Some(ErrorCode::InvalidAccountData));
+
+        // Stored in its normalized form, and unique whatever the case
+        assert_eq!(register("ａｌｉｃｅ", "s3cret-pass"), Notification::UserRegistred);
+        assert_eq!(cmd_handler.find_user("alice").unwrap().username, "alice");
+        assert_eq!(error_code(register("Alice", "s3cret-pass")), Some(ErrorCode::UserAlreadyExists));
+        assert!(matches!(connect(&cmd_handler, "ALICE", "s3cret-pass"), Notification::UserConnected(user) if user.username == "alice"));
+
+        // Bot accounts can't be registered by the clients
+        let cmd_handler: CommandHandlerRef = Arc::new(cmd_handler);
+        let register_bot = Command::Register(UserRegisterData {
+            username: "deploy-bot".into(),
+            nickname: "Deploy".into(),
+            password: "s3cret-pass".into(),
+            bot: true,
+        });
+        let response = handle_command(register_bot, &cmd_handler, &mut connection_data(), ProtocolFeatures::default()).unwrap();
+        assert_eq!(error_code(response.into_notification()), Some(ErrorCode::InvalidAccountData));
+    }
+
+    /// Writer keeping the frames pushed to a connection.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn take_notifications(&self) -> Vec<Notification> {
+            let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+            let mut reader = bytes.as_slice();
+            let mut notifications = Vec::new();
+
+            while let Ok((_, mut message)) = read_frame(&mut reader, DEFAULT_MAX_FRAME_SIZE) {
+                notifications.extend(Notification::decode(&mut message));
+            }
+
+            notifications
+        }
+    }
+
+    fn register_and_connect(cmd_handler: &ServerCommandHandler, username: &str) -> (ServerConnectionData, SharedBuffer) {
+        cmd_handler.handle_register_cmd(UserRegisterData {
+            username: username.into(),
+            nickname: username.into(),
+            password: "s3cret-pass".into(),
+            bot: false,
+        });
+
+        let pushed = SharedBuffer::default();
+        let mut connection_data = ServerConnectionData {
+            writer: Arc::new(Mutex::new(pushed.clone())),
+            ..connection_data()
+        };
+        cmd_handler.handle_connect_cmd(UserConnectData { username: username.into(), password: "s3cret-pass".into() }, &mut connection_data);
+
+        (connection_data, pushed)
+    }
+
+    #[test]
+    fn test_account_management() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let (mut alice, _) = register_and_connect(&cmd_handler, "alice");
+        let (bob, bob_pushed) = register_and_connect(&cmd_handler, "bob");
+        let alice_id = alice.user_id.unwrap();
+
+        cmd_handler.handle_request_contact_cmd("alice", &bob);
+
+        let response = cmd_handler.handle_change_nickname_cmd("  ", &alice).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::InvalidAccountData));
+
+        let response = cmd_handler.handle_change_nickname_cmd("Alice", &alice).into_notification();
+        assert!(matches!(response, Notification::AccountUpdated(user) if user.nickname == "Alice"));
+        assert_eq!(bob_pushed.take_notifications(), [Notification::ContactUpdated(Contact { id: alice_id, nickname: "Alice".into(), bot: false })]);
+
+        let change = |old: &str, new: &str| PasswordChange { old: old.into(), new: new.into() };
+        let response = cmd_handler.handle_change_password_cmd(change("guess", "new secret"), &alice).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::PasswordIncorrect));
+        let response = cmd_handler.handle_change_password_cmd(change("s3cret-pass", "short"), &alice).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::InvalidAccountData));
+        let response = cmd_handler.handle_change_password_cmd(change("s3cret-pass", "new secret"), &alice).into_notification();
+        assert!(matches!(response, Notification::AccountUpdated(_)));
+        assert_eq!(error_code(connect(&cmd_handler, "alice", "s3cret-pass")), Some(ErrorCode::PasswordIncorrect));
+
+        let response = cmd_handler.handle_delete_account_cmd("s3cret-pass", &mut alice).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::PasswordIncorrect));
+        let response = cmd_handler.handle_delete_account_cmd("new secret", &mut alice).into_notification();
+        assert_eq!(response, Notification::AccountDeleted);
+
+        assert_eq!(alice.user_id, None);
+        assert_eq!(bob_pushed.take_notifications(), [Notification::ContactDeleted(alice_id)]);
+        assert_eq!(error_code(connect(&cmd_handler, "alice", "new secret")), Some(ErrorCode::UserNotFound));
+        assert!(!cmd_handler.is_online(alice_id));
+    }
+
+    #[test]
+    fn test_disconnect_keeps_later_session() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let (first_session, _) = register_and_connect(&cmd_handler, "alice");
+        let alice_id = first_session.user_id.unwrap();
+
+        let second_pushed = SharedBuffer::default();
+        let mut second_session = ServerConnectionData {
+            writer: Arc::new(Mutex::new(second_pushed.clone())),
+            ..connection_data()
+        };
+        cmd_handler.handle_connect_cmd(UserConnectData { username: "alice".into(), password: "s3cret-pass".into() }, &mut second_session);
+
+        // The first connection times out after the second one logged in
+        cmd_handler.handle_disconnect(alice_id, &first_session.writer);
+        assert!(cmd_handler.is_online(alice_id));
+
+        let (bob, _) = register_and_connect(&cmd_handler, "bob");
+        let message = OutgoingMessage { recipient: alice_id, content: "hello".into() };
+        assert_eq!(cmd_handler.handle_send_message_cmd(message, &bob).into_notification(), Notification::MessageSent);
+        assert_eq!(second_pushed.take_notifications().len(), 1);
+
+        cmd_handler.handle_disconnect(alice_id, &second_session.writer);
+        assert!(!cmd_handler.is_online(alice_id));
+    }
+
+    #[test]
+    fn test_contact_added_events() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let (alice, _) = register_and_connect(&cmd_handler, "alice");
+        let (bob, _) = register_and_connect(&cmd_handler, "bob");
+        let events = cmd_handler.events().subscribe();
+
+        let contacts_added = || events
+            .try_iter()
+            .filter_map(|event| match event {
+                ServerEvent::ContactAdded { requester, contact } => Some((requester.username, contact.username)),
+                _ => None
             })
-            .unwrap_or(Notification::UserNotFound.into())        
+            .collect::<Vec<_>>();
+
+        // Lookups before logging in, of known contacts or of oneself add no contact
+        cmd_handler.handle_request_contact_cmd("alice", &connection_data());
+        cmd_handler.handle_request_contact_cmd("bob", &bob);
+        assert!(contacts_added().is_empty());
+
+        cmd_handler.handle_request_contact_cmd("alice", &bob);
+        cmd_handler.handle_request_contact_cmd("alice", &bob);
+        assert_eq!(contacts_added(), [(String::from("bob"), String::from("alice"))]);
+
+        cmd_handler.handle_request_contact_cmd("bob", &alice);
+        assert_eq!(contacts_added(), [(String::from("alice"), String::from("bob"))]);
+    }
+
+    #[test]
+    fn test_search_users() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let (alice, _) = register_and_connect(&cmd_handler, "alice");
+        let (alicia, _) = register_and_connect(&cmd_handler, "alicia");
+        let (bob, _) = register_and_connect(&cmd_handler, "bob");
+
+        let search = |connection_data: &ServerConnectionData, query: &str| {
+            match cmd_handler.handle_search_users_cmd(UserSearch { query: query.into(), limit: 10 }, connection_data).into_notification() {
+                Notification::SearchResults(results) => results.into_iter().map(|result| result.username).collect::<Vec<_>>(),
+                notification => panic!("unexpected answer {notification:?}")
+            }
+        };
+
+        // The user searching is not part of the results
+        assert_eq!(search(&bob, "ALI"), ["alice", "alicia"]);
+        assert_eq!(search(&alice, "ali"), ["alicia"]);
+        assert_eq!(search(&bob, "alcie"), ["alice"]);
+
+        let response = cmd_handler.handle_update_privacy_settings_cmd(PrivacySettings { discoverable: false }, &alicia).into_notification();
+        assert_eq!(response, Notification::PrivacySettings(PrivacySettings { discoverable: false }));
+        assert_eq!(search(&bob, "ali"), ["alice"]);
+        assert!(matches!(cmd_handler.handle_request_contact_cmd("alicia", &bob).into_notification(), Notification::ReceiveContactInfo(_)));
+
+        let response = cmd_handler.handle_search_users_cmd(UserSearch { query: "ali".into(), limit: 10 }, &connection_data()).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::NotAuthenticated));
+
+        let query = "a".repeat(ValidationPolicy::default().nickname_max_length + 1);
+        let response = cmd_handler.handle_search_users_cmd(UserSearch { query, limit: 10 }, &bob).into_notification();
+        assert_eq!(error_code(response), Some(ErrorCode::InvalidPayload));
+    }
+
+    #[test]
+    fn test_blocking() {
+        let cmd_handler = ServerCommandHandler::new(AuthConfig::default());
+        let (alice, alice_pushed) = register_and_connect(&cmd_handler, "alice");
+        let (bob, _) = register_and_connect(&cmd_handler, "bob");
+        let (alice_id, bob_id) = (alice.user_id.unwrap(), bob.user_id.unwrap());
+
+        let response = cmd_handler.handle_block_cmd(bob_id, &alice).into_notification();
+        assert_eq!(response, Notification::BlockedUsers(vec![Contact { id: bob_id, nickname: "bob".into(), bot: false }]));
+        assert_eq!(error_code(cmd_handler.handle_block_cmd(alice_id, &alice).into_notification()), Some(ErrorCode::InvalidPayload));
+
+        // Bob's messages look delivered but are dropped, and alice can't be found anymore
+        let message = OutgoingMessage { recipient: alice_id, content: "hello".into() };
+        assert_eq!(cmd_handler.handle_send_message_cmd(message.clone(), &bob).into_notification(), Notification::MessageSent);
+        assert!(alice_pushed.take_notifications().is_empty());
+        assert_eq!(error_code(cmd_handler.handle_request_contact_cmd("alice", &bob).into_notification()), Some(ErrorCode::UserNotFound));
+        let response = cmd_handler.handle_search_users_cmd(UserSearch { query: "ali".into(), limit: 10 }, &bob).into_notification();
+        assert_eq!(response, Notification::SearchResults(Vec::new()));
+
+        assert_eq!(cmd_handler.handle_unblock_cmd(bob_id, &alice).into_notification(), Notification::BlockedUsers(Vec::new()));
+        assert_eq!(cmd_handler.handle_send_message_cmd(message, &bob).into_notification(), Notification::MessageSent);
+        assert!(matches!(&alice_pushed.take_notifications()[..], [Notification::MessageReceived(message)] if message.sender.id == bob_id));
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,46 @@
-use std::{collections::HashMap, sync::atomic::{AtomicU32, Ordering}};
+use std::{collections::{HashMap, HashSet}, sync::atomic::{AtomicU32, Ordering}};
 
-use mxchat_core::auth::{User, UserId};
+use mxchat_core::{auth::{PrivacySettings, User, UserId}, validation::username_key};
 
+use crate::{auth::SecondFactor, search::SearchIndex};
+
+#[derive(Clone)]
 pub struct UserData {
     pub user: User,
-    pub password: String,
+    /// `None` for the accounts of external backends, which check the passwords themselves.
+    pub password: Option<String>,
+    /// Name of the [`Authenticator`](crate::auth::Authenticator) the account belongs to.
+    pub backend: &'static str,
+    /// Set once the user enrolled in two-factor authentication.
+    pub second_factor: Option<SecondFactor>,
+    /// Users who looked this one up or exchanged messages with it, their clients keep it
+    /// in the contacts and are told about its changes.
+    pub contact_of: HashSet<UserId>,
+    pub privacy: PrivacySettings,
+    /// Users whose messages and contact requests are dropped.
+    pub blocked: HashSet<UserId>,
 }
 
 pub trait UserRepository: Sync + Send {
     fn add_user(&mut self, user: UserData);
     fn find_user_with_username(&self, username: &str) -> Option<&UserData>;
+    fn find_user_with_id(&self, user_id: UserId) -> Option<&UserData>;
+    fn find_user_with_id_mut(&mut self, user_id: UserId) -> Option<&mut UserData>;
+    /// Nicknames are searched, they are changed through the repository to keep it indexed.
+    fn set_nickname(&mut self, user_id: UserId, nickname: String) -> Option<&UserData>;
+    /// Users whose username or nickname matches the query, the best matches first.
+    fn search_users(&self, query: &str) -> Vec<&UserData>;
+    /// Removes the user from the contacts and the blocked users of the other users too.
+    fn remove_user(&mut self, user_id: UserId) -> Option<UserData>;
+    fn users(&self) -> Box<dyn Iterator<Item = &UserData> + '_>;
 }
 
 pub struct InMemoryUserRepository {
     users: Vec<UserData>,
     users_ids: HashMap<UserId, usize>,
-    users_usernames: HashMap<String, usize>
+    /// Indexed by [`username_key`], so usernames differing only by case are the same account.
+    users_usernames: HashMap<String, usize>,
+    search_index: SearchIndex,
 }
 
 impl InMemoryUserRepository {
@@ -24,6 +49,7 @@ impl InMemoryUserRepository {
             users: Vec::new(),
             users_ids: HashMap::new(),
             users_usernames: HashMap::new(),
+            search_index: SearchIndex::new(),
         }
     }
 }
@@ -33,7 +59,8 @@ impl UserRepository for InMemoryUserRepository {
         let index = self.users.len();
 
         self.users_ids.insert(user.user.id, index);
-        self.users_usernames.insert(user.user.username.clone(), index);
+        self.users_usernames.insert(username_key(&user.user.username), index);
+        self.search_index.insert(&user.user);
 
         self.users.push(user);
     }
@@ -41,9 +68,66 @@ impl UserRepository for InMemoryUserRepository {
     fn find_user_with_username(&self, username: &str) -> Option<&UserData> {
         self
             .users_usernames
-            .get(username)
+            .get(&username_key(username))
+            .and_then(|index| self.users.get(*index))
+    }
+
+    fn find_user_with_id(&self, user_id: UserId) -> Option<&UserData> {
+        self
+            .users_ids
+            .get(&user_id)
             .and_then(|index| self.users.get(*index))
     }
+
+    fn find_user_with_id_mut(&mut self, user_id: UserId) -> Option<&mut UserData> {
+        self
+            .users_ids
+            .get(&user_id)
+            .and_then(|index| self.users.get_mut(*index))
+    }
+
+    fn set_nickname(&mut self, user_id: UserId, nickname: String) -> Option<&UserData> {
+        let index = *self.users_ids.get(&user_id)?;
+        let account = &mut self.users[index];
+
+        self.search_index.remove(&account.user);
+        account.user.nickname = nickname;
+        self.search_index.insert(&account.user);
+
+        Some(account)
+    }
+
+    fn search_users(&self, query: &str) -> Vec<&UserData> {
+        self.search_index
+            .search(query)
+            .into_iter()
+            .filter_map(|user_id| self.find_user_with_id(user_id))
+            .collect()
+    }
+
+    fn remove_user(&mut self, user_id: UserId) -> Option<UserData> {
+        let index = self.users_ids.remove(&user_id)?;
+        let user = self.users.swap_remove(index);
+        self.users_usernames.remove(&username_key(&user.user.username));
+        self.search_index.remove(&user.user);
+
+        // The last user took the place of the removed one
+        if let Some(moved_user) = self.users.get(index) {
+            self.users_ids.insert(moved_user.user.id, index);
+            self.users_usernames.insert(username_key(&moved_user.user.username), index);
+        }
+
+        for other_user in &mut self.users {
+            other_user.contact_of.remove(&user_id);
+            other_user.blocked.remove(&user_id);
+        }
+
+        Some(user)
+    }
+
+    fn users(&self) -> Box<dyn Iterator<Item = &UserData> + '_> {
+        Box::new(self.users.iter())
+    }
 }
 
 pub struct UserIdGenerator {
@@ -64,3 +148,55 @@ impl UserIdGenerator {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::auth::LOCAL_BACKEND;
+
+    use super::*;
+
+    fn user_data(id: u32, username: &str) -> UserData {
+        UserData {
+            user: User { id: UserId::new(id), username: username.into(), nickname: username.into(), bot: false },
+            password: Some(String::from("secret")),
+            backend: LOCAL_BACKEND,
+            second_factor: None,
+            contact_of: HashSet::new(),
+            privacy: PrivacySettings::default(),
+            blocked: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_remove_user() {
+        let mut users_repo = InMemoryUserRepository::new();
+        users_repo.add_user(user_data(0, "alice"));
+        users_repo.add_user(user_data(1, "bob"));
+        users_repo.add_user(user_data(2, "carol"));
+        users_repo.find_user_with_id_mut(UserId::new(2)).unwrap().contact_of.insert(UserId::new(0));
+        users_repo.find_user_with_id_mut(UserId::new(1)).unwrap().blocked.insert(UserId::new(0));
+
+        let removed = users_repo.remove_user(UserId::new(0)).unwrap();
+        assert_eq!(removed.user.username, "alice");
+        assert!(users_repo.remove_user(UserId::new(0)).is_none());
+
+        assert!(users_repo.find_user_with_username("alice").is_none());
+        assert_eq!(users_repo.find_user_with_username("carol").unwrap().user.id, UserId::new(2));
+        assert_eq!(users_repo.find_user_with_id(UserId::new(1)).unwrap().user.username, "bob");
+        assert!(users_repo.find_user_with_id(UserId::new(2)).unwrap().contact_of.is_empty());
+        assert!(users_repo.find_user_with_id(UserId::new(1)).unwrap().blocked.is_empty());
+        assert!(users_repo.search_users("alice").is_empty());
+    }
+
+    #[test]
+    fn test_search_follows_nickname() {
+        let mut users_repo = InMemoryUserRepository::new();
+        users_repo.add_user(user_data(0, "alice"));
+        users_repo.set_nickname(UserId::new(0), String::from("Wonderland"));
+        users_repo.set_nickname(UserId::new(0), String::from("Looking Glass"));
+
+        let found = |query| users_repo.search_users(query).iter().map(|account| account.user.id).collect::<Vec<_>>();
+        assert_eq!(found("glass"), [UserId::new(0)]);
+        assert_eq!(found("ali"), [UserId::new(0)]);
+        assert!(found("wonder").is_empty());
+    }
+}
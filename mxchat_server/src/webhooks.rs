@@ -0,0 +1,380 @@
+use std::{fs::OpenOptions, io::{self, Write}, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, mpsc::{self, Receiver, Sender}, Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, Mac};
+use mxchat_core::auth::User;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::events::ServerEvent;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key of the HMAC-SHA256 of the body, sent hex encoded in `X-Mxchat-Signature`.
+    pub secret: String,
+    /// Names of the events to deliver, such as `message_sent`. All of them when empty.
+    pub events: Vec<String>,
+    /// Messages are only delivered when they contain one of the keywords, ignoring case.
+    /// All of them when empty.
+    pub keywords: Vec<String>,
+}
+
+impl WebhookConfig {
+    fn accepts(&self, event: &ServerEvent) -> bool {
+        if !self.events.is_empty() && !self.events.iter().any(|name| name == event.name()) {
+            return false;
+        }
+
+        match event {
+            ServerEvent::MessageSent { content, .. } if !self.keywords.is_empty() => {
+                let content = content.to_lowercase();
+                self.keywords.iter().any(|keyword| content.contains(&keyword.to_lowercase()))
+            }
+            _ => true
+        }
+    }
+}
+
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled for each of the following ones.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+struct Delivery {
+    id: u64,
+    event: &'static str,
+    body: String,
+}
+
+/// Deliveries which failed every attempt, one JSON object per line.
+struct DeadLetterLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DeadLetterLog {
+    fn append(&self, url: &str, delivery: &Delivery, error: &str) -> io::Result<()> {
+        let line = json!({
+            "url": url,
+            "delivery": delivery.id,
+            "event": delivery.event,
+            "body": delivery.body,
+            "error": error,
+            "timestamp": unix_timestamp(),
+        });
+
+        let _lock = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{line}")
+    }
+}
+
+/// POSTs the server events as signed JSON to the configured URLs. Each webhook has its
+/// own thread, so a slow or failing endpoint only delays its own deliveries, which keep
+/// the order of the events.
+pub struct WebhookDispatcher {
+    webhooks: Vec<WebhookConfig>,
+    retry_policy: RetryPolicy,
+    dead_letter_path: PathBuf,
+}
+
+impl WebhookDispatcher {
+    pub fn new(webhooks: Vec<WebhookConfig>, retry_policy: RetryPolicy, dead_letter_path: impl Into<PathBuf>) -> Self {
+        Self {
+            webhooks,
+            retry_policy,
+            dead_letter_path: dead_letter_path.into(),
+        }
+    }
+
+    /// Delivers the events until the sending side of `events` is dropped, the returned
+    /// thread ends once the pending deliveries are done.
+    pub fn start(self, events: Receiver<ServerEvent>) -> JoinHandle<()> {
+        let dead_letters = Arc::new(DeadLetterLog {
+            path: self.dead_letter_path,
+            lock: Mutex::new(()),
+        });
+        let retry_policy = Arc::new(self.retry_policy);
+
+        let workers: Vec<(WebhookConfig, Sender<Arc<Delivery>>, JoinHandle<()>)> = self.webhooks
+            .into_iter()
+            .map(|webhook| {
+                let (sender, receiver) = mpsc::channel::<Arc<Delivery>>();
+                let url = webhook.url.clone();
+                let secret = webhook.secret.clone();
+                let retry_policy = Arc::clone(&retry_policy);
+                let dead_letters = Arc::clone(&dead_letters);
+
+                let handle = thread::spawn(move || {
+                    for delivery in receiver {
+                        deliver(&url, &secret, &delivery, &retry_policy, &dead_letters);
+                    }
+                });
+
+                (webhook, sender, handle)
+            })
+            .collect();
+
+        thread::spawn(move || {
+            let ids = AtomicU64::new(0);
+
+            for event in events {
+                let delivery = Arc::new(Delivery {
+                    id: ids.fetch_add(1, Ordering::Relaxed),
+                    event: event.name(),
+                    body: event_payload(&event).to_string(),
+                });
+
+                for (webhook, sender, _) in &workers {
+                    if webhook.accepts(&event) {
+                        let _ = sender.send(Arc::clone(&delivery));
+                    }
+                }
+            }
+
+            for (_, sender, handle) in workers {
+                drop(sender);
+                let _ = handle.join();
+            }
+        })
+    }
+}
+
+fn deliver(url: &str, secret: &str, delivery: &Delivery, retry_policy: &RetryPolicy, dead_letters: &DeadLetterLog) {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(REQUEST_TIMEOUT))
+        .build()
+        .into();
+
+    let mut delay = retry_policy.base_delay;
+    let mut error = String::new();
+
+    for attempt in 1..=retry_policy.max_attempts {
+        if attempt > 1 {
+            thread::sleep(delay);
+            delay = delay.saturating_mul(2);
+        }
+
+        let result = agent.post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Mxchat-Event", delivery.event)
+            .header("X-Mxchat-Delivery", delivery.id.to_string())
+            .header("X-Mxchat-Signature", format!("sha256={}", sign(secret, &delivery.body)))
+            .send(&delivery.body);
+
+        match result {
+            Ok(_) => return,
+            Err(e) => error = e.to_string()
+        }
+    }
+
+    println!("Webhook delivery {} to {url} failed: {error}", delivery.id);
+    if let Err(e) = dead_letters.append(url, delivery, &error) {
+        println!("Could not write the dead letter log: {e}");
+    }
+}
+
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn user_json(user: &User) -> Value {
+    json!({
+        "id": user.id.get(),
+        "username": user.username,
+        "nickname": user.nickname,
+        "bot": user.bot,
+    })
+}
+
+fn event_payload(event: &ServerEvent) -> Value {
+    let data = match event {
+        ServerEvent::UserRegistered(user) |
+        ServerEvent::UserConnected(user) |
+        ServerEvent::UserDisconnected(user) |
+        ServerEvent::UserUpdated(user) |
+        ServerEvent::UserDeleted(user) => json!({ "user": user_json(user) }),
+        ServerEvent::MessageSent { sender, recipient, content } => json!({
+            "sender": user_json(sender),
+            "recipient": user_json(recipient),
+            "content": content,
+        }),
+        ServerEvent::ContactAdded { requester, contact } => json!({
+            "requester": user_json(requester),
+            "contact": user_json(contact),
+        }),
+    };
+
+    json!({
+        "event": event.name(),
+        "timestamp": unix_timestamp(),
+        "data": data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use mxchat_core::auth::UserId;
+    use tiny_http::{Request, Response, Server};
+
+    use crate::events::EventBus;
+
+    use super::*;
+
+    struct ReceivedRequest {
+        event: String,
+        signature: String,
+        body: String,
+    }
+
+    /// Answers the webhook requests with the given statuses in turn, then with 200.
+    fn spawn_stand_in(statuses: Vec<u16>) -> (String, Receiver<ReceivedRequest>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", server.server_addr());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+
+            for mut request in server.incoming_requests() {
+                let event = header(&request, "X-Mxchat-Event");
+                let signature = header(&request, "X-Mxchat-Signature");
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+
+                let _ = sender.send(ReceivedRequest { event, signature, body });
+                let _ = request.respond(Response::empty(statuses.next().unwrap_or(200)));
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn header(request: &Request, name: &'static str) -> String {
+        request.headers()
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.to_string())
+            .unwrap_or_default()
+    }
+
+    fn user(id: u32, username: &str) -> User {
+        User {
+            id: UserId::new(id),
+            username: username.into(),
+            nickname: username.into(),
+            bot: false,
+        }
+    }
+
+    fn dispatch(webhooks: Vec<WebhookConfig>, dead_letter_path: &PathBuf, events: Vec<ServerEvent>) {
+        let event_bus = EventBus::new();
+        let retry_policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(10),
+        };
+
+        let dispatcher = WebhookDispatcher::new(webhooks, retry_policy, dead_letter_path)
+            .start(event_bus.subscribe());
+
+        for event in events {
+            event_bus.publish(event);
+        }
+
+        drop(event_bus);
+        dispatcher.join().unwrap();
+    }
+
+    #[test]
+    fn test_signed_deliveries_are_retried() {
+        let (url, requests) = spawn_stand_in(vec![500]);
+        let dead_letter_path = env::temp_dir().join(format!("mxchat-webhooks-{}-retried.jsonl", std::process::id()));
+
+        let webhook = WebhookConfig {
+            url,
+            secret: String::from("secret"),
+            events: Vec::new(),
+            keywords: vec![String::from("deploy")],
+        };
+
+        dispatch(vec![webhook], &dead_letter_path, vec![
+            ServerEvent::MessageSent { sender: user(0, "alice"), recipient: user(1, "bob"), content: String::from("hello") },
+            ServerEvent::MessageSent { sender: user(0, "alice"), recipient: user(1, "bob"), content: String::from("Deploy done") },
+            ServerEvent::UserRegistered(user(2, "carol")),
+        ]);
+
+        let requests: Vec<ReceivedRequest> = requests.try_iter().collect();
+        let events: Vec<&str> = requests.iter().map(|request| request.event.as_str()).collect();
+        assert_eq!(events, ["message_sent", "message_sent", "user_registered"]);
+        assert_eq!(requests[0].body, requests[1].body);
+
+        for request in &requests {
+            assert_eq!(request.signature, format!("sha256={}", sign("secret", &request.body)));
+        }
+
+        let payload: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(payload["data"]["content"], "Deploy done");
+        assert!(!dead_letter_path.exists());
+    }
+
+    #[test]
+    fn test_failed_deliveries_are_dead_lettered() {
+        let (url, requests) = spawn_stand_in(vec![500, 503]);
+        let dead_letter_path = env::temp_dir().join(format!("mxchat-webhooks-{}-dead.jsonl", std::process::id()));
+        let _ = fs::remove_file(&dead_letter_path);
+
+        let webhook = WebhookConfig {
+            url: url.clone(),
+            secret: String::from("secret"),
+            events: vec![String::from("user_connected")],
+            keywords: Vec::new(),
+        };
+
+        dispatch(vec![webhook], &dead_letter_path, vec![
+            ServerEvent::UserRegistered(user(0, "alice")),
+            ServerEvent::UserConnected(user(0, "alice")),
+        ]);
+
+        assert_eq!(requests.try_iter().count(), 2);
+
+        let dead_letters = fs::read_to_string(&dead_letter_path).unwrap();
+        fs::remove_file(&dead_letter_path).unwrap();
+
+        let dead_letter: Value = serde_json::from_str(dead_letters.trim()).unwrap();
+        assert_eq!(dead_letter["url"], url);
+        assert_eq!(dead_letter["event"], "user_connected");
+        assert_eq!(dead_letter["error"], "http status: 503");
+    }
+}
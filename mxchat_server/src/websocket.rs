@@ -0,0 +1,312 @@
+use std::{io::{self, Read, Write}, mem, net::TcpStream, sync::{Arc, Mutex}};
+
+use mxchat_core::{io::{read_frame, BytesBuffer, FrameError}, request::RequestId};
+use tungstenite::{error::CapacityError, protocol::{frame::{coding::{Data, OpCode}, Frame}, Role, WebSocketConfig, WebSocketContext}, Error, Message};
+
+use crate::{command_handler::FrameSource, server::ConnectionWriter};
+
+/// Request id, message type and payload length written before the payload of a frame.
+const FRAME_HEADER_SIZE: usize = RequestId::size() + 5;
+
+/// Write half of a WebSocket connection. [`mxchat_core::io::write_frame`] writes a whole
+/// frame then flushes, each flush sends what was written as one binary message.
+pub struct WebSocketWriter {
+    socket: TcpStream,
+    message: Vec<u8>,
+}
+
+impl WebSocketWriter {
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.socket.write_all(bytes)?;
+        self.socket.flush()
+    }
+}
+
+impl Write for WebSocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.message.is_empty() {
+            return Ok(());
+        }
+
+        let message = mem::take(&mut self.message);
+        let mut bytes = Vec::new();
+        Frame::message(message, OpCode::Data(Data::Binary), true)
+            .format(&mut bytes)
+            .map_err(into_io_error)?;
+
+        self.write_raw(&bytes)
+    }
+}
+
+/// The socket read by the WebSocket context. The control frames it answers, pongs and
+/// close replies, are kept then sent through the writer so they do not interleave with
+/// the notifications written by other threads.
+struct ReadStream {
+    socket: TcpStream,
+    replies: Vec<u8>,
+}
+
+impl Read for ReadStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl Write for ReadStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.replies.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Read half of a WebSocket connection, every binary message carries exactly one frame.
+pub struct WebSocketReader {
+    stream: ReadStream,
+    context: WebSocketContext,
+    writer: Arc<Mutex<WebSocketWriter>>,
+}
+
+impl WebSocketReader {
+    fn send_replies(&mut self) -> io::Result<()> {
+        match self.context.flush(&mut self.stream) {
+            // The reply to a close is written before the context reports the connection closed
+            Ok(()) | Err(Error::ConnectionClosed) => (),
+            Err(e) => return Err(into_io_error(e))
+        }
+
+        if self.stream.replies.is_empty() {
+            return Ok(());
+        }
+
+        let replies = mem::take(&mut self.stream.replies);
+        self.writer.lock().unwrap().write_raw(&replies)
+    }
+}
+
+impl FrameSource for WebSocketReader {
+    fn read_frame(&mut self, max_frame_size: usize) -> Result<(Option<RequestId>, BytesBuffer), FrameError> {
+        loop {
+            let message = self.context.read(&mut self.stream);
+            self.send_replies()?;
+
+            match message {
+                Ok(Message::Binary(bytes)) => {
+                    let mut bytes = bytes.as_ref();
+                    let frame = read_frame(&mut bytes, max_frame_size)?;
+
+                    if !bytes.is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "a message must carry a single frame").into());
+                    }
+
+                    return Ok(frame);
+                }
+                Ok(Message::Text(_)) =>
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "frames must be sent as binary messages").into()),
+                // Control frames are answered by the context, a close is followed by `ConnectionClosed`
+                Ok(_) => continue,
+                Err(Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+                    return Err(FrameError::Idle),
+                Err(Error::Io(e)) => return Err(e.into()),
+                Err(Error::ConnectionClosed | Error::AlreadyClosed) =>
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Err(Error::Capacity(CapacityError::MessageTooLong { size, .. })) =>
+                    return Err(FrameError::TooLarge(None, size.saturating_sub(FRAME_HEADER_SIZE))),
+                Err(e) => return Err(into_io_error(e).into()),
+            }
+        }
+    }
+}
+
+/// Answers the opening handshake of the client, which waits for it before sending frames.
+pub fn accept(socket: TcpStream, max_frame_size: usize) -> io::Result<(WebSocketReader, Arc<Mutex<WebSocketWriter>>)> {
+    let socket = tungstenite::accept(socket)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+        .into_inner();
+
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(max_frame_size + FRAME_HEADER_SIZE));
+
+    let writer = Arc::new(Mutex::new(WebSocketWriter {
+        socket: socket.try_clone()?,
+        message: Vec::new(),
+    }));
+
+    let reader = WebSocketReader {
+        stream: ReadStream {
+            socket,
+            replies: Vec::new(),
+        },
+        context: WebSocketContext::new(Role::Server, Some(config)),
+        writer: Arc::clone(&writer),
+    };
+
+    Ok((reader, writer))
+}
+
+/// Opens a connection accepted by the WebSocket listener.
+pub fn open_connection(socket: TcpStream, max_frame_size: usize) -> io::Result<(Box<dyn FrameSource>, ConnectionWriter)> {
+    let (reader, writer) = accept(socket, max_frame_size)?;
+    Ok((Box::new(reader), writer))
+}
+
+fn into_io_error(error: Error) -> io::Error {
+    match error {
+        Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread, time::Duration};
+
+    use mxchat_core::{auth::{UserConnectData, UserRegisterData}, command::Command, encoding::{Decode, Encode}, io::{write_frame, ProtocolFeatures, DEFAULT_MAX_FRAME_SIZE}, messaging::OutgoingMessage, notification::Notification, request::RequestIdGenerator};
+    use tungstenite::WebSocket;
+
+    use crate::{auth::AuthConfig, rate_limit::RateLimitConfig, server::{serve, write_notification, ServerConfig}, server_handler::ServerCommandHandler};
+
+    use super::*;
+
+    fn command_bytes(request_id: Option<RequestId>, cmd: &Command) -> Vec<u8> {
+        let mut message = BytesBuffer::empty();
+        cmd.to_bytes(&mut message);
+        frame_bytes(request_id, message)
+    }
+
+    fn frame_bytes(request_id: Option<RequestId>, mut message: BytesBuffer) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, request_id, &mut message, false).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_one_frame_per_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let (mut reader, writer) = accept(socket, DEFAULT_MAX_FRAME_SIZE).unwrap();
+
+            let Ok((request_id, mut message)) = reader.read_frame(DEFAULT_MAX_FRAME_SIZE) else {
+                panic!("could not read the frame");
+            };
+            assert!(matches!(Command::from_bytes(&mut message), Ok(Command::Ping)));
+
+            let writer: ConnectionWriter = writer;
+            write_notification(&writer, request_id, &Notification::Pong, false).unwrap();
+
+            // The second message carries two frames
+            assert!(reader.read_frame(DEFAULT_MAX_FRAME_SIZE).is_err());
+        });
+
+        let socket = TcpStream::connect(address).unwrap();
+        let (mut client, _) = tungstenite::client(format!("ws://{address}/"), socket).unwrap();
+
+        let request_id = RequestIdGenerator::new().next_id();
+        client.send(Message::binary(command_bytes(Some(request_id), &Command::Ping))).unwrap();
+
+        let mut pong = BytesBuffer::empty();
+        Notification::Pong.encode(&mut pong);
+        assert_eq!(client.read().unwrap(), Message::binary(frame_bytes(Some(request_id), pong)));
+
+        let mut two_frames = command_bytes(None, &Command::Ping);
+        two_frames.extend(command_bytes(None, &Command::Ping));
+        client.send(Message::binary(two_frames)).unwrap();
+
+        server.join().unwrap();
+    }
+
+    fn register_and_connect(username: &str) -> [Command; 2] {
+        [
+            Command::Register(UserRegisterData {
+                username: username.into(),
+                nickname: username.into(),
+                password: "s3cret-pass".into(),
+                bot: false,
+            }),
+            Command::Connect(UserConnectData { username: username.into(), password: "s3cret-pass".into() }),
+        ]
+    }
+
+    fn read_notification(reader: &mut impl Read) -> Notification {
+        let Ok((_, mut message)) = read_frame(reader, DEFAULT_MAX_FRAME_SIZE) else {
+            panic!("could not read the frame");
+        };
+        Notification::decode(&mut message).unwrap()
+    }
+
+    fn tcp_request(socket: &mut TcpStream, cmd: &Command) -> Notification {
+        socket.write_all(&command_bytes(None, cmd)).unwrap();
+        read_notification(socket)
+    }
+
+    fn websocket_notification(client: &mut WebSocket<TcpStream>) -> Notification {
+        let bytes = client.read().unwrap().into_data();
+        read_notification(&mut &bytes[..])
+    }
+
+    fn websocket_request(client: &mut WebSocket<TcpStream>, cmd: &Command) -> Notification {
+        client.send(Message::binary(command_bytes(None, cmd))).unwrap();
+        websocket_notification(client)
+    }
+
+    #[test]
+    fn test_websocket_and_tcp_users_talk() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let websocket_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (address, websocket_address) = (listener.local_addr().unwrap(), websocket_listener.local_addr().unwrap());
+
+        let config = ServerConfig {
+            address: address.ip(),
+            port: address.port(),
+            websocket_port: Some(websocket_address.port()),
+            rate_limit: RateLimitConfig::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            idle_timeout: Duration::from_secs(30),
+            features: ProtocolFeatures::default(),
+            trusted_gateways: Vec::new(),
+        };
+        let cmd_handler = Arc::new(ServerCommandHandler::new(AuthConfig::default()));
+        thread::spawn(move || serve(cmd_handler, config, listener, Some(websocket_listener)));
+
+        let mut tcp_socket = TcpStream::connect(address).unwrap();
+        let [register, connect] = register_and_connect("alice");
+        assert_eq!(tcp_request(&mut tcp_socket, &register), Notification::UserRegistred);
+        let Notification::UserConnected(alice) = tcp_request(&mut tcp_socket, &connect) else {
+            panic!("alice logs in over TCP");
+        };
+
+        let socket = TcpStream::connect(websocket_address).unwrap();
+        let (mut websocket_client, _) = tungstenite::client(format!("ws://{websocket_address}/"), socket).unwrap();
+        let [register, connect] = register_and_connect("bob");
+        assert_eq!(websocket_request(&mut websocket_client, &register), Notification::UserRegistred);
+        let Notification::UserConnected(bob) = websocket_request(&mut websocket_client, &connect) else {
+            panic!("bob logs in over WebSocket");
+        };
+
+        let message = OutgoingMessage { recipient: alice.id, content: "hello from the browser".into() };
+        assert_eq!(websocket_request(&mut websocket_client, &Command::SendMessage(message)), Notification::MessageSent);
+
+        let Notification::MessageReceived(received) = read_notification(&mut tcp_socket) else {
+            panic!("alice receives the message of bob");
+        };
+        assert_eq!(received.sender, bob);
+        assert_eq!(received.content, "hello from the browser");
+
+        let reply = OutgoingMessage { recipient: received.sender.id, content: "hello from the terminal".into() };
+        assert_eq!(tcp_request(&mut tcp_socket, &Command::SendMessage(reply)), Notification::MessageSent);
+
+        let received = websocket_notification(&mut websocket_client);
+        assert!(matches!(received, Notification::MessageReceived(received) if received.sender.id == alice.id));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{io, time::Duration};
+
+use ratatui::{crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, DefaultTerminal, Frame};
+
+use crate::{auth_screen::AuthScreen, chat_screen::ChatScreen};
+
+/// How long the event loop waits for a key before polling the connection again.
+const TICK: Duration = Duration::from_millis(100);
+
+enum Screen {
+    Auth(AuthScreen),
+    Chat(ChatScreen),
+}
+
+pub struct App {
+    screen: Screen,
+    exit: bool,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            screen: Screen::Auth(AuthScreen::new()),
+            exit: false,
+        }
+    }
+
+    pub fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.exit {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+
+            self.update();
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        match &mut self.screen {
+            Screen::Auth(auth_screen) => auth_screen.draw(frame),
+            Screen::Chat(chat_screen) => chat_screen.draw(frame),
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.exit = true;
+            return;
+        }
+
+        match &mut self.screen {
+            Screen::Auth(auth_screen) => self.exit = auth_screen.handle_key(key),
+            Screen::Chat(chat_screen) => chat_screen.handle_key(key),
+        }
+    }
+
+    fn update(&mut self) {
+        match &mut self.screen {
+            Screen::Auth(auth_screen) => if let Some(session) = auth_screen.poll() {
+                self.screen = Screen::Chat(ChatScreen::new(session));
+            },
+            Screen::Chat(chat_screen) => if chat_screen.update() {
+                self.screen = Screen::Auth(AuthScreen::new());
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::{sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+use mxchat_core::{auth::{User, UserConnectData, UserRegisterData}, validation::{ValidationError, ValidationPolicy}};
+use mxchat_sdk::{session::SessionCredentials, Client, ClientError};
+use ratatui::{crossterm::event::{KeyCode, KeyEvent, KeyModifiers}, layout::{Constraint, Layout}, style::Stylize, text::Line, widgets::Paragraph, Frame};
+
+use crate::text_input::TextInput;
+
+pub struct Session {
+    pub client: Client,
+    pub user: User,
+    pub credentials: SessionCredentials,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    HostName,
+    Port,
+    Username,
+    Nickname,
+    Password,
+    /// Only filled in for accounts enrolled in two-factor authentication.
+    SecondFactorCode,
+}
+
+const LOGIN_FIELDS: [Field; 5] = [Field::HostName, Field::Port, Field::Username, Field::Password, Field::SecondFactorCode];
+const REGISTRATION_FIELDS: [Field; 5] = [Field::HostName, Field::Port, Field::Username, Field::Nickname, Field::Password];
+
+/// Login and registration forms. Registering also logs the new user in.
+pub struct AuthScreen {
+    registering: bool,
+    host_name: TextInput,
+    port: TextInput,
+    username: TextInput,
+    nickname: TextInput,
+    password: TextInput,
+    second_factor_code: TextInput,
+    focused_field: usize,
+    /// The default policy of the servers, checked before registering.
+    validation: ValidationPolicy,
+    job: Option<Receiver<Result<Session, String>>>,
+    error_message: Option<String>,
+}
+
+impl AuthScreen {
+    pub fn new() -> Self {
+        Self {
+            registering: false,
+            host_name: TextInput::new("127.0.0.1"),
+            port: TextInput::new("8080").number(5),
+            username: TextInput::new(""),
+            nickname: TextInput::new(""),
+            password: TextInput::new("").masked(),
+            second_factor_code: TextInput::new(""),
+            focused_field: 0,
+            validation: ValidationPolicy::default(),
+            job: None,
+            error_message: None,
+        }
+    }
+
+    fn fields(&self) -> &'static [Field] {
+        if self.registering {
+            &REGISTRATION_FIELDS
+        }
+        else {
+            &LOGIN_FIELDS
+        }
+    }
+
+    fn input(&self, field: Field) -> &TextInput {
+        match field {
+            Field::HostName => &self.host_name,
+            Field::Port => &self.port,
+            Field::Username => &self.username,
+            Field::Nickname => &self.nickname,
+            Field::Password => &self.password,
+            Field::SecondFactorCode => &self.second_factor_code,
+        }
+    }
+
+    fn input_mut(&mut self, field: Field) -> &mut TextInput {
+        match field {
+            Field::HostName => &mut self.host_name,
+            Field::Port => &mut self.port,
+            Field::Username => &mut self.username,
+            Field::Nickname => &mut self.nickname,
+            Field::Password => &mut self.password,
+            Field::SecondFactorCode => &mut self.second_factor_code,
+        }
+    }
+
+    fn is_form_valid(&self) -> bool {
+        self.fields()
+            .iter()
+            .filter(|field| **field != Field::SecondFactorCode)
+            .all(|field| !self.input(*field).value.is_empty()) &&
+        (!self.registering || self.validated_registration_data().is_ok())
+    }
+
+    /// Returns the registration data with its username and nickname normalized.
+    fn validated_registration_data(&self) -> Result<UserRegisterData, ValidationError> {
+        self.validation.validate_register_data(&UserRegisterData {
+            username: self.username.value.clone(),
+            nickname: self.nickname.value.clone(),
+            password: self.password.value.clone(),
+            bot: false,
+        })
+    }
+
+    /// The reason the server would refuse the account, once the user started filling the form.
+    fn validation_error(&self) -> Option<ValidationError> {
+        let started = [&self.username, &self.nickname, &self.password].iter().any(|input| !input.value.is_empty());
+        if !self.registering || !started {
+            return None;
+        }
+
+        self.validated_registration_data().err()
+    }
+
+    /// Returns `true` when the user wants to quit.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if self.job.is_some() {
+            // Cancelling drops the receiver, the result of the job is ignored
+            if key.code == KeyCode::Esc {
+                self.job = None;
+            }
+            return false;
+        }
+
+        let fields_count = self.fields().len();
+
+        match key.code {
+            KeyCode::Esc => return true,
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.registering = !self.registering;
+                self.focused_field = 0;
+                self.error_message = None;
+            }
+            KeyCode::Tab | KeyCode::Down => self.focused_field = (self.focused_field + 1) % fields_count,
+            KeyCode::BackTab | KeyCode::Up => self.focused_field = (self.focused_field + fields_count - 1) % fields_count,
+            KeyCode::Enter if self.is_form_valid() => self.submit(),
+            _ => {
+                let field = self.fields()[self.focused_field];
+                self.input_mut(field).handle_key(key);
+            }
+        }
+
+        false
+    }
+
+    fn submit(&mut self) {
+        let address = format!("{}:{}", self.host_name.value, self.port.value);
+        let Ok(registration_data) = self.registering.then(|| self.validated_registration_data()).transpose() else {
+            return;
+        };
+        // Logs in with the normalized username, the one the server stores
+        let connect_data = UserConnectData {
+            username: registration_data.as_ref().map_or_else(|| self.username.value.clone(), |data| data.username.clone()),
+            password: self.password.value.clone(),
+        };
+        let second_factor_code = Some(self.second_factor_code.value.trim().to_string())
+            .filter(|code| !self.registering && !code.is_empty());
+
+        let (sender, receiver) = mpsc::channel();
+        self.job = Some(receiver);
+        self.error_message = None;
+
+        thread::spawn(move || {
+            let result = authenticate(address, registration_data, connect_data, second_factor_code)
+                .map_err(|e| e.to_string());
+            let _ = sender.send(result);
+        });
+    }
+
+    /// Returns the session once the user is logged in.
+    pub fn poll(&mut self) -> Option<Session> {
+        let result = match self.job.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(String::from("Authentication failed"))
+        };
+
+        self.job = None;
+
+        match result {
+            Ok(session) => Some(session),
+            Err(error_message) => {
+                self.error_message = Some(error_message);
+                None
+            }
+        }
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let fields = self.fields();
+
+        let mut constraints = vec![Constraint::Length(2)];
+        constraints.extend(fields.iter().map(|_| Constraint::Length(3)));
+        constraints.push(Constraint::Length(2));
+        constraints.push(Constraint::Min(0));
+
+        let [area] = Layout::horizontal([Constraint::Max(60)]).areas(frame.area());
+        let areas = Layout::vertical(constraints).split(area);
+
+        let title = if self.registering { "mxchat - Register" } else { "mxchat - Login" };
+        frame.render_widget(Line::from(title).bold(), areas[0]);
+
+        for (index, field) in fields.iter().enumerate() {
+            let label = match field {
+                Field::HostName => "Hostname",
+                Field::Port => "Port",
+                Field::Username => "Username",
+                Field::Nickname => "Nickname",
+                Field::Password => "Password",
+                Field::SecondFactorCode => "Two-factor code (if enabled)",
+            };
+
+            let focused = self.job.is_none() && index == self.focused_field;
+            self.input(*field).draw(frame, areas[index + 1], label, focused);
+        }
+
+        let status = if self.job.is_some() {
+            Line::from("Connecting to server... (Esc to cancel)")
+        }
+        else if let Some(error_message) = &self.error_message {
+            Line::from(error_message.as_str()).red()
+        }
+        else if let Some(error) = self.validation_error() {
+            Line::from(error.to_string()).red()
+        }
+        else {
+            let other_page = if self.registering { "login" } else { "register" };
+            Line::from(format!("Enter: submit  Tab: next  Ctrl-R: {other_page}  Esc: quit")).dim()
+        };
+
+        frame.render_widget(Paragraph::new(status), areas[fields.len() + 1]);
+    }
+}
+
+fn authenticate(address: String, registration_data: Option<UserRegisterData>, connect_data: UserConnectData, second_factor_code: Option<String>) -> Result<Session, ClientError> {
+    let client = Client::connect(&address)?;
+
+    if let Some(registration_data) = registration_data {
+        client.register(registration_data)?;
+    }
+
+    let user = match (client.login(connect_data.clone()), second_factor_code) {
+        (Err(ClientError::SecondFactorRequired), Some(code)) => client.second_factor(code)?,
+        (result, _) => result?
+    };
+    let credentials = SessionCredentials {
+        address,
+        connect_data
+    };
+
+    Ok(Session { client, user, credentials })
+}
@@ -0,0 +1,412 @@
+use std::time::Duration;
+
+use mxchat_core::{auth::{User, UserId}, command::Command, messaging::{Contact, IncomingMessage, OutgoingMessage}, notification::Notification};
+use mxchat_sdk::{pending_requests::PendingRequests, session::{ConnectionManager, ConnectionState}, Event};
+use ratatui::{crossterm::event::{KeyCode, KeyEvent}, layout::{Constraint, Layout, Rect}, style::{Style, Stylize}, text::{Line, Span}, widgets::{Block, List, ListState, Paragraph}, Frame};
+
+use crate::{auth_screen::Session, text_input::TextInput};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const HELP: &str = "Tab: switch pane  PgUp/PgDn: scroll  /add <username>: add contact  Esc: logout  Ctrl-C: quit";
+
+enum PendingRequest {
+    AddContact(String),
+    RefreshContact(String),
+    /// Message sent to the contact, with its index in the conversation.
+    SendMessage(UserId, usize),
+}
+
+enum MessageStatus {
+    Received,
+    Sending,
+    Delivered,
+    NotDelivered(String),
+}
+
+struct ChatMessage {
+    author: String,
+    content: String,
+    status: MessageStatus,
+}
+
+struct Conversation {
+    contact: Contact,
+    username: String,
+    messages: Vec<ChatMessage>,
+    unread: bool,
+}
+
+#[derive(PartialEq, Eq)]
+enum Focus {
+    Contacts,
+    Input,
+}
+
+/// Same features as the chat page of the GUI: contacts on the left, the conversation
+/// with the selected one on the right, and a status bar for the connection.
+pub struct ChatScreen {
+    connection: ConnectionManager,
+    current_user: User,
+    conversations: Vec<Conversation>,
+    contacts_state: ListState,
+    input: TextInput,
+    focus: Focus,
+    /// Lines scrolled up from the bottom of the conversation.
+    scroll: usize,
+    notice: Option<String>,
+    pending_requests: PendingRequests<PendingRequest>,
+    logout: bool,
+}
+
+impl ChatScreen {
+    pub fn new(session: Session) -> Self {
+        Self {
+            connection: ConnectionManager::new(session.client, session.credentials, || ()),
+            current_user: session.user,
+            conversations: Vec::new(),
+            contacts_state: ListState::default(),
+            input: TextInput::new(""),
+            focus: Focus::Input,
+            scroll: 0,
+            notice: None,
+            pending_requests: PendingRequests::new(REQUEST_TIMEOUT),
+            logout: false,
+        }
+    }
+
+    /// Handles what happened on the connection, returns `true` when the user logged out.
+    pub fn update(&mut self) -> bool {
+        if let Some(user) = self.connection.update() {
+            self.on_reconnected(user);
+        }
+        self.pending_requests.start_deadlines(self.connection.take_flushed());
+
+        while let Some(event) = self.connection.next_event() {
+            self.handle_event(event);
+        }
+
+        for request in self.pending_requests.take_expired() {
+            self.handle_failure(request, String::from("Request timed out"));
+        }
+
+        self.logout
+    }
+
+    /// Sends the command, its reply is awaited from when it is written to the connection.
+    fn send_request(&mut self, cmd: Command, request: PendingRequest) {
+        let request_id = self.connection.send(cmd);
+
+        if self.connection.is_queued(request_id) {
+            self.pending_requests.insert_queued(request_id, request);
+        }
+        else {
+            self.pending_requests.insert(request_id, request);
+        }
+    }
+
+    fn on_reconnected(&mut self, user: User) {
+        self.current_user = user;
+
+        let usernames: Vec<String> = self.conversations
+            .iter()
+            .map(|conversation| conversation.username.clone())
+            .collect();
+
+        for username in usernames {
+            self.send_request(Command::RequestContact(username.clone()), PendingRequest::RefreshContact(username));
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let Some(request_id) = event.request_id else {
+            match event.notification {
+                Notification::MessageReceived(message) => self.on_message_received(message),
+                Notification::Error(error) => self.notice = Some(error.message),
+                _ => ()
+            }
+            return;
+        };
+
+        let Some(request) = self.pending_requests.take(request_id) else {
+            return;
+        };
+
+        match (request, event.notification) {
+            (PendingRequest::AddContact(username), Notification::ReceiveContactInfo(contact)) => {
+                if contact.id == self.current_user.id {
+                    self.notice = Some(String::from("You can't add yourself as contact!"));
+                }
+                else {
+                    let index = self.insert_contact(contact, username);
+                    self.select_conversation(index);
+                    self.notice = None;
+                }
+            }
+            (PendingRequest::RefreshContact(_), Notification::ReceiveContactInfo(contact)) => {
+                if let Some(conversation) = self.conversation_mut(contact.id) {
+                    conversation.contact = contact;
+                }
+            }
+            (PendingRequest::SendMessage(contact_id, message_index), Notification::MessageSent) =>
+                self.set_message_status(contact_id, message_index, MessageStatus::Delivered),
+            (request, Notification::Error(error)) => self.handle_failure(request, error.message),
+            _ => ()
+        }
+    }
+
+    fn handle_failure(&mut self, request: PendingRequest, error_message: String) {
+        match request {
+            PendingRequest::AddContact(username) =>
+                self.notice = Some(format!("Could not add {username}: {error_message}")),
+            PendingRequest::RefreshContact(username) =>
+                self.notice = Some(format!("Could not refresh contact {username}: {error_message}")),
+            PendingRequest::SendMessage(contact_id, message_index) =>
+                self.set_message_status(contact_id, message_index, MessageStatus::NotDelivered(error_message)),
+        }
+    }
+
+    /// Messages from users who are not in the contacts yet add them to the contacts.
+    fn on_message_received(&mut self, message: IncomingMessage) {
+        let sender = message.sender;
+        if sender.id == self.current_user.id {
+            return;
+        }
+
+        let contact = Contact {
+            id: sender.id,
+            nickname: sender.nickname.clone(),
+            bot: sender.bot,
+        };
+        let index = self.insert_contact(contact, sender.username);
+        let selected = self.contacts_state.selected() == Some(index);
+
+        let conversation = &mut self.conversations[index];
+        conversation.messages.push(ChatMessage {
+            author: sender.nickname,
+            content: message.content,
+            status: MessageStatus::Received,
+        });
+        conversation.unread = !selected;
+    }
+
+    /// Returns the index of the conversation with the contact.
+    fn insert_contact(&mut self, contact: Contact, username: String) -> usize {
+        if let Some(index) = self.conversations.iter().position(|conversation| conversation.contact.id == contact.id) {
+            self.conversations[index].contact = contact;
+            return index;
+        }
+
+        self.conversations.push(Conversation {
+            contact,
+            username,
+            messages: Vec::new(),
+            unread: false,
+        });
+
+        if self.contacts_state.selected().is_none() {
+            self.select_conversation(0);
+        }
+
+        self.conversations.len() - 1
+    }
+
+    fn conversation_mut(&mut self, contact_id: UserId) -> Option<&mut Conversation> {
+        self.conversations
+            .iter_mut()
+            .find(|conversation| conversation.contact.id == contact_id)
+    }
+
+    fn set_message_status(&mut self, contact_id: UserId, message_index: usize, status: MessageStatus) {
+        if let Some(message) = self.conversation_mut(contact_id).and_then(|conversation| conversation.messages.get_mut(message_index)) {
+            message.status = status;
+        }
+    }
+
+    fn select_conversation(&mut self, index: usize) {
+        self.contacts_state.select(Some(index));
+        self.conversations[index].unread = false;
+        self.scroll = 0;
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc => self.logout = true,
+            KeyCode::Tab | KeyCode::BackTab => self.focus = match self.focus {
+                Focus::Contacts => Focus::Input,
+                Focus::Input => Focus::Contacts,
+            },
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Up if self.focus == Focus::Contacts => self.move_selection(-1),
+            KeyCode::Down if self.focus == Focus::Contacts => self.move_selection(1),
+            KeyCode::Enter if self.focus == Focus::Input => self.submit_input(),
+            _ if self.focus == Focus::Input => { self.input.handle_key(key); }
+            _ => ()
+        }
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.conversations.is_empty() {
+            return;
+        }
+
+        let last_index = self.conversations.len() - 1;
+        let index = match self.contacts_state.selected() {
+            Some(index) => index.saturating_add_signed(offset).min(last_index),
+            None => 0
+        };
+
+        self.select_conversation(index);
+    }
+
+    fn submit_input(&mut self) {
+        let text = self.input.value.trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+
+        if matches!(self.connection.state(), ConnectionState::SecondFactorRequired(_)) {
+            self.input.clear();
+            self.connection.send_second_factor(text);
+            return;
+        }
+
+        if let Some(username) = text.strip_prefix("/add ") {
+            let username = username.trim().to_string();
+            self.send_request(Command::RequestContact(username.clone()), PendingRequest::AddContact(username));
+            self.input.clear();
+            return;
+        }
+
+        let Some(index) = self.contacts_state.selected() else {
+            self.notice = Some(String::from("Add a contact to start chatting"));
+            return;
+        };
+
+        let content = self.input.take();
+        let conversation = &mut self.conversations[index];
+        let contact_id = conversation.contact.id;
+        conversation.messages.push(ChatMessage {
+            author: self.current_user.nickname.clone(),
+            content: content.clone(),
+            status: MessageStatus::Sending,
+        });
+        let message_index = conversation.messages.len() - 1;
+        self.scroll = 0;
+
+        let message = OutgoingMessage { recipient: contact_id, content };
+        self.send_request(Command::SendMessage(message), PendingRequest::SendMessage(contact_id, message_index));
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [contacts_area, conversation_area] = Layout::horizontal([Constraint::Percentage(30), Constraint::Min(0)]).areas(main_area);
+        let [messages_area, input_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(conversation_area);
+
+        self.draw_contacts(frame, contacts_area);
+        self.draw_conversation(frame, messages_area);
+        self.input.draw(frame, input_area, "Message", self.focus == Focus::Input);
+        self.draw_status_bar(frame, status_area);
+    }
+
+    fn draw_contacts(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self.conversations
+            .iter()
+            .map(|conversation| {
+                let mut line = Line::from(conversation.contact.nickname.as_str());
+                if conversation.contact.bot {
+                    line.push_span(Span::from(" [bot]").dim());
+                }
+                if conversation.unread {
+                    line.push_span(Span::from(" *").yellow());
+                }
+                line
+            });
+
+        let border_style = if self.focus == Focus::Contacts { Style::new().yellow() } else { Style::new() };
+        let list = List::new(items)
+            .block(Block::bordered().title(format!("Connected as {}", self.current_user.username)).border_style(border_style))
+            .highlight_style(Style::new().reversed());
+
+        frame.render_stateful_widget(list, area, &mut self.contacts_state);
+    }
+
+    fn draw_conversation(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(conversation) = self.contacts_state.selected().and_then(|index| self.conversations.get(index)) else {
+            frame.render_widget(Paragraph::new("No contact selected").block(Block::bordered()), area);
+            return;
+        };
+
+        let width = area.width.saturating_sub(2) as usize;
+        let lines: Vec<Line> = conversation.messages
+            .iter()
+            .flat_map(|message| message_lines(message, width))
+            .collect();
+
+        // Scrolling is counted from the bottom, so new messages stay in view
+        let height = area.height.saturating_sub(2) as usize;
+        let max_scroll = lines.len().saturating_sub(height);
+        self.scroll = self.scroll.min(max_scroll);
+        let offset = max_scroll - self.scroll;
+
+        let block = Block::bordered().title(format!("Chat with {}", conversation.contact.nickname));
+        frame.render_widget(Paragraph::new(lines).scroll((offset as u16, 0)).block(block), area);
+    }
+
+    fn draw_status_bar(&self, frame: &mut Frame, area: Rect) {
+        let status = match self.connection.state() {
+            ConnectionState::Connected => Line::from(self.notice.as_deref().unwrap_or(HELP)).dim(),
+            ConnectionState::Reconnecting(attempt) =>
+                Line::from(format!("Reconnecting… (attempt {attempt})")).red(),
+            ConnectionState::SecondFactorRequired(None) =>
+                Line::from("Enter your two-factor code to log back in").red(),
+            ConnectionState::SecondFactorRequired(Some(error_message)) =>
+                Line::from(format!("{error_message}, enter your two-factor code to log back in")).red(),
+            ConnectionState::Failed(error_message) =>
+                Line::from(format!("Connection to server lost: {error_message} (Esc to go back to login)")).red(),
+        };
+
+        frame.render_widget(status, area);
+    }
+}
+
+/// Lines of a message wrapped to `width`, the author on the first one.
+fn message_lines(message: &ChatMessage, width: usize) -> Vec<Line<'static>> {
+    let text = format!("{}: {}", message.author, message.content);
+    let author_length = message.author.chars().count() + 1;
+
+    let mut lines: Vec<Line> = text
+        .lines()
+        .flat_map(|line| wrap(line, width.max(1)))
+        .map(Line::from)
+        .collect();
+
+    if let Some(first_line) = lines.first_mut() {
+        let content: String = first_line.to_string();
+        let split_index = content.char_indices().nth(author_length).map(|(index, _)| index).unwrap_or(content.len());
+        let (author, rest) = content.split_at(split_index);
+        *first_line = Line::from(vec![Span::from(author.to_string()).bold(), Span::from(rest.to_string())]);
+    }
+
+    match &message.status {
+        MessageStatus::Received | MessageStatus::Delivered => (),
+        MessageStatus::Sending => lines.push(Line::from("  Sending…").dim()),
+        MessageStatus::NotDelivered(error_message) =>
+            lines.push(Line::from(format!("  Message not delivered: {error_message}")).red()),
+    }
+
+    lines
+}
+
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+
+    chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
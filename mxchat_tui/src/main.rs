@@ -0,0 +1,14 @@
+mod app;
+mod auth_screen;
+mod chat_screen;
+mod text_input;
+
+use app::App;
+
+fn main() -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new().run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
@@ -0,0 +1,112 @@
+use ratatui::{crossterm::event::{KeyCode, KeyEvent}, layout::{Position, Rect}, style::{Style, Stylize}, widgets::{Block, Paragraph}, Frame};
+
+/// Single line text field, edited at a cursor.
+pub struct TextInput {
+    pub value: String,
+    /// Position of the cursor, in chars.
+    cursor: usize,
+    masked: bool,
+    max_length: Option<usize>,
+    digits_only: bool,
+}
+
+impl TextInput {
+    pub fn new(value: &str) -> Self {
+        Self {
+            value: value.into(),
+            cursor: value.chars().count(),
+            masked: false,
+            max_length: None,
+            digits_only: false,
+        }
+    }
+
+    pub fn masked(mut self) -> Self {
+        self.masked = true;
+        self
+    }
+
+    /// Accepts up to `max_length` digits, for port numbers.
+    pub fn number(mut self, max_length: usize) -> Self {
+        self.digits_only = true;
+        self.max_length = Some(max_length);
+        self
+    }
+
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.take();
+    }
+
+    /// Returns `true` when the key has been used by the field.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char(c) => self.insert(c),
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.value.remove(self.byte_index());
+            }
+            KeyCode::Delete if self.cursor < self.value.chars().count() => {
+                self.value.remove(self.byte_index());
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.value.chars().count()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.value.chars().count(),
+            KeyCode::Backspace | KeyCode::Delete => (),
+            _ => return false
+        }
+
+        true
+    }
+
+    fn insert(&mut self, c: char) {
+        if self.digits_only && !c.is_ascii_digit() {
+            return;
+        }
+
+        if self.max_length.is_some_and(|max_length| self.value.chars().count() >= max_length) {
+            return;
+        }
+
+        self.value.insert(self.byte_index(), c);
+        self.cursor += 1;
+    }
+
+    fn byte_index(&self) -> usize {
+        self.value
+            .char_indices()
+            .nth(self.cursor)
+            .map(|(index, _)| index)
+            .unwrap_or(self.value.len())
+    }
+
+    pub fn draw(&self, frame: &mut Frame, area: Rect, title: &str, focused: bool) {
+        let text = if self.masked {
+            "*".repeat(self.value.chars().count())
+        }
+        else {
+            self.value.clone()
+        };
+
+        let border_style = if focused { Style::new().yellow() } else { Style::new() };
+        let block = Block::bordered()
+            .title(title)
+            .border_style(border_style);
+
+        // Keeps the cursor visible by scrolling long values
+        let inner_width = area.width.saturating_sub(2) as usize;
+        let scroll = (self.cursor + 1).saturating_sub(inner_width);
+
+        frame.render_widget(Paragraph::new(text).scroll((0, scroll as u16)).block(block), area);
+
+        if focused {
+            let x = area.x + 1 + (self.cursor - scroll) as u16;
+            frame.set_cursor_position(Position::new(x, area.y + 1));
+        }
+    }
+}